@@ -0,0 +1,13 @@
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+CREATE TABLE issue_delivery_failures (
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL,
+    last_error TEXT NOT NULL,
+    failed_at timestamptz NOT NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE issue_delivery_log (
+    log_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL,
+    error_message TEXT NULL,
+    logged_at timestamptz NOT NULL
+);
+
+CREATE INDEX issue_delivery_log_newsletter_issue_id_idx
+    ON issue_delivery_log (newsletter_issue_id, subscriber_email);
@@ -0,0 +1,3 @@
+-- NULL means the issue goes out as soon as it is published.
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN cancelled_at timestamptz NULL;
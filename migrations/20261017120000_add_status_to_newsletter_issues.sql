@@ -0,0 +1,4 @@
+ALTER TABLE newsletter_issues
+    ADD COLUMN status TEXT NOT NULL DEFAULT 'published';
+-- Drafts are not published yet.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
@@ -0,0 +1,7 @@
+CREATE TABLE issue_test_sends (
+    test_send_id uuid NOT NULL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+    recipients TEXT[] NOT NULL,
+    sent_by uuid NOT NULL REFERENCES users (user_id),
+    sent_at timestamptz NOT NULL
+);
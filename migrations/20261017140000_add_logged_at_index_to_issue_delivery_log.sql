@@ -0,0 +1,3 @@
+-- Throughput is computed from the most recent entries of the log.
+CREATE INDEX issue_delivery_log_logged_at_idx
+    ON issue_delivery_log (logged_at);
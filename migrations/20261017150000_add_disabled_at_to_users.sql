@@ -0,0 +1,3 @@
+-- Disabled users keep their row (issues and test sends reference them) but
+-- can no longer log in.
+ALTER TABLE users ADD COLUMN disabled_at timestamptz;
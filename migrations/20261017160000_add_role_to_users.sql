@@ -0,0 +1,5 @@
+-- Existing users could already do everything: they become owners.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'owner';
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE users
+    ADD CONSTRAINT users_role_check CHECK (role IN ('owner', 'editor', 'viewer'));
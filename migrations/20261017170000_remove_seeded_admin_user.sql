@@ -0,0 +1,17 @@
+-- The seeded admin/admin_password account is a well-known credential:
+-- remove it unless its password has been changed since. It is only disabled
+-- if test sends still reference it.
+DELETE FROM idempotency
+WHERE user_id IN (
+    SELECT user_id FROM users
+    WHERE password_hash = '$argon2id$v=19$m=15000,t=2,p=1$ebHcL7T2mvxGsBWVOGmCsw$3gYd6cCSiEU9wuhVZ5YmVK+pzh3pMqGgNCp3KyaeW7Y'
+);
+UPDATE users
+SET disabled_at = now()
+WHERE
+    password_hash = '$argon2id$v=19$m=15000,t=2,p=1$ebHcL7T2mvxGsBWVOGmCsw$3gYd6cCSiEU9wuhVZ5YmVK+pzh3pMqGgNCp3KyaeW7Y' AND
+    disabled_at IS NULL;
+DELETE FROM users u
+WHERE
+    password_hash = '$argon2id$v=19$m=15000,t=2,p=1$ebHcL7T2mvxGsBWVOGmCsw$3gYd6cCSiEU9wuhVZ5YmVK+pzh3pMqGgNCp3KyaeW7Y' AND
+    NOT EXISTS (SELECT 1 FROM issue_test_sends t WHERE t.sent_by = u.user_id);
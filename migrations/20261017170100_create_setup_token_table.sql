@@ -0,0 +1,6 @@
+-- At most one setup token at a time, used to create the first owner account.
+CREATE TABLE setup_token (
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    token_hash TEXT NOT NULL,
+    issued_at timestamptz NOT NULL DEFAULT now()
+);
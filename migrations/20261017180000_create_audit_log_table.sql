@@ -0,0 +1,10 @@
+-- Security-relevant events, such as lockouts, kept for later review.
+CREATE TABLE audit_log (
+    audit_event_id uuid PRIMARY KEY,
+    event TEXT NOT NULL,
+    username TEXT,
+    client_ip TEXT,
+    details TEXT NOT NULL,
+    recorded_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX audit_log_recorded_at_idx ON audit_log (recorded_at);
@@ -0,0 +1,10 @@
+-- Base32 TOTP secret, NULL while two-factor authentication is off.
+ALTER TABLE users ADD COLUMN totp_secret TEXT;
+-- The last time step a code was accepted for: every code works only once.
+ALTER TABLE users ADD COLUMN totp_last_used_step BIGINT;
+-- Single-use codes to log in without the authenticator, stored hashed.
+CREATE TABLE recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,12 @@
+-- Named tokens for automation scripts, accepted as `Authorization: Bearer`.
+CREATE TABLE api_tokens (
+    api_token_id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES users (user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    last_used_at timestamptz,
+    revoked_at timestamptz
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
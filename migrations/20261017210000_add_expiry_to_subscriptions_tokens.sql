@@ -0,0 +1,8 @@
+-- Confirmation tokens expire, and can only be used once.
+ALTER TABLE subscriptions_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz,
+    ADD COLUMN used_at timestamptz;
+-- Tokens issued so far get the full validity from now on.
+UPDATE subscriptions_tokens SET expires_at = created_at + interval '48 hours';
+ALTER TABLE subscriptions_tokens ALTER COLUMN expires_at SET NOT NULL;
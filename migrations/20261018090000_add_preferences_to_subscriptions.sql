@@ -0,0 +1,12 @@
+-- Subscribers can pause delivery, and change their email address once they
+-- confirm the new one.
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz;
+
+CREATE TABLE email_change_tokens (
+    email_change_token TEXT PRIMARY KEY,
+    subscriber_id UUID NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email TEXT NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    expires_at timestamptz NOT NULL,
+    used_at timestamptz
+);
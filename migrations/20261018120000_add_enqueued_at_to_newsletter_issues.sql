@@ -0,0 +1,5 @@
+-- Delivery tasks are created when an issue becomes due rather than when it
+-- is published, so that scheduled issues reach whoever is subscribed at send
+-- time. NULL means the recipients have not been picked yet.
+ALTER TABLE newsletter_issues ADD COLUMN enqueued_at timestamptz NULL;
+UPDATE newsletter_issues SET enqueued_at = published_at WHERE status = 'published';
@@ -0,0 +1,42 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Who an audit event is about. Either can be unknown, e.g. an API request
+/// without credentials has no username.
+pub struct AuditSubject<'a> {
+    pub username: Option<&'a str>,
+    pub client_ip: Option<&'a str>,
+}
+
+/// Records a security-relevant event in `audit_log`, and in the logs.
+#[tracing::instrument(name = "Record audit event", skip(subject, pool))]
+pub async fn record_audit_event(
+    pool: &PgPool,
+    event: &str,
+    subject: AuditSubject<'_>,
+    details: &str,
+) -> Result<(), anyhow::Error> {
+    tracing::warn!(
+        audit.event = event,
+        audit.username = subject.username,
+        audit.client_ip = subject.client_ip,
+        "{}",
+        details
+    );
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (audit_event_id, event, username, client_ip, details)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        event,
+        subject.username,
+        subject.client_ip,
+        details
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the audit event.")?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::Role;
+
+/// Tokens start with it, so that they are easy to tell apart from passwords,
+/// e.g. by secret scanners.
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// What an API token is allowed to do, on top of what its user's role allows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+    ReadNewsletters,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::PublishNewsletters, ApiScope::ReadNewsletters];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PublishNewsletters => "newsletters:publish",
+            ApiScope::ReadNewsletters => "newsletters:read",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<ApiScope, String> {
+        ApiScope::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid scope.", s))
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The scopes of the credentials a request was authenticated with.
+/// Passwords grant every scope.
+#[derive(Clone, Debug)]
+pub struct GrantedScopes(pub Vec<ApiScope>);
+
+impl GrantedScopes {
+    pub fn all() -> Self {
+        Self(ApiScope::ALL.to_vec())
+    }
+
+    pub fn contains(&self, scope: ApiScope) -> bool {
+        self.0.contains(&scope)
+    }
+}
+
+/// An API token as listed to its user. The token itself is only known when
+/// it is created.
+pub struct ApiTokenRecord {
+    pub api_token_id: Uuid,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
+
+/// The user an API token acts on behalf of.
+pub struct ApiTokenOwner {
+    pub user_id: Uuid,
+    pub role: Role,
+    pub scopes: GrantedScopes,
+}
+
+fn hash_api_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+fn parse_scopes(scopes: Vec<String>) -> Result<Vec<ApiScope>, anyhow::Error> {
+    scopes
+        .iter()
+        .map(|s| ApiScope::parse(s).map_err(anyhow::Error::msg))
+        .collect()
+}
+
+/// Only a hash of the token is stored: the returned token cannot be
+/// retrieved again.
+#[tracing::instrument(name = "Create API token", skip(pool))]
+pub async fn create_api_token(
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    pool: &PgPool,
+) -> Result<Secret<String>, anyhow::Error> {
+    let mut rng = thread_rng();
+    let token = Secret::new(format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(40)
+            .collect::<String>()
+    ));
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (api_token_id, user_id, name, token_hash, scopes)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_api_token(&token),
+        &scopes
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the API token.")?;
+    Ok(token)
+}
+
+pub async fn list_api_tokens(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ApiTokenRecord>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            api_token_id,
+            name,
+            scopes,
+            created_at,
+            last_used_at,
+            revoked_at IS NOT NULL AS "revoked!"
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the API tokens.")?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(ApiTokenRecord {
+                api_token_id: r.api_token_id,
+                name: r.name,
+                scopes: parse_scopes(r.scopes)?,
+                created_at: r.created_at,
+                last_used_at: r.last_used_at,
+                revoked: r.revoked,
+            })
+        })
+        .collect()
+}
+
+/// Returns `false` if `user_id` has no such token, or it was already revoked.
+#[tracing::instrument(name = "Revoke API token", skip(pool))]
+pub async fn revoke_api_token(
+    user_id: Uuid,
+    api_token_id: Uuid,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let revoked_rows = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE api_token_id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        api_token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the API token.")?
+    .rows_affected();
+    Ok(revoked_rows > 0)
+}
+
+/// Looks up the active user behind a token that has not been revoked, and
+/// records that the token was used. Returns `None` for unknown tokens.
+#[tracing::instrument(name = "Authenticate API token", skip(token, pool))]
+pub async fn authenticate_api_token(
+    token: &Secret<String>,
+    pool: &PgPool,
+) -> Result<Option<ApiTokenOwner>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        FROM users
+        WHERE api_tokens.token_hash = $1
+            AND api_tokens.revoked_at IS NULL
+            AND users.user_id = api_tokens.user_id
+            AND users.disabled_at IS NULL
+        RETURNING users.user_id, users.role, api_tokens.scopes
+        "#,
+        hash_api_token(token)
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the API token.")?;
+    row.map(|r| {
+        Ok(ApiTokenOwner {
+            user_id: r.user_id,
+            role: Role::parse(&r.role).map_err(anyhow::Error::msg)?,
+            scopes: GrantedScopes(parse_scopes(r.scopes)?),
+        })
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::ApiScope;
+
+    #[test]
+    fn scopes_round_trip_through_their_name() {
+        for scope in ApiScope::ALL {
+            assert_ok_eq!(ApiScope::parse(scope.as_str()), scope);
+        }
+    }
+
+    #[test]
+    fn unknown_scopes_are_rejected() {
+        assert_err!(ApiScope::parse("newsletters:delete"));
+    }
+}
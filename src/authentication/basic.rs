@@ -41,3 +41,14 @@ pub fn get_basic_authentication_credentials(
         password: Secret::new(password),
     })
 }
+
+/// Returns `None` if the request does not use the `Bearer` scheme.
+pub fn get_bearer_token(headers: &HeaderMap) -> Option<Secret<String>> {
+    let token = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    Some(Secret::new(token.to_string()))
+}
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{ops::Deref, time::Duration};
 
 use actix_web::{
     body::MessageBody,
@@ -8,55 +8,80 @@ use actix_web::{
     web, FromRequest, HttpMessage, HttpResponse, ResponseError,
 };
 use actix_web_lab::middleware::Next;
-use reqwest::{header::HeaderValue, StatusCode};
+use reqwest::StatusCode;
 use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
     routes::error_chain_fmt,
     session_state::TypedSession,
-    utils::{e500, see_other},
+    utils::{client_ip, e403, e500, see_other, whole_seconds},
 };
 
-use super::{get_basic_authentication_credentials, validate_credentials, AuthError};
+use super::{
+    authenticate_api_token, get_active_user_role, get_basic_authentication_credentials,
+    get_bearer_token, validate_credentials, ApiScope, AuthError, GrantedScopes, LoginThrottle,
+    Role,
+};
 
 /// Tag type for Uuid that model UserIds.
 #[derive(Copy, Clone, Debug)]
 pub struct UserId(Uuid);
 
 #[derive(thiserror::Error)]
-pub enum BasicAuthError {
+pub enum ApiAuthError {
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
     #[error("Authentication failed")]
     Unauthorized(#[source] anyhow::Error),
+    #[error("Invalid API token")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error("The API token lacks the {0} scope")]
+    InsufficientScope(ApiScope),
+    #[error("Too many failed authentication attempts")]
+    LockedOut(Duration),
 }
 
-impl std::fmt::Debug for BasicAuthError {
+impl std::fmt::Debug for ApiAuthError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
     }
 }
 
-impl ResponseError for BasicAuthError {
+impl ResponseError for ApiAuthError {
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
         match self {
-            BasicAuthError::Unexpected(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
-            BasicAuthError::Unauthorized(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(header::WWW_AUTHENTICATE, header_value);
-                response
-            }
+            ApiAuthError::Unexpected(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            ApiAuthError::Unauthorized(_) => HttpResponse::Unauthorized()
+                .append_header((header::WWW_AUTHENTICATE, r#"Basic realm="publish""#))
+                .append_header((header::WWW_AUTHENTICATE, r#"Bearer realm="publish""#))
+                .finish(),
+            ApiAuthError::InvalidToken(_) => HttpResponse::Unauthorized()
+                .insert_header((
+                    header::WWW_AUTHENTICATE,
+                    r#"Bearer realm="publish", error="invalid_token""#,
+                ))
+                .finish(),
+            ApiAuthError::InsufficientScope(scope) => HttpResponse::Forbidden()
+                .insert_header((
+                    header::WWW_AUTHENTICATE,
+                    format!(
+                        r#"Bearer realm="publish", error="insufficient_scope", scope="{}""#,
+                        scope
+                    ),
+                ))
+                .finish(),
+            ApiAuthError::LockedOut(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, whole_seconds(retry_after)))
+                .finish(),
         }
     }
 }
 
 /// Rejects users that are not authenticated using session-based authentication.
-#[tracing::instrument(name = "User Session Authentication", skip(req, next))]
+#[tracing::instrument(name = "User Session Authentication", skip(pool, req, next))]
 pub async fn users_session_authentication(
+    pool: web::Data<PgPool>,
     mut req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
@@ -66,10 +91,19 @@ pub async fn users_session_authentication(
     }?;
     let user_id_mb = session.get_user_id().map_err(e500)?;
     match user_id_mb {
-        Some(user_id) => {
-            req.extensions_mut().insert(UserId(user_id));
-            next.call(req).await
-        }
+        Some(user_id) => match get_active_user_role(user_id, &pool).await.map_err(e500)? {
+            Some(role) => {
+                req.extensions_mut().insert(UserId(user_id));
+                req.extensions_mut().insert(role);
+                next.call(req).await
+            }
+            None => {
+                session.log_out();
+                let response = see_other("/login");
+                let e = anyhow::anyhow!("The user has been disabled");
+                Err(InternalError::from_response(e, response).into())
+            }
+        },
         None => {
             let response = see_other("/login");
             let e = anyhow::anyhow!("The user has not logged in");
@@ -78,26 +112,152 @@ pub async fn users_session_authentication(
     }
 }
 
-/// Rejects users that are not authenticated using basic authentication.
-#[tracing::instrument(name = "User Basic Authentication", skip(pool, req, next))]
-pub async fn users_basic_authentication(
+/// Rejects API requests that carry neither a valid `Bearer` API token nor
+/// valid `Basic` credentials.
+#[tracing::instrument(name = "User API Authentication", skip(pool, throttle, req, next))]
+pub async fn users_api_authentication(
     pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
     req: ServiceRequest,
     next: Next<impl MessageBody>,
 ) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
-    let headers = req.headers();
+    let (user_id, role, scopes) = match get_bearer_token(req.headers()) {
+        Some(token) => {
+            let owner = authenticate_api_token(&token, &pool)
+                .await
+                .map_err(ApiAuthError::Unexpected)?
+                .ok_or_else(|| {
+                    ApiAuthError::InvalidToken(anyhow::anyhow!(
+                        "The token is unknown, revoked, or its user is disabled."
+                    ))
+                })?;
+            (owner.user_id, owner.role, owner.scopes)
+        }
+        None => {
+            let user_id = basic_authentication(&req, &pool, &throttle).await?;
+            let role = get_active_user_role(user_id, &pool)
+                .await
+                .map_err(ApiAuthError::Unexpected)?
+                .ok_or_else(|| {
+                    ApiAuthError::Unauthorized(anyhow::anyhow!("The user is disabled."))
+                })?;
+            (user_id, role, GrantedScopes::all())
+        }
+    };
+
+    req.extensions_mut().insert(UserId(user_id));
+    req.extensions_mut().insert(role);
+    req.extensions_mut().insert(scopes);
+    next.call(req).await
+}
+
+/// Failed attempts count towards the same lockouts as the login form.
+async fn basic_authentication(
+    req: &ServiceRequest,
+    pool: &PgPool,
+    throttle: &LoginThrottle,
+) -> Result<Uuid, ApiAuthError> {
     let credentials =
-        get_basic_authentication_credentials(headers).map_err(BasicAuthError::Unauthorized)?;
+        get_basic_authentication_credentials(req.headers()).map_err(ApiAuthError::Unauthorized)?;
+    let username = credentials.username.clone();
+    let client_ip = client_ip(req.request());
 
-    let user_id = validate_credentials(credentials, &pool)
+    if let Some(retry_after) = throttle
+        .locked_out_for(&username, &client_ip)
         .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => BasicAuthError::Unauthorized(e.into()),
-            AuthError::UnexpectedError(_) => BasicAuthError::Unexpected(e.into()),
-        })?;
+        .map_err(ApiAuthError::Unexpected)?
+    {
+        return Err(ApiAuthError::LockedOut(retry_after));
+    }
 
-    req.extensions_mut().insert(UserId(user_id));
-    next.call(req).await
+    let user_id = match validate_credentials(credentials, pool).await {
+        Ok(user_id) => user_id,
+        Err(e @ AuthError::InvalidCredentials(_)) => {
+            let lockout = throttle
+                .record_failure(&username, &client_ip, pool)
+                .await
+                .map_err(ApiAuthError::Unexpected)?;
+            return Err(match lockout {
+                Some(retry_after) => ApiAuthError::LockedOut(retry_after),
+                None => ApiAuthError::Unauthorized(e.into()),
+            });
+        }
+        Err(e @ AuthError::UnexpectedError(_)) => return Err(ApiAuthError::Unexpected(e.into())),
+    };
+    throttle
+        .record_success(&username)
+        .await
+        .map_err(ApiAuthError::Unexpected)?;
+    Ok(user_id)
+}
+
+/// Rejects API requests whose token may not publish newsletter issues.
+/// Must be layered on top of [users_api_authentication].
+pub async fn require_publish_scope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_scope(ApiScope::PublishNewsletters, req, next).await
+}
+
+/// Rejects API requests whose token may not follow the delivery of issues.
+/// Must be layered on top of [users_api_authentication].
+pub async fn require_read_scope(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_scope(ApiScope::ReadNewsletters, req, next).await
+}
+
+#[tracing::instrument(name = "Check API token scope", skip(req, next))]
+async fn require_scope(
+    required_scope: ApiScope,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let allowed = req
+        .extensions()
+        .get::<GrantedScopes>()
+        .is_some_and(|scopes| scopes.contains(required_scope));
+    if allowed {
+        next.call(req).await
+    } else {
+        Err(ApiAuthError::InsufficientScope(required_scope).into())
+    }
+}
+
+/// Rejects users who cannot write or publish newsletter issues.
+/// Must be layered on top of one of the authentication middlewares.
+pub async fn require_editor(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Editor, req, next).await
+}
+
+/// Rejects users who cannot manage the other users.
+/// Must be layered on top of one of the authentication middlewares.
+pub async fn require_owner(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    require_role(Role::Owner, req, next).await
+}
+
+#[tracing::instrument(name = "Check user role", skip(req, next))]
+async fn require_role(
+    required_role: Role,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let role = req.extensions().get::<Role>().copied();
+    match role {
+        Some(role) if role >= required_role => next.call(req).await,
+        _ => Err(e403(anyhow::anyhow!(
+            "This action requires the {} role.",
+            required_role
+        ))),
+    }
 }
 
 impl std::fmt::Display for UserId {
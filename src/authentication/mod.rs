@@ -1,7 +1,33 @@
+mod api_tokens;
 mod basic;
 mod middleware;
 mod password;
+mod role;
+mod setup;
+mod throttling;
+mod two_factor;
+mod users;
 
-pub use basic::get_basic_authentication_credentials;
-pub use middleware::{users_basic_authentication, users_session_authentication, UserId};
+pub use api_tokens::{
+    authenticate_api_token, create_api_token, list_api_tokens, revoke_api_token, ApiScope,
+    ApiTokenOwner, ApiTokenRecord, GrantedScopes,
+};
+pub use basic::{get_basic_authentication_credentials, get_bearer_token};
+pub use middleware::{
+    require_editor, require_owner, require_publish_scope, require_read_scope,
+    users_api_authentication, users_session_authentication, UserId,
+};
 pub use password::{change_password, validate_credentials, AuthError, Credentials};
+pub use role::Role;
+pub use setup::{
+    consume_setup_token, ensure_no_default_credentials, has_active_owner, issue_setup_token,
+};
+pub use throttling::LoginThrottle;
+pub use two_factor::{
+    disable_two_factor, enable_two_factor, generate_totp_secret, get_totp_secret, otpauth_uri,
+    totp_code, verify_second_factor,
+};
+pub use users::{
+    create_user, disable_user, get_active_user_role, get_user_id, list_users, set_user_role,
+    UserRecord,
+};
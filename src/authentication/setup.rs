@@ -0,0 +1,122 @@
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+
+use crate::configuration::Environment;
+
+/// Hash of `admin_password`, seeded for the `admin` user by an early
+/// migration and therefore known to anyone who read the repository.
+const SEEDED_ADMIN_PASSWORD_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$\
+    ebHcL7T2mvxGsBWVOGmCsw$\
+    3gYd6cCSiEU9wuhVZ5YmVK+pzh3pMqGgNCp3KyaeW7Y";
+
+/// Fails in production if a user can still log in with the seeded default
+/// credentials. Elsewhere, only warns about it.
+pub async fn ensure_no_default_credentials(
+    pool: &PgPool,
+    environment: Environment,
+) -> Result<(), anyhow::Error> {
+    let in_use = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM users
+            WHERE password_hash = $1 AND disabled_at IS NULL
+        ) AS "in_use!"
+        "#,
+        SEEDED_ADMIN_PASSWORD_HASH
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look for users with the default credentials.")?
+    .in_use;
+
+    if !in_use {
+        return Ok(());
+    }
+    if environment == Environment::Production {
+        anyhow::bail!(
+            "A user still has the default `admin_password` password. \
+            Refusing to start in production: change it or disable the user."
+        );
+    }
+    tracing::warn!("A user still has the default `admin_password` password.");
+    Ok(())
+}
+
+pub async fn has_active_owner(pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM users
+            WHERE role = 'owner' AND disabled_at IS NULL
+        ) AS "exists!"
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look for an owner account.")?;
+    Ok(r.exists)
+}
+
+fn hash_setup_token(token: &Secret<String>) -> String {
+    hex::encode(Sha256::digest(token.expose_secret().as_bytes()))
+}
+
+/// Issues a one-time token to create the first owner account, as long as
+/// there is none. Returns `None` as well if a token is already outstanding,
+/// e.g. issued by a previous run: it keeps working until it is used.
+#[tracing::instrument(name = "Issue setup token", skip(pool))]
+pub async fn issue_setup_token(pool: &PgPool) -> Result<Option<Secret<String>>, anyhow::Error> {
+    if has_active_owner(pool).await? {
+        sqlx::query!("DELETE FROM setup_token")
+            .execute(pool)
+            .await
+            .context("Failed to remove the stale setup token.")?;
+        return Ok(None);
+    }
+
+    let mut rng = thread_rng();
+    let token = Secret::new(
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+            .map(char::from)
+            .take(32)
+            .collect::<String>(),
+    );
+    let inserted_rows = sqlx::query!(
+        r#"
+        INSERT INTO setup_token (token_hash)
+        VALUES ($1)
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        hash_setup_token(&token)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store the setup token.")?
+    .rows_affected();
+    Ok((inserted_rows > 0).then_some(token))
+}
+
+/// Returns `false` if the token is not the outstanding one. Either way, a
+/// valid token cannot be used twice: consume it in the transaction that
+/// creates the owner, so that it is only gone if the owner exists.
+#[tracing::instrument(name = "Consume setup token", skip(token, executor))]
+pub async fn consume_setup_token<'e>(
+    token: &Secret<String>,
+    executor: impl PgExecutor<'e>,
+) -> Result<bool, anyhow::Error> {
+    let deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM setup_token
+        WHERE token_hash = $1
+        "#,
+        hash_setup_token(token)
+    )
+    .execute(executor)
+    .await
+    .context("Failed to consume the setup token.")?
+    .rows_affected();
+    Ok(deleted_rows > 0)
+}
@@ -0,0 +1,219 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    audit::{record_audit_event, AuditSubject},
+    configuration::LoginThrottlingSettings,
+};
+
+/// Counts failed logins per username and per client IP in Redis, so that
+/// every replica of the API shares them, and locks out the ones with too many.
+pub struct LoginThrottle {
+    connection: ConnectionManager,
+    settings: LoginThrottlingSettings,
+}
+
+/// The two things a brute-force attack can be pinned to.
+#[derive(Clone, Copy)]
+enum Scope {
+    User,
+    Ip,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::User => "user",
+            Scope::Ip => "ip",
+        }
+    }
+}
+
+/// Exponential backoff: `base` once `max_failures` is reached, doubling with
+/// every further failure, up to `max`.
+fn lockout_duration(failures: u64, max_failures: u32, base: u64, max: u64) -> Option<Duration> {
+    let excess = failures.checked_sub(u64::from(max_failures))?;
+    let factor = 1u64.checked_shl(excess.min(63) as u32).unwrap_or(u64::MAX);
+    Some(Duration::from_secs(base.saturating_mul(factor).min(max)))
+}
+
+impl LoginThrottle {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        settings: LoginThrottlingSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("Invalid Redis URI.")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis.")?;
+        Ok(Self {
+            connection,
+            settings,
+        })
+    }
+
+    fn key(&self, kind: &str, scope: Scope, id: &str) -> String {
+        format!(
+            "{}:login_{}:{}:{}",
+            self.settings.redis_key_prefix,
+            kind,
+            scope.as_str(),
+            id
+        )
+    }
+
+    fn max_failures(&self, scope: Scope) -> u32 {
+        match scope {
+            Scope::User => self.settings.max_failures_per_user,
+            Scope::Ip => self.settings.max_failures_per_ip,
+        }
+    }
+
+    /// How long until `username` may try to log in from `client_ip` again,
+    /// if either is locked out.
+    pub async fn locked_out_for(
+        &self,
+        username: &str,
+        client_ip: &str,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let mut longest: Option<Duration> = None;
+        for (scope, id) in [(Scope::User, username), (Scope::Ip, client_ip)] {
+            // Negative when the key does not exist.
+            let remaining_ms: i64 = redis::cmd("PTTL")
+                .arg(self.key("lockout", scope, id))
+                .query_async(&mut connection)
+                .await
+                .context("Failed to check for a login lockout.")?;
+            if remaining_ms > 0 {
+                let remaining = Duration::from_millis(remaining_ms as u64);
+                longest = Some(longest.map_or(remaining, |l| l.max(remaining)));
+            }
+        }
+        Ok(longest)
+    }
+
+    /// Counts a failed login, locking out the username or the client IP when
+    /// they have failed too often. Returns the lockout, if any.
+    #[tracing::instrument(name = "Record failed login", skip(self, pool))]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        client_ip: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let mut longest: Option<Duration> = None;
+        for (scope, id) in [(Scope::User, username), (Scope::Ip, client_ip)] {
+            let failures_key = self.key("failures", scope, id);
+            let failures: u64 = redis::cmd("INCR")
+                .arg(&failures_key)
+                .query_async(&mut connection)
+                .await
+                .context("Failed to count a failed login.")?;
+            let lockout = lockout_duration(
+                failures,
+                self.max_failures(scope),
+                self.settings.base_lockout_seconds,
+                self.settings.max_lockout_seconds,
+            );
+            // Failures keep counting for a while after a lockout ends, so
+            // that the next one lasts longer.
+            let window = self.settings.failure_window_seconds
+                + lockout.map(|l| l.as_secs()).unwrap_or_default();
+            let _: () = redis::cmd("EXPIRE")
+                .arg(&failures_key)
+                .arg(window)
+                .query_async(&mut connection)
+                .await
+                .context("Failed to set the expiry of the failed login counter.")?;
+
+            let Some(lockout) = lockout else {
+                continue;
+            };
+            let _: () = redis::cmd("SET")
+                .arg(self.key("lockout", scope, id))
+                .arg(failures)
+                .arg("EX")
+                .arg(lockout.as_secs())
+                .query_async(&mut connection)
+                .await
+                .context("Failed to lock out a user.")?;
+            record_audit_event(
+                pool,
+                "login_lockout",
+                AuditSubject {
+                    username: Some(username),
+                    client_ip: Some(client_ip),
+                },
+                &format!(
+                    "Locked out {} {} for {} seconds after {} failed logins.",
+                    scope.as_str(),
+                    id,
+                    lockout.as_secs(),
+                    failures
+                ),
+            )
+            .await?;
+            longest = Some(longest.map_or(lockout, |l| l.max(lockout)));
+        }
+        Ok(longest)
+    }
+
+    /// Forgets the failed logins of `username`, but not those of the client
+    /// IP: a valid account must not let an attacker reset them.
+    pub async fn record_success(&self, username: &str) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let _: () = redis::cmd("DEL")
+            .arg(self.key("failures", Scope::User, username))
+            .query_async(&mut connection)
+            .await
+            .context("Failed to reset the failed login counter.")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::lockout_duration;
+
+    #[test]
+    fn there_is_no_lockout_below_the_threshold() {
+        assert_eq!(lockout_duration(4, 5, 30, 3600), None);
+    }
+
+    #[test]
+    fn lockouts_double_with_every_further_failure() {
+        assert_eq!(
+            lockout_duration(5, 5, 30, 3600),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            lockout_duration(6, 5, 30, 3600),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            lockout_duration(7, 5, 30, 3600),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn lockouts_are_capped() {
+        assert_eq!(
+            lockout_duration(12, 5, 30, 3600),
+            Some(Duration::from_secs(3600))
+        );
+        assert_eq!(
+            lockout_duration(500, 5, 30, 3600),
+            Some(Duration::from_secs(3600))
+        );
+    }
+}
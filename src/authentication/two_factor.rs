@@ -0,0 +1,345 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use secrecy::{ExposeSecret, Secret};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// RFC 6238 defaults, the only parameters every authenticator app supports.
+const TIME_STEP_SECONDS: u64 = 30;
+const CODE_DIGITS: u32 = 6;
+/// Codes of the previous and next time steps are accepted too, to allow for
+/// clock drift between the server and the authenticator.
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32, without padding, as expected in `otpauth://` URIs.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// RFC 4226, truncated to `CODE_DIGITS` digits.
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    binary % 10u32.pow(CODE_DIGITS)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The system clock is set before 1970")
+        .as_secs()
+}
+
+/// The code an authenticator shows at `unix_time` for the base32 `secret`.
+/// Returns `None` if the secret is not valid base32.
+pub fn totp_code(secret: &Secret<String>, unix_time: u64) -> Option<String> {
+    let key = base32_decode(secret.expose_secret())?;
+    Some(format!(
+        "{:0width$}",
+        hotp(&key, unix_time / TIME_STEP_SECONDS),
+        width = CODE_DIGITS as usize
+    ))
+}
+
+/// The time step `code` was generated for, if it is valid around `unix_time`.
+fn matching_step(secret: &Secret<String>, code: &str, unix_time: u64) -> Option<u64> {
+    let key = base32_decode(secret.expose_secret())?;
+    let code: u32 = code.parse().ok()?;
+    let current = unix_time / TIME_STEP_SECONDS;
+    (current.saturating_sub(ALLOWED_DRIFT_STEPS)..=current + ALLOWED_DRIFT_STEPS)
+        .find(|&step| hotp(&key, step) == code)
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == CODE_DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// 160 random bits, the key size recommended by RFC 4226.
+pub fn generate_totp_secret() -> Secret<String> {
+    let mut key = [0u8; 20];
+    thread_rng().fill_bytes(&mut key);
+    Secret::new(base32_encode(&key))
+}
+
+/// What authenticator apps scan or import to enrol `secret`.
+pub fn otpauth_uri(secret: &Secret<String>, username: &str) -> String {
+    let issuer = "zero2prod";
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={CODE_DIGITS}&period={TIME_STEP_SECONDS}",
+        account = urlencoding::encode(username),
+        secret = secret.expose_secret(),
+    )
+}
+
+/// Recovery codes are shown as `xxxxx-xxxxx`, but typed any which way.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(normalize_recovery_code(code).as_bytes()))
+}
+
+fn generate_recovery_code() -> String {
+    let code: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Returns `None` if the user has not enabled two-factor authentication.
+pub async fn get_totp_secret(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Secret<String>>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT totp_secret
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the TOTP secret of the user.")?;
+    Ok(row.and_then(|r| r.totp_secret).map(Secret::new))
+}
+
+/// Turns two-factor authentication on, if `code` proves that the user's
+/// authenticator has enrolled `secret`. Returns the new recovery codes, which
+/// cannot be retrieved later: only their hashes are stored.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(secret, code, pool))]
+pub async fn enable_two_factor(
+    user_id: Uuid,
+    secret: &Secret<String>,
+    code: &str,
+    pool: &PgPool,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let Some(step) = matching_step(secret, code.trim(), unix_time()) else {
+        return Ok(None);
+    };
+
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+
+    let mut transaction = pool.begin().await.context("Failed to begin transaction.")?;
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = $1, totp_last_used_step = $2
+        WHERE user_id = $3
+        "#,
+        secret.expose_secret(),
+        step as i64,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the TOTP secret.")?;
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the previous recovery codes.")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, * FROM UNNEST($2::TEXT[])
+        "#,
+        user_id,
+        &code_hashes
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to store the recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction.")?;
+
+    Ok(Some(recovery_codes))
+}
+
+/// Turns two-factor authentication off and discards the recovery codes.
+/// Returns `false` if it was not enabled.
+#[tracing::instrument(name = "Disable two-factor authentication", skip(pool))]
+pub async fn disable_two_factor(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction.")?;
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_secret = NULL, totp_last_used_step = NULL
+        WHERE user_id = $1 AND totp_secret IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to clear the TOTP secret.")?
+    .rows_affected();
+    sqlx::query!(r#"DELETE FROM recovery_codes WHERE user_id = $1"#, user_id)
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to delete the recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction.")?;
+    Ok(updated_rows > 0)
+}
+
+/// Checks a code from the authenticator, or a recovery code. Either is
+/// accepted only once.
+#[tracing::instrument(name = "Verify second factor", skip(code, pool))]
+pub async fn verify_second_factor(
+    user_id: Uuid,
+    code: &str,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let code = code.trim();
+    if !is_totp_code(code) {
+        let used_rows = sqlx::query!(
+            r#"
+            DELETE FROM recovery_codes
+            WHERE user_id = $1 AND code_hash = $2
+            "#,
+            user_id,
+            hash_recovery_code(code)
+        )
+        .execute(pool)
+        .await
+        .context("Failed to use a recovery code.")?
+        .rows_affected();
+        return Ok(used_rows > 0);
+    }
+
+    let Some(secret) = get_totp_secret(user_id, pool).await? else {
+        return Ok(false);
+    };
+    let Some(step) = matching_step(&secret, code, unix_time()) else {
+        return Ok(false);
+    };
+    // Conditional, so that concurrent requests cannot replay the same code.
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE users
+        SET totp_last_used_step = $1
+        WHERE user_id = $2
+            AND (totp_last_used_step IS NULL OR totp_last_used_step < $1)
+        "#,
+        step as i64,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of a TOTP code.")?
+    .rows_affected();
+    Ok(updated_rows > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::{base32_decode, base32_encode, hash_recovery_code, matching_step, totp_code};
+
+    /// The SHA1 key of the RFC 6238 test vectors, "12345678901234567890".
+    fn rfc_secret() -> Secret<String> {
+        Secret::new(base32_encode(b"12345678901234567890"))
+    }
+
+    #[test]
+    fn base32_matches_the_rfc_4648_test_vectors() {
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("MZXW6YTBOI").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+    }
+
+    #[test]
+    fn totp_matches_the_rfc_6238_test_vectors() {
+        // The last six digits of the eight-digit codes in the RFC.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(totp_code(&rfc_secret(), time).unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn codes_of_adjacent_time_steps_are_accepted() {
+        let secret = rfc_secret();
+        let code = totp_code(&secret, 1111111109).unwrap();
+        assert_eq!(
+            matching_step(&secret, &code, 1111111109 + 30),
+            Some(37037036)
+        );
+        assert_eq!(matching_step(&secret, &code, 1111111109 + 90), None);
+    }
+
+    #[test]
+    fn recovery_codes_are_hashed_regardless_of_formatting() {
+        assert_eq!(
+            hash_recovery_code("abcde-12345"),
+            hash_recovery_code(" ABCDE 12345 ")
+        );
+    }
+}
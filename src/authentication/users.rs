@@ -0,0 +1,150 @@
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::telemetry::spawn_blocking_with_tracing;
+
+use super::{password::compute_password_hash, Role};
+
+pub struct UserRecord {
+    pub user_id: Uuid,
+    pub username: String,
+    pub role: Role,
+    pub disabled: bool,
+    pub two_factor: bool,
+}
+
+#[tracing::instrument(name = "Create user", skip(password, executor))]
+pub async fn create_user<'e>(
+    username: &str,
+    password: Secret<String>,
+    role: Role,
+    executor: impl PgExecutor<'e>,
+) -> Result<Uuid, anyhow::Error> {
+    let password_hash = spawn_blocking_with_tracing(move || compute_password_hash(password))
+        .await?
+        .context("Failed to hash password")?;
+
+    let user_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        user_id,
+        username,
+        password_hash.expose_secret(),
+        role.as_str(),
+    )
+    .execute(executor)
+    .await
+    .context("Failed to store the new user in the database")?;
+    Ok(user_id)
+}
+
+/// Returns `None` if no user goes by `username`.
+pub async fn get_user_id(username: &str, pool: &PgPool) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT user_id
+        FROM users
+        WHERE username = $1
+        "#,
+        username,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the user id.")?;
+    Ok(row.map(|r| r.user_id))
+}
+
+/// Prevents the user from logging in again and ends their current sessions.
+/// Returns `false` if the user was already disabled.
+#[tracing::instrument(name = "Disable user", skip(pool))]
+pub async fn disable_user(user_id: Uuid, pool: &PgPool) -> Result<bool, anyhow::Error> {
+    let disabled_rows = sqlx::query!(
+        r#"
+        UPDATE users
+        SET disabled_at = now()
+        WHERE user_id = $1 AND disabled_at IS NULL
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to disable the user.")?
+    .rows_affected();
+    Ok(disabled_rows > 0)
+}
+
+#[tracing::instrument(name = "Set user role", skip(pool))]
+pub async fn set_user_role(
+    user_id: Uuid,
+    role: Role,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE users
+        SET role = $1
+        WHERE user_id = $2
+        "#,
+        role.as_str(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change the role of the user.")?
+    .rows_affected();
+    Ok(updated_rows > 0)
+}
+
+/// Returns `None` if the user no longer exists or has been disabled.
+pub async fn get_active_user_role(
+    user_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Role>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT role
+        FROM users
+        WHERE user_id = $1 AND disabled_at IS NULL
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the role of the user.")?;
+    row.map(|r| Role::parse(&r.role).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+pub async fn list_users(pool: &PgPool) -> Result<Vec<UserRecord>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            user_id,
+            username,
+            role,
+            disabled_at IS NOT NULL AS "disabled!",
+            totp_secret IS NOT NULL AS "two_factor!"
+        FROM users
+        ORDER BY username
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the users.")?;
+    rows.into_iter()
+        .map(|r| {
+            Ok(UserRecord {
+                user_id: r.user_id,
+                username: r.username,
+                role: Role::parse(&r.role).map_err(anyhow::Error::msg)?,
+                disabled: r.disabled,
+                two_factor: r.two_factor,
+            })
+        })
+        .collect()
+}
@@ -1,12 +1,16 @@
 use secrecy::ExposeSecret;
 use secrecy::Secret;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::PgConnectOptions;
 use sqlx::postgres::PgSslMode;
 use sqlx::ConnectOptions;
 
+use std::sync::Arc;
+
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailAPIClient;
+use crate::email_client::{EmailAPIClient, EmailSender, FileSinkEmailSender, SmtpEmailSender};
 
 #[derive(serde::Deserialize, Clone)]
 pub struct ApplicationSettings {
@@ -15,6 +19,10 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    /// Proxies allowed to tell who the client is, through the entries they
+    /// append to `X-Forwarded-For`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -28,12 +36,123 @@ pub struct DatabaseSettings {
     pub require_ssl: bool,
 }
 
+#[derive(serde::Deserialize, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailTransport {
+    #[default]
+    Postmark,
+    Smtp,
+    FileSink,
+}
+
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailAPIClientSettings {
+    #[serde(default)]
+    pub transport: EmailTransport,
     pub api_base_url: String,
     pub sender_email: String,
     pub authorization_token: String,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink_directory: Option<String>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    #[serde(default)]
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WorkerSettings {
+    /// Number of workers pulling from the delivery queue at the same time.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: usize,
+    /// How long an idle worker waits before polling the queue again, when
+    /// it cannot rely on notifications of new tasks.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_poll_interval_milliseconds: u64,
+    /// How long a worker waits after a failed iteration.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub error_backoff_milliseconds: u64,
+    /// Queue rows locked and delivered together by a single worker iteration.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub delivery_batch_size: usize,
+    /// Sending budget shared by all the workers of a process. Leave unset
+    /// to send as fast as the email provider allows.
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_sends_per_second: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub max_sends_per_hour: Option<u32>,
+    /// Port of the health check served by worker-only processes.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub health_check_port: u16,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottlingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_user: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: u32,
+    /// How long a failed login counts towards a lockout.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_lockout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lockout_seconds: u64,
+    /// Namespaces the counters, e.g. when several deployments share Redis.
+    pub redis_key_prefix: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionThrottlingSettings {
+    /// Subscription requests allowed per window, from a client IP and for an
+    /// email address.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests_per_ip: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_requests_per_email: u32,
+    /// Stricter limit per client IP for requests without a signup form
+    /// token, which skip the form's bot checks.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_tokenless_requests_per_ip: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    /// Confirmation emails a pending subscriber can be sent per window.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_confirmation_emails: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_email_window_hours: u32,
+    /// Signup forms submitted faster than this are taken for bots.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_form_fill_seconds: u64,
+    /// Namespaces the counters, e.g. when several deployments share Redis.
+    pub redis_key_prefix: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct MaintenanceSettings {
+    /// How often the worker process purges stale rows.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+    /// How long confirmation tokens are kept after they expire, so that
+    /// their links can still ask for a fresh one.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub expired_token_retention_hours: u32,
+    /// How long subscriptions wait for a confirmation after their latest
+    /// confirmation email.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pending_subscription_retention_days: u32,
+    /// How long retries with the same idempotency key get the saved response.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idempotency_retention_hours: u32,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -41,7 +160,14 @@ pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailAPIClientSettings,
+    pub worker: WorkerSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub subscription_throttling: SubscriptionThrottlingSettings,
+    pub maintenance: MaintenanceSettings,
     pub redis_uri: Secret<String>,
+    /// Taken from `APP_ENVIRONMENT` rather than from the configuration files.
+    #[serde(skip)]
+    pub environment: Environment,
 }
 
 impl EmailAPIClientSettings {
@@ -54,7 +180,31 @@ impl EmailAPIClientSettings {
     }
 }
 
+impl WorkerSettings {
+    pub fn idle_poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.idle_poll_interval_milliseconds)
+    }
+
+    pub fn error_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.error_backoff_milliseconds)
+    }
+}
+
+impl SubscriptionThrottlingSettings {
+    pub fn window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.window_seconds)
+    }
+}
+
+impl MaintenanceSettings {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.interval_seconds)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum Environment {
+    #[default]
     Local,
     Production,
 }
@@ -109,7 +259,9 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize::<Settings>()
+    let mut settings = settings.try_deserialize::<Settings>()?;
+    settings.environment = environment;
+    Ok(settings)
 }
 
 impl DatabaseSettings {
@@ -146,4 +298,36 @@ impl EmailAPIClientSettings {
             timeout,
         )
     }
+
+    /// Builds the transport selected by `transport`.
+    pub fn email_sender(self) -> Result<Arc<dyn EmailSender>, anyhow::Error> {
+        let sender_email = self.sender().map_err(anyhow::Error::msg)?;
+        match self.transport {
+            EmailTransport::Postmark => Ok(Arc::new(self.client())),
+            EmailTransport::Smtp => {
+                let timeout = self.timeout();
+                let smtp = self
+                    .smtp
+                    .ok_or_else(|| anyhow::anyhow!("Missing `email_client.smtp` settings."))?;
+                let credentials = smtp.username.zip(smtp.password);
+                Ok(Arc::new(SmtpEmailSender::new(
+                    &smtp.host,
+                    smtp.port,
+                    smtp.starttls,
+                    credentials,
+                    sender_email,
+                    timeout,
+                )?))
+            }
+            EmailTransport::FileSink => {
+                let directory = self.file_sink_directory.ok_or_else(|| {
+                    anyhow::anyhow!("Missing `email_client.file_sink_directory` setting.")
+                })?;
+                Ok(Arc::new(FileSinkEmailSender::new(
+                    directory.into(),
+                    sender_email,
+                )?))
+            }
+        }
+    }
 }
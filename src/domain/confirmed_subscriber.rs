@@ -1,30 +0,0 @@
-use sqlx::PgPool;
-
-use super::SubscriberEmail;
-
-pub struct ConfirmedSubscriber {
-    pub email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-pub async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subs = sqlx::query!(
-        r#"
-    SELECT email
-    FROM subscriptions
-    WHERE status = 'confirmed'
-    "#
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-
-    Ok(confirmed_subs)
-}
@@ -1,13 +1,20 @@
-mod confirmed_subscriber;
 mod new_subscriber;
+mod preferences_token;
 mod publish_issue;
+mod scheduled_for;
+mod signup_form_token;
 mod subscriber_email;
 mod subscriber_name;
 mod subscriber_status;
+mod subscriber_token;
+mod unsubscribe_token;
 
-pub use confirmed_subscriber::*;
 pub use new_subscriber::NewSubscriber;
+pub use preferences_token::PreferencesToken;
 pub use publish_issue::*;
+pub use scheduled_for::ScheduledFor;
+pub use signup_form_token::{SignupFormToken, SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
 pub use subscriber_status::SubscriberStatus;
+pub use unsubscribe_token::UnsubscribeToken;
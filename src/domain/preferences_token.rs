@@ -0,0 +1,60 @@
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::subscriber_token::SubscriberToken;
+
+/// Token embedded in the preferences links sent to subscribers.
+///
+/// It has a scope of its own: leaking an unsubscribe link does not let
+/// anyone change the subscriber's email address. It is bound to the email
+/// address it was sent to, so that links in the old mailbox stop working
+/// once the address changes.
+#[derive(Debug)]
+pub struct PreferencesToken(String);
+
+const TOKEN: SubscriberToken = SubscriberToken::new("preferences");
+
+impl PreferencesToken {
+    pub fn new(subscriber_id: Uuid, email: &str, hmac_secret: &Secret<String>) -> Self {
+        Self(TOKEN.issue(subscriber_id, email, hmac_secret))
+    }
+
+    /// The subscriber the token claims to be for, to look up their current
+    /// email address before calling `verify`.
+    pub fn claimed_subscriber_id(token: &str) -> Result<Uuid, String> {
+        TOKEN.claimed_subscriber_id(token)
+    }
+
+    /// Checks the token signature against the current email address of the
+    /// subscriber, and returns the subscriber id it was issued for.
+    pub fn verify(token: &str, email: &str, hmac_secret: &Secret<String>) -> Result<Uuid, String> {
+        TOKEN.verify(token, email, hmac_secret)
+    }
+
+    pub fn preferences_link(&self, base_url: &str) -> String {
+        format!("{}/subscriptions/preferences?token={}", base_url, self.0)
+    }
+}
+
+impl AsRef<str> for PreferencesToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreferencesToken;
+    use crate::domain::UnsubscribeToken;
+    use claims::assert_err;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    #[test]
+    fn an_unsubscribe_token_is_not_a_preferences_token() {
+        let secret = Secret::new(Uuid::new_v4().to_string());
+        let subscriber_id = Uuid::new_v4();
+        let token = UnsubscribeToken::new(subscriber_id, &secret);
+        assert_err!(PreferencesToken::verify(token.as_ref(), "", &secret));
+    }
+}
@@ -1,15 +1,4 @@
-use anyhow::Context;
-use sqlx::PgPool;
-
-use crate::email_client::EmailAPIClient;
-
-use super::get_confirmed_subscribers;
-
-#[derive(serde::Deserialize)]
-pub struct IssueContent {
-    pub title: String,
-    pub content: Content,
-}
+use crate::email_client::EmailHeader;
 
 #[derive(serde::Deserialize)]
 pub struct Content {
@@ -17,42 +6,42 @@ pub struct Content {
     pub text: String,
 }
 
-/// Publishes the issue content to the confirmed subscribers.
-#[tracing::instrument(
-    name = "Publish issue",
-    skip(issue, email_client, pool),
-    fields(num_subscribers)
-)]
-pub async fn publish_issue(
-    issue: &IssueContent,
-    email_client: &EmailAPIClient,
-    pool: &PgPool,
-) -> Result<(), anyhow::Error> {
-    let subscribers = get_confirmed_subscribers(pool)
-        .await
-        .context("Failed to retrieve subscribers")?;
+/// Appends the subscriber's preferences and unsubscribe links to the HTML
+/// body of an issue.
+pub fn html_with_subscription_links(
+    html_content: &str,
+    preferences_link: &str,
+    unsubscribe_link: &str,
+) -> String {
+    format!(
+        "{}<p><a href=\"{}\">Manage your preferences</a> or <a href=\"{}\">unsubscribe</a> from this newsletter.</p>",
+        html_content, preferences_link, unsubscribe_link
+    )
+}
 
-    tracing::Span::current().record("num_subscribers", subscribers.len());
+/// Appends the subscriber's preferences and unsubscribe links to the plain
+/// text body of an issue.
+pub fn text_with_subscription_links(
+    text_content: &str,
+    preferences_link: &str,
+    unsubscribe_link: &str,
+) -> String {
+    format!(
+        "{}\n\nManage your preferences: {}\nUnsubscribe from this newsletter: {}",
+        text_content, preferences_link, unsubscribe_link
+    )
+}
 
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &issue.title,
-                        &issue.content.html,
-                        &issue.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(error.cause_chain = ?error, "Skipping a confirmed subscriber. Their stored contact details are invalid!");
-            }
-        }
-    }
-    Ok(())
+/// RFC 8058 headers letting mailbox providers offer a one-click unsubscribe.
+pub fn list_unsubscribe_headers(one_click_unsubscribe_link: &str) -> Vec<EmailHeader> {
+    vec![
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!("<{}>", one_click_unsubscribe_link),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ]
 }
@@ -0,0 +1,116 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+
+/// Point in the future at which a newsletter issue starts going out.
+#[derive(Clone, Copy, Debug)]
+pub struct ScheduledFor(DateTime<Utc>);
+
+impl ScheduledFor {
+    /// Accepts RFC 3339 timestamps and the offset-less values submitted by
+    /// HTML `datetime-local` inputs. Those are taken at `utc_offset`, such as
+    /// `+02:00`, or as UTC when it is missing or blank.
+    pub fn parse(s: &str, utc_offset: Option<&str>) -> Result<ScheduledFor, String> {
+        let s = s.trim();
+        let offset = match utc_offset.map(str::trim) {
+            Some(o) if !o.is_empty() => o
+                .parse::<FixedOffset>()
+                .map_err(|_| format!("Invalid UTC offset: {}", o))?,
+            _ => FixedOffset::east_opt(0).unwrap(),
+        };
+        let scheduled_for = DateTime::parse_from_rfc3339(s)
+            .map(|t| t.with_timezone(&Utc))
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+                    .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M"))
+                    .map(|t| (t - offset).and_utc())
+            })
+            .map_err(|_| format!("Invalid send time: {}", s))?;
+
+        if scheduled_for <= Utc::now() {
+            return Err(format!("The send time {} is in the past.", s));
+        }
+        Ok(Self(scheduled_for))
+    }
+
+    /// Parses an optional form or JSON field, where a blank value means
+    /// "send right away".
+    pub fn parse_optional(
+        s: Option<&str>,
+        utc_offset: Option<&str>,
+    ) -> Result<Option<ScheduledFor>, String> {
+        match s {
+            Some(s) if !s.trim().is_empty() => Self::parse(s, utc_offset).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl AsRef<DateTime<Utc>> for ScheduledFor {
+    fn as_ref(&self) -> &DateTime<Utc> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_none, assert_ok, assert_some};
+
+    use super::ScheduledFor;
+
+    #[test]
+    fn future_rfc3339_timestamps_are_accepted() {
+        let s = (Utc::now() + Duration::days(3)).to_rfc3339();
+        assert_ok!(ScheduledFor::parse(&s, None));
+    }
+
+    #[test]
+    fn datetime_local_values_are_accepted_as_utc() {
+        let future = Utc::now() + Duration::days(3);
+        let s = future.format("%Y-%m-%dT%H:%M").to_string();
+        let scheduled_for = ScheduledFor::parse(&s, Some("")).unwrap();
+        assert_eq!(
+            scheduled_for.as_ref().format("%Y-%m-%dT%H:%M").to_string(),
+            s
+        );
+    }
+
+    #[test]
+    fn datetime_local_values_are_taken_at_the_given_utc_offset() {
+        let future = Utc::now() + Duration::days(3);
+        let s = future.format("%Y-%m-%dT%H:%M").to_string();
+        let scheduled_for = ScheduledFor::parse(&s, Some("+02:00")).unwrap();
+        assert_eq!(
+            (*scheduled_for.as_ref() + Duration::hours(2))
+                .format("%Y-%m-%dT%H:%M")
+                .to_string(),
+            s
+        );
+    }
+
+    #[test]
+    fn invalid_utc_offsets_are_rejected() {
+        let s = (Utc::now() + Duration::days(3))
+            .format("%Y-%m-%dT%H:%M")
+            .to_string();
+        assert_err!(ScheduledFor::parse(&s, Some("Europe/Rome")));
+    }
+
+    #[test]
+    fn past_timestamps_are_rejected() {
+        let s = (Utc::now() - Duration::minutes(1)).to_rfc3339();
+        assert_err!(ScheduledFor::parse(&s, None));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(ScheduledFor::parse("next monday", None));
+    }
+
+    #[test]
+    fn blank_optional_values_mean_no_schedule() {
+        assert_none!(ScheduledFor::parse_optional(None, None).unwrap());
+        assert_none!(ScheduledFor::parse_optional(Some("  "), None).unwrap());
+        let s = (Utc::now() + Duration::hours(1)).to_rfc3339();
+        assert_some!(ScheduledFor::parse_optional(Some(&s), None).unwrap());
+    }
+}
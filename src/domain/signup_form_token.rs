@@ -0,0 +1,110 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+
+/// Hidden field of the signup form, recording when the form was served.
+///
+/// Bots submit forms much faster than people fill them in. The timestamp is
+/// signed, so that they cannot pretend to have waited.
+#[derive(Debug)]
+pub struct SignupFormToken(String);
+
+/// Domain separation for the tag, as for `SubscriberToken`.
+const SCOPE: &[u8] = b"signup_form";
+
+/// Past this, the form has to be reloaded: a token cannot be kept around
+/// and replayed.
+pub const SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS: i64 = 3 * 60 * 60;
+
+fn mac(issued_at: i64, hmac_secret: &Secret<String>) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take keys of any size.");
+    mac.update(SCOPE);
+    mac.update(&issued_at.to_be_bytes());
+    mac
+}
+
+impl SignupFormToken {
+    /// `issued_at` is a Unix timestamp, in seconds.
+    pub fn new(issued_at: i64, hmac_secret: &Secret<String>) -> Self {
+        let tag = hex::encode(mac(issued_at, hmac_secret).finalize().into_bytes());
+        Self(format!("{}.{}", issued_at, tag))
+    }
+
+    /// Checks the token signature and age, and returns when the form was
+    /// served. `now` is a Unix timestamp, in seconds.
+    pub fn verify(token: &str, now: i64, hmac_secret: &Secret<String>) -> Result<i64, String> {
+        let (issued_at, tag) = token
+            .split_once('.')
+            .ok_or_else(|| "Malformed signup form token.".to_string())?;
+        let issued_at: i64 = issued_at
+            .parse()
+            .map_err(|_| "Malformed signup form token.".to_string())?;
+        let tag = hex::decode(tag).map_err(|_| "Malformed signup form token.".to_string())?;
+
+        mac(issued_at, hmac_secret)
+            .verify_slice(&tag)
+            .map_err(|_| "Invalid signup form token.".to_string())?;
+        if now - issued_at > SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS {
+            return Err("The signup form has expired, reload the page.".into());
+        }
+        Ok(issued_at)
+    }
+}
+
+impl AsRef<str> for SignupFormToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SignupFormToken, SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS};
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+
+    fn secret() -> Secret<String> {
+        Secret::new(uuid::Uuid::new_v4().to_string())
+    }
+
+    #[test]
+    fn a_token_verifies_to_the_time_it_was_issued_at() {
+        let secret = secret();
+        let token = SignupFormToken::new(1_700_000_000, &secret);
+        assert_ok_eq!(
+            SignupFormToken::verify(token.as_ref(), 1_700_000_010, &secret),
+            1_700_000_000
+        );
+    }
+
+    #[test]
+    fn a_backdated_token_is_rejected() {
+        let secret = secret();
+        let token = SignupFormToken::new(1_700_000_000, &secret);
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", 1_600_000_000, tag);
+        assert_err!(SignupFormToken::verify(&forged, 1_700_000_010, &secret));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = SignupFormToken::new(1_700_000_000, &secret());
+        assert_err!(SignupFormToken::verify(
+            token.as_ref(),
+            1_700_000_010,
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn an_old_token_is_rejected() {
+        let secret = secret();
+        let token = SignupFormToken::new(1_700_000_000, &secret);
+        let now = 1_700_000_000 + SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS;
+        assert_ok_eq!(
+            SignupFormToken::verify(token.as_ref(), now, &secret),
+            1_700_000_000
+        );
+        assert_err!(SignupFormToken::verify(token.as_ref(), now + 1, &secret));
+    }
+}
@@ -9,6 +9,7 @@ impl SubscriberStatus {
         match s {
             "pending_confirmation" => Ok(Self::PendingConfirmation),
             "confirmed" => Ok(Self::Confirmed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
             _ => Err(format!("Invalid status representation: {}", s)),
         }
     }
@@ -0,0 +1,144 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+/// Signed token naming a subscriber, for the links sent to them.
+///
+/// It carries the subscriber id and an HMAC tag of it, so that subscribers
+/// can act on their subscription without logging in and without us storing
+/// a token per recipient. The tag also covers:
+/// - a scope, for domain separation: a token issued for one kind of link
+///   cannot be replayed as another;
+/// - a binding, e.g. the subscriber email: the token stops working once it
+///   changes.
+pub(crate) struct SubscriberToken {
+    scope: &'static str,
+}
+
+impl SubscriberToken {
+    pub(crate) const fn new(scope: &'static str) -> Self {
+        Self { scope }
+    }
+
+    fn mac(
+        &self,
+        subscriber_id: Uuid,
+        binding: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Hmac<sha2::Sha256> {
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take keys of any size.");
+        mac.update(self.scope.as_bytes());
+        mac.update(subscriber_id.as_bytes());
+        mac.update(binding.as_bytes());
+        mac
+    }
+
+    pub(crate) fn issue(
+        &self,
+        subscriber_id: Uuid,
+        binding: &str,
+        hmac_secret: &Secret<String>,
+    ) -> String {
+        let tag = hex::encode(
+            self.mac(subscriber_id, binding, hmac_secret)
+                .finalize()
+                .into_bytes(),
+        );
+        format!("{}.{}", subscriber_id, tag)
+    }
+
+    fn parse(&self, token: &str) -> Result<(Uuid, Vec<u8>), String> {
+        let malformed = || format!("Malformed {} token.", self.scope);
+        let (subscriber_id, tag) = token.split_once('.').ok_or_else(malformed)?;
+        let subscriber_id = Uuid::parse_str(subscriber_id).map_err(|_| malformed())?;
+        let tag = hex::decode(tag).map_err(|_| malformed())?;
+        Ok((subscriber_id, tag))
+    }
+
+    /// The subscriber the token claims to be for, before it is verified:
+    /// only good for looking up what it is bound to.
+    pub(crate) fn claimed_subscriber_id(&self, token: &str) -> Result<Uuid, String> {
+        self.parse(token).map(|(subscriber_id, _)| subscriber_id)
+    }
+
+    /// Checks the token signature and returns the subscriber id it was
+    /// issued for.
+    pub(crate) fn verify(
+        &self,
+        token: &str,
+        binding: &str,
+        hmac_secret: &Secret<String>,
+    ) -> Result<Uuid, String> {
+        let (subscriber_id, tag) = self.parse(token)?;
+        self.mac(subscriber_id, binding, hmac_secret)
+            .verify_slice(&tag)
+            .map_err(|_| format!("Invalid {} token.", self.scope))?;
+        Ok(subscriber_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberToken;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    const TOKEN: SubscriberToken = SubscriberToken::new("test");
+
+    fn secret() -> Secret<String> {
+        Secret::new(Uuid::new_v4().to_string())
+    }
+
+    #[test]
+    fn a_token_verifies_to_the_subscriber_it_was_issued_for() {
+        let secret = secret();
+        let subscriber_id = Uuid::new_v4();
+        let token = TOKEN.issue(subscriber_id, "binding", &secret);
+        assert_ok_eq!(TOKEN.claimed_subscriber_id(&token), subscriber_id);
+        assert_ok_eq!(TOKEN.verify(&token, "binding", &secret), subscriber_id);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token = TOKEN.issue(Uuid::new_v4(), "", &secret());
+        assert_err!(TOKEN.verify(&token, "", &secret()));
+    }
+
+    #[test]
+    fn a_token_for_a_different_subscriber_is_rejected() {
+        let secret = secret();
+        let token = TOKEN.issue(Uuid::new_v4(), "", &secret);
+        let (_, tag) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", Uuid::new_v4(), tag);
+        assert_err!(TOKEN.verify(&forged, "", &secret));
+    }
+
+    #[test]
+    fn a_token_of_another_scope_is_rejected() {
+        let secret = secret();
+        let token = SubscriberToken::new("other").issue(Uuid::new_v4(), "", &secret);
+        assert_err!(TOKEN.verify(&token, "", &secret));
+    }
+
+    #[test]
+    fn a_token_with_another_binding_is_rejected() {
+        let secret = secret();
+        let token = TOKEN.issue(Uuid::new_v4(), "old@example.com", &secret);
+        assert_err!(TOKEN.verify(&token, "new@example.com", &secret));
+    }
+
+    #[test]
+    fn malformed_tokens_are_rejected() {
+        let secret = secret();
+        for token in [
+            "",
+            "not-a-token",
+            "not-a-uuid.abcd",
+            &format!("{}.zz", Uuid::new_v4()),
+        ] {
+            assert_err!(TOKEN.verify(token, "", &secret));
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use secrecy::Secret;
+use uuid::Uuid;
+
+use super::subscriber_token::SubscriberToken;
+
+/// Token embedded in the unsubscribe links sent to subscribers.
+///
+/// Unbound: leaving keeps working whatever changed since the link was sent.
+#[derive(Debug)]
+pub struct UnsubscribeToken(String);
+
+const TOKEN: SubscriberToken = SubscriberToken::new("unsubscribe");
+
+impl UnsubscribeToken {
+    pub fn new(subscriber_id: Uuid, hmac_secret: &Secret<String>) -> Self {
+        Self(TOKEN.issue(subscriber_id, "", hmac_secret))
+    }
+
+    /// Checks the token signature and returns the subscriber id it was
+    /// issued for.
+    pub fn verify(token: &str, hmac_secret: &Secret<String>) -> Result<Uuid, String> {
+        TOKEN.verify(token, "", hmac_secret)
+    }
+
+    pub fn unsubscribe_link(&self, base_url: &str) -> String {
+        format!("{}/subscriptions/unsubscribe?token={}", base_url, self.0)
+    }
+
+    /// Target of the RFC 8058 `List-Unsubscribe` header: mailbox providers
+    /// POST to it directly, without a browser session.
+    pub fn one_click_unsubscribe_link(&self, base_url: &str) -> String {
+        format!(
+            "{}/subscriptions/unsubscribe/one-click?token={}",
+            base_url, self.0
+        )
+    }
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
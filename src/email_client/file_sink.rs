@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use crate::domain::SubscriberEmail;
+
+use super::{build_message, EmailHeader, EmailSender, SendEmailError};
+
+/// Writes every email as an `.eml` file in a local directory, for
+/// development without an email provider.
+#[derive(Clone)]
+pub struct FileSinkEmailSender {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkEmailSender {
+    pub fn new(directory: PathBuf, sender: SubscriberEmail) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Failed to create {}", directory.display()))?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(directory),
+            sender,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSinkEmailSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to write the email to the sink directory.")
+            .map_err(SendEmailError::Permanent)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_ok;
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailHeader, EmailSender},
+    };
+
+    use super::FileSinkEmailSender;
+
+    #[tokio::test]
+    async fn emails_are_written_to_the_sink_directory() {
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let sender = SubscriberEmail::parse("sender@example.com".into()).unwrap();
+        let email_sender = FileSinkEmailSender::new(directory.clone(), sender).unwrap();
+        let recipient = SubscriberEmail::parse("recipient@example.com".into()).unwrap();
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: "<https://example.com/unsubscribe>".into(),
+        }];
+
+        assert_ok!(
+            email_sender
+                .send_email_with_headers(&recipient, "Subject", "<p>HTML</p>", "Text", &headers)
+                .await
+        );
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let eml = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(eml.contains("To: recipient@example.com"));
+        assert!(eml.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+mod file_sink;
+mod postmark;
+mod smtp;
+
+pub use file_sink::FileSinkEmailSender;
+pub use postmark::EmailAPIClient;
+pub use smtp::SmtpEmailSender;
+
+use std::time::Duration;
+
+use anyhow::Context;
+use lettre::message::{
+    header::{HeaderName, HeaderValue},
+    Mailbox, Message, MultiPart,
+};
+
+use crate::{domain::SubscriberEmail, routes::error_chain_fmt};
+
+/// Custom header added to an outgoing email.
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    /// Worth retrying later: the transport may recover on its own.
+    #[error(transparent)]
+    Transient(anyhow::Error),
+    #[error(transparent)]
+    Permanent(anyhow::Error),
+    /// The provider is throttling us: nothing should be sent before
+    /// `retry_after`, when it tells us.
+    #[error("The email provider is rate limiting us.")]
+    RateLimited { retry_after: Option<Duration> },
+}
+
+impl SendEmailError {
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            SendEmailError::Transient(_) | SendEmailError::RateLimited { .. }
+        )
+    }
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// A fully rendered email, ready to be handed to a transport in a batch.
+#[derive(Clone, Debug)]
+pub struct OutgoingEmail {
+    pub recipient: SubscriberEmail,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub headers: Vec<EmailHeader>,
+}
+
+/// Transport used to deliver every email the application sends.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    /// Sends every email and reports the outcome of each one, in order.
+    /// A failure only affects the email it belongs to.
+    ///
+    /// Transports without a batch API send the emails one at a time.
+    async fn send_batch(&self, emails: &[OutgoingEmail]) -> Vec<Result<(), SendEmailError>> {
+        let mut results = Vec::with_capacity(emails.len());
+        for email in emails {
+            results.push(
+                self.send_email_with_headers(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers,
+                )
+                .await,
+            );
+        }
+        results
+    }
+}
+
+/// MIME message shared by the transports that do not go through an HTTP API.
+fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[EmailHeader],
+) -> Result<Message, anyhow::Error> {
+    let from: Mailbox = sender.as_ref().parse().context("Invalid sender address.")?;
+    let to: Mailbox = recipient
+        .as_ref()
+        .parse()
+        .context("Invalid recipient address.")?;
+    let mut builder = Message::builder().from(from).to(to).subject(subject);
+    for header in headers {
+        let name = HeaderName::new_from_ascii(header.name.clone())
+            .with_context(|| format!("Invalid header name: {}", header.name))?;
+        builder = builder.raw_header(HeaderValue::new(name, header.value.clone()));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))
+        .context("Failed to build the email message.")
+}
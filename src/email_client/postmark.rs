@@ -0,0 +1,578 @@
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::SubscriberEmail;
+
+use super::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError};
+
+/// Postmark accepts at most this many messages per call to `/email/batch`.
+const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Clone)]
+pub struct EmailAPIClient {
+    http_client: Client,
+    api_base_url: String,
+    sender: SubscriberEmail, // Worth using a different name for a generic "validated" email?
+    authorization_token: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    headers: &'a [EmailHeader],
+}
+
+/// Outcome of a single message of a batch, as reported by Postmark.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+    error_code: i64,
+    message: String,
+}
+
+impl EmailAPIClient {
+    pub fn new(
+        api_base_url: String,
+        sender: SubscriberEmail,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+
+        Self {
+            http_client,
+            api_base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    /// Posts up to `MAX_BATCH_SIZE` emails in a single request.
+    ///
+    /// Postmark answers 200 as long as the request itself is valid and
+    /// reports the outcome of each message in the body, in the order they
+    /// were sent.
+    async fn send_batch_chunk(
+        &self,
+        emails: &[OutgoingEmail],
+    ) -> Result<Vec<Result<(), SendEmailError>>, SendEmailError> {
+        let url = format!("{}/email/batch", self.api_base_url);
+
+        let request_body: Vec<_> = emails
+            .iter()
+            .map(|email| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: email.recipient.as_ref(),
+                subject: &email.subject,
+                html_body: &email.html_content,
+                text_body: &email.text_content,
+                headers: &email.headers,
+            })
+            .collect();
+
+        let message_results: Vec<BatchMessageResult> = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(SendEmailError::from)
+            .and_then(check_rate_limit)?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if message_results.len() != emails.len() {
+            return Err(SendEmailError::Permanent(anyhow::anyhow!(
+                "Postmark returned {} results for a batch of {} emails.",
+                message_results.len(),
+                emails.len()
+            )));
+        }
+
+        Ok(message_results
+            .into_iter()
+            .map(|r| match r.error_code {
+                0 => Ok(()),
+                error_code => Err(SendEmailError::Permanent(anyhow::anyhow!(
+                    "Postmark rejected the email (error code {}): {}",
+                    error_code,
+                    r.message
+                ))),
+            })
+            .collect())
+    }
+}
+
+/// Postmark answers 429 when we send too fast. `Retry-After`, when present,
+/// is a number of seconds.
+fn check_rate_limit(response: reqwest::Response) -> Result<reqwest::Response, SendEmailError> {
+    if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return Ok(response);
+    }
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(std::time::Duration::from_secs);
+    Err(SendEmailError::RateLimited { retry_after })
+}
+
+/// Spreads the failure of a whole batch request to each of its emails.
+fn batch_failure(e: SendEmailError, n_emails: usize) -> Vec<Result<(), SendEmailError>> {
+    if let SendEmailError::RateLimited { retry_after } = e {
+        return (0..n_emails)
+            .map(|_| Err(SendEmailError::RateLimited { retry_after }))
+            .collect();
+    }
+    let is_transient = e.is_transient();
+    let message = e.to_string();
+    (0..n_emails)
+        .map(|_| {
+            let e = anyhow::anyhow!("{}", message);
+            Err(if is_transient {
+                SendEmailError::Transient(e)
+            } else {
+                SendEmailError::Permanent(e)
+            })
+        })
+        .collect()
+}
+
+/// Timeouts, connection errors, rate limiting and server errors may go away
+/// on their own; anything else will fail again on retry.
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        let is_transient = e.is_timeout()
+            || e.is_connect()
+            || e.status().is_some_and(|status| {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            });
+        if is_transient {
+            SendEmailError::Transient(e.into())
+        } else {
+            SendEmailError::Permanent(e.into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for EmailAPIClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let url = format!("{}/email", self.api_base_url);
+
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+
+        self.http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(SendEmailError::from)
+            .and_then(check_rate_limit)?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    async fn send_batch(&self, emails: &[OutgoingEmail]) -> Vec<Result<(), SendEmailError>> {
+        // A lone email goes through `/email`, whose status code tells apart
+        // rejections worth retrying.
+        if let [email] = emails {
+            return vec![
+                self.send_email_with_headers(
+                    &email.recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                    &email.headers,
+                )
+                .await,
+            ];
+        }
+
+        let mut results = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_batch_chunk(chunk).await {
+                Ok(chunk_results) => results.extend(chunk_results),
+                Err(e) => results.extend(batch_failure(e, chunk.len())),
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::SubscriberEmail,
+        email_client::{EmailHeader, EmailSender, OutgoingEmail, SendEmailError},
+    };
+
+    use super::EmailAPIClient;
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let res: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = res {
+                dbg!(&body);
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    struct HeadersBodyMatcher;
+
+    impl wiremock::Match for HeadersBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let res: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = res {
+                body["Headers"]
+                    == serde_json::json!([{ "Name": "X-Test-Header", "Value": "test value" }])
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Answers a batch request with a successful result for each message.
+    struct AcceptEveryMessage;
+
+    impl wiremock::Respond for AcceptEveryMessage {
+        fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+            let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = body
+                .iter()
+                .map(|message| {
+                    serde_json::json!({ "ErrorCode": 0, "Message": "OK", "To": message["To"] })
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    fn email_client(api_base_url: String) -> EmailAPIClient {
+        EmailAPIClient::new(
+            api_base_url,
+            email(),
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(200),
+        )
+    }
+
+    fn outgoing_emails(n: usize) -> Vec<OutgoingEmail> {
+        (0..n)
+            .map(|_| OutgoingEmail {
+                recipient: email(),
+                subject: subject(),
+                html_content: content(),
+                text_content: content(),
+                headers: vec![],
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_email_fires_a_request_to_api_url() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_passes_the_headers_to_the_api() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .and(HeadersBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let headers = [EmailHeader {
+            name: "X-Test-Header".into(),
+            value: "test value".into(),
+        }];
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)); // Wait for 3 mins
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn server_errors_and_timeouts_are_transient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(assert_err!(outcome).is_transient());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_permanent() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(!assert_err!(outcome).is_transient());
+    }
+
+    #[tokio::test]
+    async fn send_batch_posts_every_email_in_a_single_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(AcceptEveryMessage)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&outgoing_emails(3)).await;
+
+        assert_eq!(results.len(), 3);
+        for result in results {
+            assert_ok!(result);
+        }
+        let requests = mock_server.received_requests().await.unwrap();
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body.len(), 3);
+        assert!(body[0].get("HtmlBody").is_some());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(AcceptEveryMessage)
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&outgoing_emails(501)).await;
+
+        assert_eq!(results.len(), 501);
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_reports_the_outcome_of_each_message() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK" },
+                { "ErrorCode": 406, "Message": "You tried to send to a recipient that has been marked as inactive." },
+                { "ErrorCode": 0, "Message": "OK" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut results = email_client.send_batch(&outgoing_emails(3)).await;
+
+        assert_ok!(results.remove(0));
+        assert!(!assert_err!(results.remove(0)).is_transient());
+        assert_ok!(results.remove(0));
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_request_fails_every_message() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let results = email_client.send_batch(&outgoing_emails(3)).await;
+
+        assert_eq!(results.len(), 3);
+        for result in results {
+            assert!(assert_err!(result).is_transient());
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_report_when_to_retry() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "30"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        match assert_err!(outcome) {
+            SendEmailError::RateLimited { retry_after } => {
+                assert_eq!(retry_after, Some(std::time::Duration::from_secs(30)))
+            }
+            e => panic!("Expected a rate limiting error, got {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use lettre::{
+    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport,
+    Tokio1Executor,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::domain::SubscriberEmail;
+
+use super::{build_message, EmailHeader, EmailSender, SendEmailError};
+
+/// Delivers emails to an SMTP relay.
+#[derive(Clone)]
+pub struct SmtpEmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl SmtpEmailSender {
+    pub fn new(
+        host: &str,
+        port: u16,
+        starttls: bool,
+        credentials: Option<(String, Secret<String>)>,
+        sender: SubscriberEmail,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = if starttls {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+        };
+        builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            sender,
+        })
+    }
+}
+
+/// 4xx replies, timeouts and dropped connections are transient: anything
+/// else, like a rejected recipient, will fail again.
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        if e.is_permanent() || e.is_client() || e.is_tls() {
+            SendEmailError::Permanent(e.into())
+        } else {
+            SendEmailError::Transient(e.into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for SmtpEmailSender {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<(), SendEmailError> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )
+        .map_err(SendEmailError::Permanent)?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
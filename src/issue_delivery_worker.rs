@@ -1,121 +1,374 @@
-use std::time::Duration;
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
 
-use sqlx::{PgPool, Postgres, Transaction};
-use tracing::{field::display, Span};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use secrecy::Secret;
+use sqlx::{postgres::PgListener, PgExecutor, PgPool, Postgres, Transaction};
+use tokio::{sync::watch, task::JoinSet};
+use tracing::{Instrument, Span};
 use uuid::Uuid;
 
 use crate::{
-    configuration, domain::SubscriberEmail, email_client::EmailAPIClient,
+    configuration::{self, WorkerSettings},
+    domain::{
+        html_with_subscription_links, list_unsubscribe_headers, text_with_subscription_links,
+        PreferencesToken, SubscriberEmail, UnsubscribeToken,
+    },
+    email_client::{EmailSender, OutgoingEmail, SendEmailError},
+    maintenance::run_maintenance,
+    send_rate_limiter::SendRateLimiter,
     startup::get_connection_pool,
 };
 
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    /// The sending budget is used up: try again after the given delay.
+    Throttled(Duration),
 }
 
-#[tracing::instrument(skip_all, fields(
-    newsletter_issue_id=tracing::field::Empty,
-    subscriber_email=tracing::field::Empty
-), err)]
+/// Delivery attempts after the first one before a task is given up on.
+pub const MAX_RETRIES: i16 = 5;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[tracing::instrument(skip_all, fields(n_tasks=tracing::field::Empty), err)]
 pub async fn try_execute_delivery(
     pool: &PgPool,
-    email_client: &EmailAPIClient,
+    email_client: &dyn EmailSender,
+    rate_limiter: &SendRateLimiter,
+    batch_size: usize,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
+    // Sends that do not go out, early returns and errors included, go back
+    // to the budget when the permit is dropped.
+    let mut permit = match rate_limiter.try_acquire_permit(batch_size.max(1)) {
+        Ok(permit) => permit,
+        Err(wait) => return Ok(ExecutionOutcome::Throttled(wait)),
+    };
+    enqueue_due_issues(pool, None).await?;
+    let (mut transaction, tasks) = dequeue_tasks(pool, permit.available() as i64).await?;
 
-    if task.is_none() {
+    if tasks.is_empty() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
 
-    let (transaction, issue_id, email) = task.unwrap();
-
-    Span::current().record("newsletter_issue_id", &display(issue_id));
-    Span::current().record("subscriber_email", &display(&email));
-
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_newsletter_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
-                .await
-            {
-                tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to deliver issue to a confirmed subscriber. Skipping.");
+    Span::current().record("n_tasks", tasks.len());
+
+    let issue_ids: Vec<Uuid> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    let issues = get_newsletter_issues(pool, &issue_ids).await?;
+    let emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    let subscribers = get_confirmed_subscribers(pool, &emails).await?;
+
+    let mut outcomes: Vec<Option<(DeliveryOutcome, Option<String>)>> =
+        tasks.iter().map(|_| None).collect();
+    let mut batch = Vec::new();
+    let mut batch_task_indexes = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        let _span = tracing::info_span!(
+            "Preparing delivery",
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email
+        )
+        .entered();
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => match subscribers.get(email.as_ref()) {
+                Some(subscriber) if subscriber.paused => {
+                    tracing::info!("Skipping a subscriber that paused delivery.");
+                    outcomes[i] = Some((DeliveryOutcome::SkippedPaused, None));
+                }
+                Some(subscriber) => {
+                    let issue = issues
+                        .get(&task.newsletter_issue_id)
+                        .context("The newsletter issue of a queued delivery is missing.")?;
+                    batch.push(newsletter_email(
+                        issue,
+                        email,
+                        subscriber.id,
+                        base_url,
+                        hmac_secret,
+                    ));
+                    batch_task_indexes.push(i);
+                }
+                None => {
+                    tracing::info!("Skipping a subscriber that is no longer confirmed.");
+                    outcomes[i] = Some((DeliveryOutcome::SkippedNotConfirmed, None));
+                }
+            },
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, error.message = %e, "Skipping a confirmed subscriber. Stored contact details invalid.");
+                outcomes[i] = Some((DeliveryOutcome::SkippedInvalidEmail, Some(e)));
             }
         }
-        Err(e) => {
-            tracing::error!(error.cause_chain = ?e, error.message = %e, "Skipping a confirmed subscriber. Stored contact details invalid.");
+    }
+
+    permit.use_sends(batch.len());
+    drop(permit);
+
+    let results = email_client.send_batch(&batch).await;
+    // Honour the longest delay the provider asked for, across the batch.
+    let provider_pause = results
+        .iter()
+        .filter_map(|result| match result {
+            Err(SendEmailError::RateLimited { retry_after }) => {
+                Some(retry_after.unwrap_or(BASE_RETRY_DELAY))
+            }
+            _ => None,
+        })
+        .max();
+    if let Some(pause) = provider_pause {
+        tracing::warn!(
+            "The email provider is rate limiting us. Pausing for {:?}.",
+            pause
+        );
+        rate_limiter.pause_for(pause);
+    }
+
+    for (i, result) in batch_task_indexes.into_iter().zip(results) {
+        let task = &tasks[i];
+        let _span = tracing::info_span!(
+            "Delivery result",
+            newsletter_issue_id = %task.newsletter_issue_id,
+            subscriber_email = %task.subscriber_email
+        )
+        .entered();
+        outcomes[i] = Some(match result {
+            Ok(()) => (DeliveryOutcome::Sent, None),
+            // Being throttled says nothing about the message itself: it does
+            // not count as a retry.
+            Err(e @ SendEmailError::RateLimited { .. }) => {
+                (DeliveryOutcome::RateLimited, Some(e.to_string()))
+            }
+            Err(e) if e.is_transient() && task.n_retries < MAX_RETRIES => {
+                tracing::warn!(error.cause_chain = ?e, error.message = %e, "Failed to deliver issue to a confirmed subscriber. Retrying later.");
+                (DeliveryOutcome::Retrying, Some(e.to_string()))
+            }
+            Err(e) => {
+                tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to deliver issue to a confirmed subscriber. Giving up.");
+                (DeliveryOutcome::Failed, Some(e.to_string()))
+            }
+        });
+    }
+
+    for (task, outcome) in tasks.iter().zip(outcomes) {
+        let (outcome, error_message) = outcome.context("A dequeued task has no outcome.")?;
+        log_delivery(&mut transaction, task, outcome, error_message.as_deref()).await?;
+        match outcome {
+            DeliveryOutcome::Retrying => reschedule_task(&mut transaction, task).await?,
+            DeliveryOutcome::RateLimited => {
+                let delay = provider_pause.unwrap_or(BASE_RETRY_DELAY);
+                postpone_task(&mut transaction, task, delay).await?
+            }
+            DeliveryOutcome::Failed => {
+                let last_error = error_message.as_deref().unwrap_or_default();
+                move_task_to_failures(&mut transaction, task, last_error).await?
+            }
+            _ => delete_task(&mut transaction, task).await?,
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
+    transaction.commit().await?;
 
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
-struct NewsletterIssueRecord {
-    title: String,
-    text_content: String,
-    html_content: String,
+/// What happened to a single delivery attempt, as recorded in
+/// `issue_delivery_log`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Sent,
+    Retrying,
+    RateLimited,
+    Failed,
+    SkippedNotConfirmed,
+    SkippedInvalidEmail,
+    SkippedPaused,
 }
 
-async fn get_newsletter_issue(
+impl DeliveryOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Sent => "sent",
+            DeliveryOutcome::Retrying => "retrying",
+            DeliveryOutcome::RateLimited => "rate_limited",
+            DeliveryOutcome::Failed => "failed",
+            DeliveryOutcome::SkippedNotConfirmed => "skipped_not_confirmed",
+            DeliveryOutcome::SkippedInvalidEmail => "skipped_invalid_email",
+            DeliveryOutcome::SkippedPaused => "skipped_paused",
+        }
+    }
+}
+
+/// Exponential backoff with up to 50% of random jitter, so that tasks failed
+/// by the same outage do not all retry at once.
+fn retry_delay(n_retries: i16) -> Duration {
+    let delay = BASE_RETRY_DELAY * 2u32.pow(n_retries.clamp(0, 10) as u32);
+    let jitter = rand::thread_rng().gen_range(0.0..=0.5);
+    delay.mul_f64(1.0 + jitter)
+}
+
+pub(crate) struct NewsletterIssueRecord {
+    pub(crate) newsletter_issue_id: Uuid,
+    pub(crate) title: String,
+    pub(crate) text_content: String,
+    pub(crate) html_content: String,
+}
+
+async fn get_newsletter_issues(
     pool: &PgPool,
-    issue_id: Uuid,
-) -> Result<NewsletterIssueRecord, anyhow::Error> {
-    let issue = sqlx::query_as!(
+    issue_ids: &[Uuid],
+) -> Result<HashMap<Uuid, NewsletterIssueRecord>, anyhow::Error> {
+    let issues = sqlx::query_as!(
         NewsletterIssueRecord,
         r#"
-    SELECT title, text_content, html_content
+    SELECT newsletter_issue_id, title, text_content, html_content
     FROM newsletter_issues
-    WHERE newsletter_issue_id = $1
+    WHERE newsletter_issue_id = ANY($1)
     "#,
-        issue_id
+        issue_ids
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
-    Ok(issue)
+    Ok(issues
+        .into_iter()
+        .map(|issue| (issue.newsletter_issue_id, issue))
+        .collect())
+}
+
+struct ConfirmedSubscriber {
+    id: Uuid,
+    paused: bool,
+}
+
+/// Subscribers can leave, or pause delivery, between enqueueing and delivery:
+/// only the ones still confirmed and not paused get the issue.
+async fn get_confirmed_subscribers(
+    pool: &PgPool,
+    emails: &[String],
+) -> Result<HashMap<String, ConfirmedSubscriber>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+    SELECT id, email, paused_until IS NOT NULL AND paused_until > now() AS "paused!"
+    FROM subscriptions
+    WHERE email = ANY($1) AND status = 'confirmed'
+    "#,
+        emails
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let subscriber = ConfirmedSubscriber {
+                id: r.id,
+                paused: r.paused,
+            };
+            (r.email, subscriber)
+        })
+        .collect())
+}
+
+/// The email a subscriber gets for an issue, with their preferences and
+/// unsubscribe links in the footer and the List-Unsubscribe headers.
+pub(crate) fn newsletter_email(
+    issue: &NewsletterIssueRecord,
+    recipient: SubscriberEmail,
+    subscriber_id: Uuid,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> OutgoingEmail {
+    let token = UnsubscribeToken::new(subscriber_id, hmac_secret);
+    let unsubscribe_link = token.unsubscribe_link(base_url);
+    let preferences_link = PreferencesToken::new(subscriber_id, recipient.as_ref(), hmac_secret)
+        .preferences_link(base_url);
+    OutgoingEmail {
+        recipient,
+        subject: issue.title.clone(),
+        html_content: html_with_subscription_links(
+            &issue.html_content,
+            &preferences_link,
+            &unsubscribe_link,
+        ),
+        text_content: text_with_subscription_links(
+            &issue.text_content,
+            &preferences_link,
+            &unsubscribe_link,
+        ),
+        headers: list_unsubscribe_headers(&token.one_click_unsubscribe_link(base_url)),
+    }
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// Locks up to `batch_size` due tasks. The locks are held by the returned
+/// transaction until every task has been resolved.
 #[tracing::instrument(skip(pool))]
-async fn dequeue_task(
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    batch_size: i64,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
 
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
         r#"
-    SELECT newsletter_issue_id, subscriber_email
-    FROM issue_delivery_queue
-    FOR UPDATE
+    SELECT q.newsletter_issue_id, q.subscriber_email, q.n_retries
+    FROM issue_delivery_queue q
+    JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+    WHERE
+        q.execute_after <= now() AND
+        (i.scheduled_for IS NULL OR i.scheduled_for <= now())
+    FOR UPDATE OF q
     SKIP LOCKED
-    LIMIT 1
-    "#
+    LIMIT $1
+    "#,
+        batch_size
     )
-    .fetch_optional(&mut *transaction)
+    .fetch_all(&mut *transaction)
     .await?;
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip(transaction, task))]
+async fn log_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
+    error_message: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_log (
+            newsletter_issue_id,
+            subscriber_email,
+            outcome,
+            n_retries,
+            error_message,
+            logged_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+    "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome.as_str(),
+        task.n_retries,
+        error_message
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
 }
 
 async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
@@ -124,36 +377,452 @@ async fn delete_task(
             newsletter_issue_id = $1 AND
             subscriber_email = $2
     "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
     )
-    .execute(&mut *transaction)
+    .execute(&mut **transaction)
     .await?;
-    transaction.commit().await?;
     Ok(())
 }
 
-async fn worker_loop(pool: PgPool, email_client: EmailAPIClient) -> Result<(), anyhow::Error> {
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let delay_milliseconds = retry_delay(task.n_retries).as_millis() as i64;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + $3::bigint * interval '1 millisecond'
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+    "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay_milliseconds
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Puts the task back in the queue for later, without using up a retry.
+#[tracing::instrument(skip_all)]
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = now() + $3::bigint * interval '1 millisecond'
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+    "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_millis() as i64
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// Dead-letters the task: admins can inspect and requeue it from the
+/// delivery failures page.
+#[tracing::instrument(skip_all)]
+async fn move_task_to_failures(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+    "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries,
+        last_error
+    )
+    .execute(&mut **transaction)
+    .await?;
+    delete_task(transaction, task).await
+}
+
+pub struct QueuedDelivery {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub subscriber_email: String,
+    pub n_retries: i16,
+    pub due_at: DateTime<Utc>,
+}
+
+/// The next `limit` deliveries, in the order they become due.
+pub async fn list_queued_deliveries(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<QueuedDelivery>, anyhow::Error> {
+    let deliveries = sqlx::query_as!(
+        QueuedDelivery,
+        r#"
+    SELECT
+        q.newsletter_issue_id,
+        i.title,
+        q.subscriber_email,
+        q.n_retries,
+        GREATEST(q.execute_after, i.scheduled_for) AS "due_at!"
+    FROM issue_delivery_queue q
+    JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+    ORDER BY 5, q.subscriber_email
+    LIMIT $1
+    "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the queued deliveries.")?;
+    Ok(deliveries)
+}
+
+/// Moves every failed delivery, or only those of one issue, back to the
+/// queue with a fresh retry budget. Returns how many were requeued.
+#[tracing::instrument(name = "Requeue delivery failures", skip(pool))]
+pub async fn requeue_delivery_failures(
+    pool: &PgPool,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start requeue transaction.")?;
+    let requeued = sqlx::query!(
+        r#"
+    WITH failures AS (
+        DELETE FROM issue_delivery_failures
+        WHERE $1::uuid IS NULL OR newsletter_issue_id = $1
+        RETURNING newsletter_issue_id, subscriber_email
+    )
+    INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+    SELECT newsletter_issue_id, subscriber_email FROM failures
+    ON CONFLICT DO NOTHING
+    "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to move the delivery failures back to the queue.")?
+    .rows_affected();
+    notify_workers(&mut *transaction)
+        .await
+        .context("Failed to notify the delivery workers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit requeue transaction.")?;
+    Ok(requeued)
+}
+
+/// Creates the delivery tasks of the published issues that are due but have
+/// no recipients yet: all of them, or only `newsletter_issue_id`. Returns how
+/// many tasks were created.
+///
+/// Marking the issue as enqueued in the same statement makes concurrent
+/// workers enqueue it only once.
+#[tracing::instrument(skip(executor))]
+pub async fn enqueue_due_issues<'e>(
+    executor: impl PgExecutor<'e>,
+    newsletter_issue_id: Option<Uuid>,
+) -> Result<u64, sqlx::Error> {
+    let enqueued = sqlx::query!(
+        r#"
+    WITH due AS (
+        UPDATE newsletter_issues
+        SET enqueued_at = now()
+        WHERE
+            ($1::uuid IS NULL OR newsletter_issue_id = $1) AND
+            status = 'published' AND
+            enqueued_at IS NULL AND
+            cancelled_at IS NULL AND
+            (scheduled_for IS NULL OR scheduled_for <= now())
+        RETURNING newsletter_issue_id
+    )
+    INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+    SELECT due.newsletter_issue_id, s.email
+    FROM due
+    CROSS JOIN subscriptions s
+    WHERE s.status = 'confirmed'
+    ON CONFLICT DO NOTHING
+    "#,
+        newsletter_issue_id
+    )
+    .execute(executor)
+    .await?
+    .rows_affected();
+    Ok(enqueued)
+}
+
+/// Postgres channel notified whenever delivery tasks are enqueued.
+pub const NEW_TASKS_CHANNEL: &str = "issue_delivery_queue";
+
+/// Wakes up idle workers. Inside a transaction, Postgres only delivers the
+/// notification once it commits, by which time the new tasks are visible.
+pub async fn notify_workers<'e>(executor: impl PgExecutor<'e>) -> Result<(), sqlx::Error> {
+    sqlx::query("SELECT pg_notify($1, '')")
+        .bind(NEW_TASKS_CHANNEL)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// How long until the earliest task waiting on a retry delay, or the
+/// earliest scheduled issue still to be enqueued, becomes due, if there is
+/// any.
+async fn next_task_due_in(pool: &PgPool) -> Result<Option<Duration>, anyhow::Error> {
+    let r = sqlx::query!(
+        r#"
+    SELECT min(due_at) AS next_due_at
+    FROM (
+        SELECT GREATEST(q.execute_after, i.scheduled_for) AS due_at
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+        UNION ALL
+        SELECT scheduled_for
+        FROM newsletter_issues
+        WHERE status = 'published' AND enqueued_at IS NULL AND cancelled_at IS NULL
+    ) due
+    "#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(r.next_due_at
+        .map(|next_due_at| (next_due_at - Utc::now()).to_std().unwrap_or_default()))
+}
+
+/// Keeps a `LISTEN` connection open and forwards every notification to the
+/// workers. The value sent tells them whether they can rely on it or have
+/// to fall back to polling.
+async fn listen_for_new_tasks(
+    pool: PgPool,
+    wake_up: watch::Sender<bool>,
+    settings: WorkerSettings,
+) {
     loop {
-        let task_outcome = try_execute_delivery(&pool, &email_client).await;
-        match task_outcome {
-            Ok(ExecutionOutcome::EmptyQueue) => {
-                tokio::time::sleep(Duration::from_secs(10)).await;
+        match PgListener::connect_with(&pool).await {
+            Ok(mut listener) => match listener.listen(NEW_TASKS_CHANNEL).await {
+                Ok(()) => {
+                    // Tasks may have been enqueued while we were not listening.
+                    wake_up.send_replace(true);
+                    // `try_recv` yields `None` when the connection drops.
+                    while let Ok(Some(_)) = listener.try_recv().await {
+                        wake_up.send_replace(true);
+                    }
+                    tracing::warn!("Lost the connection listening for new delivery tasks.");
+                }
+                Err(e) => {
+                    tracing::warn!(error.cause_chain = ?e, error.message = %e, "Failed to listen for new delivery tasks.");
+                }
+            },
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, error.message = %e, "Failed to connect to listen for new delivery tasks.");
             }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        wake_up.send_replace(false);
+        tokio::time::sleep(settings.error_backoff()).await;
+    }
+}
+
+/// Everything a worker needs to deliver issues, shared by the whole pool.
+struct DeliveryContext {
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    rate_limiter: Arc<SendRateLimiter>,
+    base_url: String,
+    hmac_secret: Secret<String>,
+}
+
+async fn worker_loop(
+    context: Arc<DeliveryContext>,
+    settings: WorkerSettings,
+    mut wake_up: watch::Receiver<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let DeliveryContext {
+        pool,
+        email_client,
+        rate_limiter,
+        base_url,
+        hmac_secret,
+    } = context.as_ref();
+    while !*shutdown.borrow() {
+        // Anything enqueued from now on must wake us up from the pause below.
+        let listening = *wake_up.borrow_and_update();
+        let task_outcome = try_execute_delivery(
+            pool,
+            email_client.as_ref(),
+            rate_limiter,
+            settings.delivery_batch_size,
+            base_url,
+            hmac_secret,
+        )
+        .await;
+        let pause = match task_outcome {
+            Ok(ExecutionOutcome::TaskCompleted) => continue,
+            Ok(ExecutionOutcome::Throttled(wait)) => Some(wait),
+            // A task already due but not dequeued is locked by another
+            // worker: poll in case it gets released instead of delivered.
+            Ok(ExecutionOutcome::EmptyQueue) if listening => match next_task_due_in(pool).await {
+                Ok(Some(due_in)) if due_in.is_zero() => Some(settings.idle_poll_interval()),
+                Ok(due_in) => due_in,
+                Err(_) => Some(settings.idle_poll_interval()),
+            },
+            Ok(ExecutionOutcome::EmptyQueue) => Some(settings.idle_poll_interval()),
+            Err(_) => Some(settings.error_backoff()),
+        };
+        let sleep = async {
+            match pause {
+                Some(pause) => tokio::time::sleep(pause).await,
+                None => std::future::pending().await,
             }
+        };
+        tokio::select! {
+            _ = sleep => {}
+            _ = wake_up.changed() => {}
+            _ = shutdown.changed() => {}
         }
     }
+    tracing::info!("Delivery worker stopped.");
+}
+
+/// Completes on SIGTERM or Ctrl+C.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for Ctrl+C.");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM.")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
+/// `rate_limiter` is shared with the API when both run in the same process,
+/// so that test sends come out of the same budget.
 pub async fn run_worker_until_stopped(
     configuration: configuration::Settings,
+    rate_limiter: Arc<SendRateLimiter>,
+) -> Result<(), anyhow::Error> {
+    run_workers_sharing_until(configuration, rate_limiter, shutdown_signal()).await
+}
+
+/// Runs `worker.concurrency` workers sharing the delivery queue until
+/// `shutdown` completes. Workers then stop dequeuing, but the deliveries
+/// they are in the middle of are allowed to finish.
+pub async fn run_workers_until(
+    configuration: configuration::Settings,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), anyhow::Error> {
+    let rate_limiter = Arc::new(SendRateLimiter::new(&configuration.worker));
+    run_workers_sharing_until(configuration, rate_limiter, shutdown).await
+}
+
+/// Same as `run_workers_until`, taking the sends out of `rate_limiter`.
+pub async fn run_workers_sharing_until(
+    configuration: configuration::Settings,
+    rate_limiter: Arc<SendRateLimiter>,
+    shutdown: impl Future<Output = ()>,
 ) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
 
-    let email_client = configuration.email_client.client();
+    let context = Arc::new(DeliveryContext {
+        pool: connection_pool.clone(),
+        email_client: configuration.email_client.email_sender()?,
+        rate_limiter,
+        base_url: configuration.application.base_url,
+        hmac_secret: configuration.application.hmac_secret,
+    });
+
+    let (wake_up_sender, wake_up) = watch::channel(false);
+    // Dropping the set stops the listener and the maintenance task, whichever
+    // way we return.
+    let mut background = JoinSet::new();
+    background.spawn(listen_for_new_tasks(
+        connection_pool.clone(),
+        wake_up_sender,
+        configuration.worker.clone(),
+    ));
+    background.spawn(run_maintenance(connection_pool, configuration.maintenance));
 
-    worker_loop(connection_pool, email_client).await
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    let mut workers = JoinSet::new();
+    for worker_id in 0..configuration.worker.concurrency.max(1) {
+        let worker = worker_loop(
+            context.clone(),
+            configuration.worker.clone(),
+            wake_up.clone(),
+            shutdown_receiver.clone(),
+        );
+        workers.spawn(worker.instrument(tracing::info_span!("Delivery worker", worker_id)));
+    }
+
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(outcome) = workers.join_next() => {
+                outcome.context("A delivery worker crashed.")?;
+            }
+        }
+    }
+
+    tracing::info!("Shutting down: waiting for in-flight deliveries to complete.");
+    shutdown_sender.send(true)?;
+    while let Some(outcome) = workers.join_next().await {
+        outcome.context("A delivery worker crashed.")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, BASE_RETRY_DELAY};
+
+    #[test]
+    fn retry_delay_grows_exponentially_within_jitter_bounds() {
+        for n_retries in 0..5 {
+            let base = BASE_RETRY_DELAY * 2u32.pow(n_retries as u32);
+            let delay = retry_delay(n_retries);
+            assert!(delay >= base, "{:?} < {:?}", delay, base);
+            assert!(delay <= base.mul_f64(1.5), "{:?} > {:?}", delay, base);
+        }
+    }
 }
@@ -1,11 +1,15 @@
+pub mod audit;
 pub mod authentication;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod maintenance;
 pub mod routes;
+pub mod send_rate_limiter;
 pub mod session_state;
 pub mod startup;
+pub mod subscription_throttle;
 pub mod telemetry;
 pub mod utils;
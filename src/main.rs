@@ -1,32 +1,290 @@
 use std::fmt::{Debug, Display};
+use std::sync::Arc;
 
-use tokio::task::JoinError;
-use zero2prod::configuration::get_configuration;
-use zero2prod::issue_delivery_worker::run_worker_until_stopped;
-use zero2prod::startup::Application;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::{ExposeSecret, Secret};
+use tokio::task::{JoinError, JoinHandle};
+use uuid::Uuid;
+use zero2prod::authentication::{
+    change_password, create_user, disable_user, get_user_id, has_active_owner, issue_setup_token,
+    Role,
+};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::issue_delivery_worker::{
+    list_queued_deliveries, requeue_delivery_failures, run_worker_until_stopped,
+};
+use zero2prod::maintenance::purge_stale_rows;
+use zero2prod::send_rate_limiter::SendRateLimiter;
+use zero2prod::startup::{get_connection_pool, migrate_database, Application, WorkerHealthCheck};
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
+#[derive(Parser)]
+#[command(about = "Mailing list API and newsletter delivery workers")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Roles can be deployed separately, so that API replicas and delivery
+/// workers scale independently.
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the HTTP API only.
+    Serve,
+    /// Run the newsletter delivery workers only, with their own health check.
+    Worker,
+    /// Run both the HTTP API and the delivery workers (default).
+    All,
+    /// Apply the pending database migrations.
+    Migrate,
+    /// Manage the admin users.
+    #[command(subcommand)]
+    Users(UsersCommand),
+    /// Inspect the delivery queue.
+    #[command(subcommand)]
+    Queue(QueueCommand),
+    /// Purge stale rows once. Worker processes also do it periodically.
+    Cleanup,
+}
+
+/// Passwords are read from standard input, so that they do not end up in the
+/// shell history.
+#[derive(Subcommand)]
+enum UsersCommand {
+    /// Create an admin user.
+    Create {
+        username: String,
+        /// One of viewer, editor or owner.
+        #[arg(long, default_value = "owner", value_parser = Role::parse)]
+        role: Role,
+    },
+    /// Prevent a user from logging in and end their sessions.
+    Disable { username: String },
+    /// Set a new password for a user.
+    ResetPassword { username: String },
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// List the deliveries waiting in the queue, earliest due first.
+    List {
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+    },
+    /// Move failed deliveries back to the queue with a fresh retry budget.
+    Requeue {
+        /// Only requeue the failed deliveries of this newsletter issue.
+        #[arg(long)]
+        issue_id: Option<Uuid>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::All);
+
     // Setting up telemetry!
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+    // Admin commands print their results on stdout: keep the logs out of it.
+    match command {
+        Command::Serve | Command::Worker | Command::All => {
+            init_subscriber(get_subscriber(
+                "zero2prod".into(),
+                "info".into(),
+                std::io::stdout,
+            ));
+        }
+        _ => {
+            init_subscriber(get_subscriber(
+                "zero2prod".into(),
+                "warn".into(),
+                std::io::stderr,
+            ));
+        }
+    }
 
     let configuration = get_configuration().expect("Failed to read configuration.");
 
-    let app = Application::build(configuration.clone()).await?;
+    match command {
+        Command::Serve => {
+            let app = Application::build(configuration.clone()).await?;
+            offer_setup_link(&configuration).await?;
+            report_exit("API", tokio::spawn(app.run_until_stopped()).await);
+        }
+        Command::Worker => {
+            let health_check = WorkerHealthCheck::build(&configuration)?;
+            let rate_limiter = Arc::new(SendRateLimiter::new(&configuration.worker));
+            run_together(
+                (
+                    "Worker health check",
+                    tokio::spawn(health_check.run_until_stopped()),
+                ),
+                (
+                    "Newsletter Issue worker",
+                    tokio::spawn(run_worker_until_stopped(configuration, rate_limiter)),
+                ),
+            )
+            .await;
+        }
+        Command::All => {
+            let app = Application::build(configuration.clone()).await?;
+            let rate_limiter = app.send_rate_limiter();
+            run_together(
+                ("API", tokio::spawn(app.run_until_stopped())),
+                (
+                    "Newsletter Issue worker",
+                    tokio::spawn(run_worker_until_stopped(configuration, rate_limiter)),
+                ),
+            )
+            .await;
+        }
+        Command::Migrate => {
+            let pool = get_connection_pool(&configuration.database);
+            migrate_database(&pool)
+                .await
+                .context("Failed to migrate the database.")?;
+            println!("The database is up to date.");
+        }
+        Command::Users(command) => run_users_command(command, &configuration).await?,
+        Command::Queue(command) => run_queue_command(command, &configuration).await?,
+        Command::Cleanup => {
+            let pool = get_connection_pool(&configuration.database);
+            let report = purge_stale_rows(&pool, &configuration.maintenance).await?;
+            println!(
+                "Purged {} expired confirmation tokens, {} pending subscriptions and {} idempotency keys.",
+                report.expired_tokens, report.pending_subscriptions, report.idempotency_keys
+            );
+        }
+    }
 
-    let app_task = tokio::spawn(app.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    Ok(())
+}
 
-    tokio::select! {
-        out = app_task => report_exit("API", out),
-        out = worker_task => report_exit("Newsletter Issue worker", out),
-    };
+/// Until there is an owner account, prints a one-time link to create it. The
+/// link goes to the terminal only, not to the logs.
+async fn offer_setup_link(configuration: &Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    if let Some(token) = issue_setup_token(&pool).await? {
+        eprintln!(
+            "There is no owner account yet: create one at {}/setup?token={}",
+            configuration.application.base_url,
+            urlencoding::encode(token.expose_secret())
+        );
+    } else if !has_active_owner(&pool).await? {
+        tracing::warn!(
+            "There is no owner account yet: use the setup link printed when it was issued, \
+            or `zero2prod users create`."
+        );
+    }
+    Ok(())
+}
+
+async fn run_users_command(
+    command: UsersCommand,
+    configuration: &Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    match command {
+        UsersCommand::Create { username, role } => {
+            let password = read_password()?;
+            let user_id = create_user(&username, password, role, &pool).await?;
+            println!("Created {} '{}' ({}).", role, username, user_id);
+        }
+        UsersCommand::Disable { username } => {
+            let user_id = find_user(&username, &pool).await?;
+            if disable_user(user_id, &pool).await? {
+                println!("Disabled user '{}'.", username);
+            } else {
+                println!("User '{}' was already disabled.", username);
+            }
+        }
+        UsersCommand::ResetPassword { username } => {
+            let user_id = find_user(&username, &pool).await?;
+            let password = read_password()?;
+            change_password(user_id, password, &pool).await?;
+            println!("Changed the password of user '{}'.", username);
+        }
+    }
+    Ok(())
+}
 
+async fn run_queue_command(
+    command: QueueCommand,
+    configuration: &Settings,
+) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&configuration.database);
+    match command {
+        QueueCommand::List { limit } => {
+            let deliveries = list_queued_deliveries(&pool, limit).await?;
+            if deliveries.is_empty() {
+                println!("The delivery queue is empty.");
+            }
+            for d in deliveries {
+                println!(
+                    "{}\t{}\t{}\t{} retries\t\"{}\"",
+                    d.due_at.to_rfc3339(),
+                    d.newsletter_issue_id,
+                    d.subscriber_email,
+                    d.n_retries,
+                    d.title
+                );
+            }
+        }
+        QueueCommand::Requeue { issue_id } => {
+            let requeued = requeue_delivery_failures(&pool, issue_id).await?;
+            println!("Requeued {} failed deliveries.", requeued);
+        }
+    }
     Ok(())
 }
 
+async fn find_user(username: &str, pool: &sqlx::PgPool) -> Result<Uuid, anyhow::Error> {
+    get_user_id(username, pool)
+        .await?
+        .with_context(|| format!("There is no user named '{}'.", username))
+}
+
+fn read_password() -> Result<Secret<String>, anyhow::Error> {
+    eprintln!("Enter the password, followed by a newline:");
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .context("Failed to read the password from standard input.")?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        anyhow::bail!("The password cannot be empty.");
+    }
+    Ok(Secret::new(password))
+}
+
+type Task<E> = (&'static str, JoinHandle<Result<(), E>>);
+
+/// Both tasks stop gracefully on SIGTERM: once one of them is done, give the
+/// other a chance to finish instead of cutting it short.
+async fn run_together<A, B>((a_name, mut a_task): Task<A>, (b_name, mut b_task): Task<B>)
+where
+    A: Debug + Display,
+    B: Debug + Display,
+{
+    tokio::select! {
+        out = &mut a_task => {
+            let clean_exit = matches!(out, Ok(Ok(())));
+            report_exit(a_name, out);
+            if clean_exit {
+                report_exit(b_name, b_task.await);
+            }
+        }
+        out = &mut b_task => {
+            let clean_exit = matches!(out, Ok(Ok(())));
+            report_exit(b_name, out);
+            if clean_exit {
+                report_exit(a_name, a_task.await);
+            }
+        }
+    };
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => {
@@ -0,0 +1,133 @@
+use anyhow::Context;
+use sqlx::PgPool;
+
+use crate::configuration::MaintenanceSettings;
+
+/// Rows deleted by a single `purge_stale_rows` run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+    pub expired_tokens: u64,
+    pub pending_subscriptions: u64,
+    pub idempotency_keys: u64,
+}
+
+/// Deletes the rows that outlived their retention window: confirmation and
+/// email change tokens past their expiry, subscriptions that were never
+/// confirmed, and saved idempotent responses.
+#[tracing::instrument(name = "Purge stale rows", skip_all)]
+pub async fn purge_stale_rows(
+    pool: &PgPool,
+    settings: &MaintenanceSettings,
+) -> Result<PurgeReport, anyhow::Error> {
+    let pending_subscriptions =
+        purge_pending_subscriptions(pool, settings.pending_subscription_retention_days).await?;
+    let expired_tokens = sqlx::query!(
+        r#"
+        DELETE FROM subscriptions_tokens
+        WHERE expires_at < now() - $1::bigint * interval '1 hour'
+        "#,
+        i64::from(settings.expired_token_retention_hours)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to purge expired confirmation tokens.")?
+    .rows_affected();
+    let expired_email_change_tokens = sqlx::query!(
+        r#"
+        DELETE FROM email_change_tokens
+        WHERE expires_at < now() - $1::bigint * interval '1 hour'
+        "#,
+        i64::from(settings.expired_token_retention_hours)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to purge expired email change tokens.")?
+    .rows_affected();
+    let expired_tokens = expired_tokens + expired_email_change_tokens;
+    let idempotency_keys = sqlx::query!(
+        r#"
+        DELETE FROM idempotency
+        WHERE created_at < now() - $1::bigint * interval '1 hour'
+        "#,
+        i64::from(settings.idempotency_retention_hours)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to purge idempotency keys.")?
+    .rows_affected();
+
+    tracing::info!(
+        expired_tokens,
+        pending_subscriptions,
+        idempotency_keys,
+        "Purged stale rows."
+    );
+    Ok(PurgeReport {
+        expired_tokens,
+        pending_subscriptions,
+        idempotency_keys,
+    })
+}
+
+/// Subscriptions still pending confirmation with no confirmation email sent
+/// within the retention window, along with their tokens.
+async fn purge_pending_subscriptions(
+    pool: &PgPool,
+    retention_days: u32,
+) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("Failed to begin transaction.")?;
+    // Locking the rows keeps a concurrent subscription request from adding
+    // a token to a subscription that is about to go.
+    let subscriber_ids: Vec<uuid::Uuid> = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM subscriptions
+        WHERE status = 'pending_confirmation'
+            AND subscribed_at < now() - $1::bigint * interval '1 day'
+            AND NOT EXISTS (
+                SELECT 1
+                FROM subscriptions_tokens
+                WHERE subscriber_id = subscriptions.id
+                    AND created_at >= now() - $1::bigint * interval '1 day'
+            )
+        FOR UPDATE
+        "#,
+        i64::from(retention_days)
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to find stale pending subscriptions.")?;
+    sqlx::query!(
+        r#"DELETE FROM subscriptions_tokens WHERE subscriber_id = ANY($1)"#,
+        &subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete the tokens of stale pending subscriptions.")?;
+    let deleted_rows = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+        &subscriber_ids
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to delete stale pending subscriptions.")?
+    .rows_affected();
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction.")?;
+    Ok(deleted_rows)
+}
+
+/// Purges stale rows every `settings.interval()`, starting right away.
+/// Failures are logged: the next run tries again.
+pub async fn run_maintenance(pool: PgPool, settings: MaintenanceSettings) {
+    let mut interval = tokio::time::interval(settings.interval());
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = purge_stale_rows(&pool, &settings).await {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to purge stale rows.");
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{list_api_tokens, ApiScope, UserId},
+    utils::e500,
+};
+
+/// Lists the API tokens of the logged in user, with a form to create one.
+#[tracing::instrument(name = "API tokens page", skip(pool, flash_messages))]
+pub async fn api_tokens_page(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let tokens = list_api_tokens(**user_id, &pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for token in &tokens {
+        let scopes: Vec<&str> = token.scopes.iter().map(|s| s.as_str()).collect();
+        let actions_html = if token.revoked {
+            "revoked".to_string()
+        } else {
+            format!(
+                r#"<form action="/admin/api-tokens/{}/revoke" method="post">
+                        <button type="submit">Revoke</button>
+                    </form>"#,
+                token.api_token_id
+            )
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{name}</td>
+                <td>{scopes}</td>
+                <td>{created_at}</td>
+                <td>{last_used_at}</td>
+                <td>
+                    {actions_html}
+                </td>
+            </tr>"#,
+            name = htmlescape::encode_minimal(&token.name),
+            scopes = scopes.join(", "),
+            created_at = token.created_at.to_rfc3339(),
+            last_used_at = token
+                .last_used_at
+                .map(|t| t.to_rfc3339())
+                .unwrap_or_else(|| "never".into()),
+        )
+        .unwrap();
+    }
+
+    let mut scopes_html = String::new();
+    for scope in ApiScope::ALL {
+        writeln!(
+            scopes_html,
+            r#"<label><input type="checkbox" name="scope" value="{scope}" checked> {scope}</label>"#
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>API tokens</title>
+    </head>
+    <body>
+        <h1>API tokens</h1>
+        {msg_html}
+        <p>Send them as <code>Authorization: Bearer &lt;token&gt;</code> to the <code>/newsletters</code> API.</p>
+        <table>
+            <tr>
+                <th>Name</th>
+                <th>Scopes</th>
+                <th>Created</th>
+                <th>Last used</th>
+                <th></th>
+            </tr>
+            {rows_html}
+        </table>
+        <h2>Create a token</h2>
+        <form action="/admin/api-tokens" method="post">
+            <label>
+                Name
+                <input type="text" placeholder="What the token is for" name="name">
+            </label>
+            <br>
+            {scopes_html}
+            <br>
+            <button type="submit">Create token</button>
+        </form>
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,131 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditSubject},
+    authentication::{create_api_token, revoke_api_token, ApiScope, UserId},
+    routes::get_username,
+    utils::{client_ip, e400, e500, see_other},
+};
+
+fn api_tokens_page() -> HttpResponse {
+    see_other("/admin/api-tokens")
+}
+
+async fn audit_api_token_change(
+    event: &str,
+    details: &str,
+    user_id: &UserId,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let username = get_username(**user_id, pool).await?;
+    record_audit_event(
+        pool,
+        event,
+        AuditSubject {
+            username: Some(&username),
+            client_ip: Some(&client_ip(request)),
+        },
+        details,
+    )
+    .await
+}
+
+/// The form repeats the `scope` field once per checked box, which a struct
+/// cannot capture: it is read as a list of pairs instead.
+#[tracing::instrument(name = "Create API token", skip(form, pool, request))]
+pub async fn create_api_token_submission(
+    form: web::Form<Vec<(String, String)>>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut name = "";
+    let mut scopes = Vec::new();
+    for (key, value) in &form.0 {
+        match key.as_str() {
+            "name" => name = value.trim(),
+            "scope" => scopes.push(ApiScope::parse(value).map_err(e400)?),
+            _ => {}
+        }
+    }
+    if name.is_empty() {
+        FlashMessage::error("The token needs a name.").send();
+        return Ok(api_tokens_page());
+    }
+    if scopes.is_empty() {
+        FlashMessage::error("The token needs at least one scope.").send();
+        return Ok(api_tokens_page());
+    }
+
+    let token = create_api_token(*user_id, name, &scopes, &pool)
+        .await
+        .map_err(e500)?;
+    audit_api_token_change(
+        "api_token_created",
+        &format!("Created the API token '{}'.", name),
+        &user_id,
+        &request,
+        &pool,
+    )
+    .await
+    .map_err(e500)?;
+
+    // The token is never shown again, hence no redirect.
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>API tokens</title>
+    </head>
+    <body>
+        <h1>API tokens</h1>
+        <p>The token {name} has been created. Copy it now: it will not be shown again.</p>
+        <p><code>{token}</code></p>
+        <p>
+            <a href="/admin/api-tokens">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+            name = htmlescape::encode_minimal(name),
+            token = token.expose_secret(),
+        )))
+}
+
+#[tracing::instrument(name = "Revoke API token", skip(pool, request))]
+pub async fn revoke_api_token_submission(
+    api_token_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let api_token_id = api_token_id.into_inner();
+    if !revoke_api_token(*user_id, api_token_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The token does not exist or was already revoked.").send();
+        return Ok(api_tokens_page());
+    }
+    audit_api_token_change(
+        "api_token_revoked",
+        &format!("Revoked the API token {}.", api_token_id),
+        &user_id,
+        &request,
+        &pool,
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("The token has been revoked.").send();
+    Ok(api_tokens_page())
+}
@@ -4,7 +4,10 @@ use sqlx::PgPool;
 use tracing::field::display;
 use uuid::Uuid;
 
-use crate::{authentication::UserId, utils::e500};
+use crate::{
+    authentication::{Role, UserId},
+    utils::e500,
+};
 
 #[tracing::instrument(
     name = "Admin dashboard", 
@@ -13,12 +16,31 @@ use crate::{authentication::UserId, utils::e500};
 )]
 pub async fn admin_dashboard(
     user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     tracing::Span::current().record("user_id", &display(&user_id));
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
     tracing::Span::current().record("username", &display(&username));
+    let role = role.into_inner();
+    let send_newsletter_html = if role >= Role::Editor {
+        r#"<li>
+                    <a href="/admin/newsletters">Send a newsletter issue</a>
+                </li>
+                <li>
+                    <a href="/admin/api-tokens">Manage API tokens</a>
+                </li>"#
+    } else {
+        ""
+    };
+    let manage_users_html = if role >= Role::Owner {
+        r#"<li>
+                    <a href="/admin/users">Manage users</a>
+                </li>"#
+    } else {
+        ""
+    };
     let body = format!(
         r#"
     <!DOCTYPE html>
@@ -28,15 +50,23 @@ pub async fn admin_dashboard(
         <title>Admin dashboard</title>
         </head>
         <body>
-            <p>Welcome {username}</p>
+            <p>Welcome {username} ({role})</p>
             <p>Available action:</p>
             <ol>
                 <li>
                     <a href="/admin/password">Change password</a>
                 </li>
                 <li>
-                    <a href="/admin/newsletters">Send a newsletter issue</a>
+                    <a href="/admin/two-factor">Two-factor authentication</a>
+                </li>
+                {send_newsletter_html}
+                <li>
+                    <a href="/admin/newsletters/drafts">Manage newsletter drafts</a>
+                </li>
+                <li>
+                    <a href="/admin/delivery-failures">Review failed deliveries</a>
                 </li>
+                {manage_users_html}
                 <li>
                     <form name="logoutForm" action="/admin/logout" method="post">
                         <input type="submit" value="logout">
@@ -0,0 +1,124 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+struct DeliveryFailureRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i16,
+    last_error: String,
+    failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Lists the deliveries the worker gave up on, each with a requeue button.
+#[tracing::instrument(name = "Delivery failures page", skip(pool, flash_messages))]
+pub async fn delivery_failures(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let failures = get_delivery_failures(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for failure in &failures {
+        let issue_id = failure.newsletter_issue_id;
+        let email = htmlescape::encode_attribute(&failure.subscriber_email);
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{title}</td>
+                <td>{email}</td>
+                <td>{n_retries}</td>
+                <td>{last_error}</td>
+                <td>{failed_at}</td>
+                <td>
+                    <form action="/admin/delivery-failures/requeue" method="post">
+                        <input hidden type="text" name="newsletter_issue_id" value="{issue_id}">
+                        <input hidden type="text" name="subscriber_email" value="{email}">
+                        <button type="submit">Requeue</button>
+                    </form>
+                </td>
+            </tr>"#,
+            title = htmlescape::encode_minimal(&failure.title),
+            n_retries = failure.n_retries,
+            last_error = htmlescape::encode_minimal(&failure.last_error),
+            failed_at = failure.failed_at.to_rfc3339(),
+        )
+        .unwrap();
+    }
+    let table_html = if failures.is_empty() {
+        "<p>No failed deliveries.</p>".to_string()
+    } else {
+        format!(
+            r#"<table>
+            <tr>
+                <th>Issue</th>
+                <th>Subscriber</th>
+                <th>Retries</th>
+                <th>Last error</th>
+                <th>Failed at</th>
+                <th></th>
+            </tr>
+            {rows_html}
+        </table>"#
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Delivery failures</title>
+    </head>
+    <body>
+        <h1>Delivery failures</h1>
+        {msg_html}
+        {table_html}
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_delivery_failures(pool: &PgPool) -> Result<Vec<DeliveryFailureRecord>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        DeliveryFailureRecord,
+        r#"
+        SELECT
+            f.newsletter_issue_id,
+            i.title,
+            f.subscriber_email,
+            f.n_retries,
+            f.last_error,
+            f.failed_at
+        FROM issue_delivery_failures f
+        JOIN newsletter_issues i ON i.newsletter_issue_id = f.newsletter_issue_id
+        ORDER BY f.failed_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve delivery failures.")?;
+    Ok(failures)
+}
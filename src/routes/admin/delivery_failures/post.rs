@@ -0,0 +1,84 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::notify_workers;
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct RequeueFormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+/// Moves a failed delivery back to the queue with a fresh retry budget.
+#[tracing::instrument(name = "Requeue delivery failure", skip(pool))]
+pub async fn requeue_delivery_failure(
+    form: web::Form<RequeueFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let requeued = requeue(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .map_err(e500)?;
+
+    if requeued {
+        FlashMessage::info("The delivery has been requeued.").send();
+    } else {
+        FlashMessage::error("The delivery failure no longer exists.").send();
+    }
+    Ok(see_other("/admin/delivery-failures"))
+}
+
+async fn requeue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start requeue transaction.")?;
+    let deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_failures
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove the delivery failure.")?
+    .rows_affected();
+    if deleted_rows == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to enqueue the delivery task.")?;
+    notify_workers(&mut *transaction)
+        .await
+        .context("Failed to notify the delivery workers.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit requeue transaction.")?;
+    Ok(true)
+}
@@ -1,9 +1,17 @@
+mod api_tokens;
 mod dashboard;
+mod delivery_failures;
 mod logout;
 mod newsletters;
 mod password;
+mod two_factor;
+mod users;
 
+pub use api_tokens::*;
 pub use dashboard::*;
+pub use delivery_failures::*;
 pub use logout::log_out;
 pub use newsletters::*;
 pub use password::*;
+pub use two_factor::*;
+pub use users::*;
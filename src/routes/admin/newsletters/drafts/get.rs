@@ -0,0 +1,314 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::{domain::html_with_subscription_links, utils::e500};
+
+use super::{get_draft, DraftRecord};
+
+fn flash_messages_html(flash_messages: &IncomingFlashMessages) -> String {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    msg_html
+}
+
+/// Title and content inputs shared by the new and edit draft pages.
+fn draft_form_html(action: &str, draft: &DraftRecord) -> String {
+    format!(
+        r#"<form action="{action}" method="post">
+            <label for="title">
+                Issue Title
+            </label> <br>
+            <input
+                id="title"
+                name="title"
+                type="text"
+                value="{title}">
+            <br>
+            <label for="html_content">
+                HTML content
+            </label> <br>
+            <textarea
+                id="html_content"
+                name="html_content">{html_content}</textarea>
+            <br>
+            <label for="text_content">
+                Text content
+            </label> <br>
+            <textarea
+                id="text_content"
+                name="text_content">{text_content}</textarea>
+            <br>
+            <button type="submit">Save draft</button>
+        </form>"#,
+        title = htmlescape::encode_minimal(&draft.title),
+        html_content = htmlescape::encode_minimal(&draft.html_content),
+        text_content = htmlescape::encode_minimal(&draft.text_content),
+    )
+}
+
+struct DraftSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+}
+
+#[tracing::instrument(name = "List drafts", skip(pool, flash_messages))]
+pub async fn list_drafts(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let drafts = sqlx::query_as!(
+        DraftSummary,
+        r#"
+        SELECT newsletter_issue_id, title
+        FROM newsletter_issues
+        WHERE status = 'draft'
+        ORDER BY title
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve drafts.")
+    .map_err(e500)?;
+
+    let mut drafts_html = String::new();
+    for draft in &drafts {
+        let id = draft.newsletter_issue_id;
+        writeln!(
+            drafts_html,
+            r#"<li>
+                <a href="/admin/newsletters/drafts/{id}">{title}</a>
+                <a href="/admin/newsletters/drafts/{id}/preview">Preview</a>
+                <form action="/admin/newsletters/drafts/{id}/delete" method="post">
+                    <button type="submit">Delete</button>
+                </form>
+            </li>"#,
+            title = htmlescape::encode_minimal(&draft.title),
+        )
+        .unwrap();
+    }
+    let msg_html = flash_messages_html(&flash_messages);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Drafts</title>
+    </head>
+    <body>
+        <h1>Drafts</h1>
+        {msg_html}
+        <ul>
+            {drafts_html}
+        </ul>
+        <p>
+            <a href="/admin/newsletters/drafts/new">New draft</a>
+        </p>
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "New draft form", skip(flash_messages))]
+pub async fn new_draft_form(
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let msg_html = flash_messages_html(&flash_messages);
+    let empty_draft = DraftRecord {
+        title: String::new(),
+        text_content: String::new(),
+        html_content: String::new(),
+    };
+    let form_html = draft_form_html("/admin/newsletters/drafts", &empty_draft);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>New draft</title>
+    </head>
+    <body>
+        <h1>New draft</h1>
+        {msg_html}
+        {form_html}
+        <p>
+            <a href="/admin/newsletters/drafts">&lt;- Drafts</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+/// Edit form of a draft, with the actions to preview and publish it.
+#[tracing::instrument(name = "Edit draft form", skip(pool, flash_messages))]
+pub async fn edit_draft_form(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let msg_html = flash_messages_html(&flash_messages);
+    let form_html = draft_form_html(&format!("/admin/newsletters/drafts/{}", draft_id), &draft);
+    let idempotency_key = Uuid::new_v4().to_string();
+    let test_sends_html = test_sends_html(&pool, draft_id).await.map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Edit draft</title>
+    </head>
+    <body>
+        <h1>Edit draft</h1>
+        {msg_html}
+        {form_html}
+        <p>
+            <a href="/admin/newsletters/drafts/{draft_id}/preview">Preview</a>
+        </p>
+        <form action="/admin/newsletters/drafts/{draft_id}/test" method="post">
+            <label for="recipients">
+                Send a test to (comma separated)
+            </label>
+            <input
+                id="recipients"
+                name="recipients"
+                type="text">
+            <button type="submit">Send test</button>
+        </form>
+        {test_sends_html}
+        <form action="/admin/newsletters/drafts/{draft_id}/publish" method="post">
+            <label for="scheduled_for">
+                Send at (leave empty to send now)
+            </label>
+            <input
+                id="scheduled_for"
+                name="scheduled_for"
+                type="datetime-local">
+            <label for="utc_offset">
+                UTC offset
+            </label>
+            <input
+                id="utc_offset"
+                name="utc_offset"
+                type="text"
+                placeholder="+00:00">
+            <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+            <button type="submit">Publish</button>
+        </form>
+        <p>
+            <a href="/admin/newsletters/drafts">&lt;- Drafts</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+struct TestSendRecord {
+    recipients: Vec<String>,
+    username: String,
+    sent_at: chrono::DateTime<chrono::Utc>,
+}
+
+async fn test_sends_html(pool: &PgPool, draft_id: Uuid) -> Result<String, anyhow::Error> {
+    let test_sends = sqlx::query_as!(
+        TestSendRecord,
+        r#"
+        SELECT t.recipients, u.username, t.sent_at
+        FROM issue_test_sends t
+        JOIN users u ON u.user_id = t.sent_by
+        WHERE t.newsletter_issue_id = $1
+        ORDER BY t.sent_at DESC
+        "#,
+        draft_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve test sends.")?;
+    if test_sends.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut html = String::from("<p>Test sends:</p>\n<ul>\n");
+    for test_send in test_sends {
+        writeln!(
+            html,
+            "<li>{} by {} to {}</li>",
+            test_send.sent_at.to_rfc3339(),
+            htmlescape::encode_minimal(&test_send.username),
+            htmlescape::encode_minimal(&test_send.recipients.join(", ")),
+        )
+        .unwrap();
+    }
+    html.push_str("</ul>");
+    Ok(html)
+}
+
+/// Renders the draft the way subscribers will see it.
+#[tracing::instrument(name = "Preview draft", skip(pool))]
+pub async fn preview_draft(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Preview: {title}</title>
+    </head>
+    <body>
+        <h1>{title}</h1>
+        <h2>HTML version</h2>
+        <div id="html_preview">
+            {html_preview}
+        </div>
+        <h2>Text version</h2>
+        <pre id="text_preview">{text_preview}</pre>
+        <p>
+            <a href="/admin/newsletters/drafts/{draft_id}">&lt;- Edit draft</a>
+        </p>
+    </body>
+</html>"#,
+            title = htmlescape::encode_minimal(&draft.title),
+            html_preview = html_with_subscription_links(&draft.html_content, "#", "#"),
+            text_preview = htmlescape::encode_minimal(&draft.text_content),
+        )))
+}
@@ -0,0 +1,32 @@
+mod get;
+mod post;
+
+pub use get::*;
+pub use post::*;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct DraftRecord {
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Option<DraftRecord>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        DraftRecord,
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve draft.")?;
+    Ok(draft)
+}
@@ -0,0 +1,477 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    authentication::UserId,
+    domain::{ScheduledFor, SubscriberEmail},
+    email_client::EmailSender,
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::{newsletter_email, NewsletterIssueRecord},
+    routes::enqueue_delivery_tasks,
+    send_rate_limiter::SendRateLimiter,
+    startup::{ApplicationBaseUrl, HmacSecret},
+    utils::{e400, e500, see_other},
+};
+
+use super::{
+    super::post::{published_page, success_message},
+    get_draft, DraftRecord,
+};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct DraftFormContent {
+    title: String,
+    html_content: String,
+    text_content: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PublishDraftFormData {
+    idempotency_key: String,
+    scheduled_for: Option<String>,
+    utc_offset: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct TestSendFormData {
+    recipients: String,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct IssueTestSendFormData {
+    title: String,
+    html_content: String,
+    text_content: String,
+    recipients: String,
+}
+
+fn drafts_page() -> HttpResponse {
+    see_other("/admin/newsletters/drafts")
+}
+
+fn draft_page(draft_id: Uuid) -> HttpResponse {
+    see_other(&format!("/admin/newsletters/drafts/{}", draft_id))
+}
+
+fn draft_not_found_message() -> FlashMessage {
+    FlashMessage::error("The draft does not exist or was already published.")
+}
+
+#[tracing::instrument(name = "Create draft", skip(body, pool))]
+pub async fn create_draft(
+    body: web::Form<DraftFormContent>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft = DraftRecord {
+        title: body.0.title,
+        text_content: body.0.text_content,
+        html_content: body.0.html_content,
+    };
+    let draft_id = insert_draft(&pool, &draft).await.map_err(e500)?;
+
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(draft_page(draft_id))
+}
+
+async fn insert_draft(pool: &PgPool, draft: &DraftRecord) -> Result<Uuid, anyhow::Error> {
+    let draft_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            status
+        )
+        VALUES ($1, $2, $3, $4, 'draft')
+        "#,
+        draft_id,
+        draft.title,
+        draft.text_content,
+        draft.html_content
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store draft.")?;
+    Ok(draft_id)
+}
+
+#[tracing::instrument(name = "Update draft", skip(body, pool))]
+pub async fn update_draft(
+    draft_id: web::Path<Uuid>,
+    body: web::Form<DraftFormContent>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        body.title,
+        body.text_content,
+        body.html_content
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update draft.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated_rows == 0 {
+        draft_not_found_message().send();
+        return Ok(drafts_page());
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(draft_page(draft_id))
+}
+
+#[tracing::instrument(name = "Delete draft", skip(pool))]
+pub async fn delete_draft(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let deleted_rows = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete draft.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if deleted_rows == 0 {
+        draft_not_found_message().send();
+    } else {
+        FlashMessage::info("The draft has been deleted.").send();
+    }
+    Ok(drafts_page())
+}
+
+/// Publishes a draft with the same idempotency guarantees as the send form.
+#[tracing::instrument(
+    name = "Publish draft",
+    skip(body, pool),
+    fields(idempotency_key = body.idempotency_key)
+)]
+pub async fn publish_draft(
+    draft_id: web::Path<Uuid>,
+    body: web::Form<PublishDraftFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let user_id = user_id.into_inner();
+    let PublishDraftFormData {
+        idempotency_key,
+        scheduled_for,
+        utc_offset,
+    } = body.0;
+
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let scheduled_for =
+        ScheduledFor::parse_optional(scheduled_for.as_deref(), utc_offset.as_deref())
+            .map_err(e400)?;
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => {
+            success_message().send();
+            return Ok(saved_response);
+        }
+    };
+
+    let published_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            status = 'published',
+            published_at = now(),
+            scheduled_for = $2
+        WHERE newsletter_issue_id = $1 AND status = 'draft'
+        "#,
+        draft_id,
+        scheduled_for.map(|s| *s.as_ref())
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to publish draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if published_rows == 0 {
+        // Dropping the transaction releases the idempotency key as well.
+        draft_not_found_message().send();
+        return Ok(drafts_page());
+    }
+
+    enqueue_delivery_tasks(&mut transaction, draft_id)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
+
+    let response = published_page(draft_id, scheduled_for);
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+
+    Ok(response)
+}
+
+/// Sends the draft to a handful of addresses so editors can check the real
+/// email. Nothing is enqueued and the draft stays a draft.
+#[tracing::instrument(
+    name = "Send test issue",
+    skip(body, pool, email_client, rate_limiter, base_url, hmac_secret)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn send_test_issue(
+    draft_id: web::Path<Uuid>,
+    body: web::Form<TestSendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    rate_limiter: web::Data<SendRateLimiter>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(&pool, draft_id).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => {
+            draft_not_found_message().send();
+            return Ok(drafts_page());
+        }
+    };
+    send_test(
+        &pool,
+        email_client.get_ref(),
+        &rate_limiter,
+        &base_url,
+        &hmac_secret,
+        draft_id,
+        draft,
+        &body.recipients,
+        *user_id.into_inner(),
+    )
+    .await
+}
+
+/// Test send from the publish form: the content is saved as a draft first,
+/// so it is not lost while the editor checks the email.
+#[tracing::instrument(
+    name = "Send test issue from the publish form",
+    skip(body, pool, email_client, rate_limiter, base_url, hmac_secret)
+)]
+pub async fn send_test_issue_form_submission(
+    body: web::Form<IssueTestSendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<dyn EmailSender>,
+    rate_limiter: web::Data<SendRateLimiter>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let IssueTestSendFormData {
+        title,
+        html_content,
+        text_content,
+        recipients,
+    } = body.0;
+    let draft = DraftRecord {
+        title,
+        text_content,
+        html_content,
+    };
+    let draft_id = insert_draft(&pool, &draft).await.map_err(e500)?;
+    FlashMessage::info("The issue has been saved as a draft.").send();
+    send_test(
+        &pool,
+        email_client.get_ref(),
+        &rate_limiter,
+        &base_url,
+        &hmac_secret,
+        draft_id,
+        draft,
+        &recipients,
+        *user_id.into_inner(),
+    )
+    .await
+}
+
+/// Test issues are built like the ones subscribers get, footer and
+/// List-Unsubscribe headers included, with links for a placeholder
+/// subscriber. They take their sends from the same budget as the delivery
+/// workers.
+#[allow(clippy::too_many_arguments)]
+async fn send_test(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    rate_limiter: &SendRateLimiter,
+    base_url: &ApplicationBaseUrl,
+    hmac_secret: &HmacSecret,
+    draft_id: Uuid,
+    draft: DraftRecord,
+    recipients: &str,
+    user_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    let recipients = match parse_recipients(recipients) {
+        Ok(recipients) => recipients,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(draft_page(draft_id));
+        }
+    };
+    let mut permit = match rate_limiter.try_acquire_permit(recipients.len()) {
+        Ok(permit) if permit.available() == recipients.len() => permit,
+        _ => {
+            FlashMessage::error("Too many emails are being sent right now. Try again later.")
+                .send();
+            return Ok(draft_page(draft_id));
+        }
+    };
+    permit.use_sends(recipients.len());
+    drop(permit);
+
+    let issue = NewsletterIssueRecord {
+        newsletter_issue_id: draft_id,
+        title: format!("[Test] {}", draft.title),
+        text_content: draft.text_content,
+        html_content: draft.html_content,
+    };
+    let mut delivered = Vec::with_capacity(recipients.len());
+    for recipient in &recipients {
+        let email = newsletter_email(
+            &issue,
+            recipient.clone(),
+            Uuid::nil(),
+            &base_url.0,
+            &hmac_secret.0,
+        );
+        if let Err(e) = email_client
+            .send_email_with_headers(
+                &email.recipient,
+                &email.subject,
+                &email.html_content,
+                &email.text_content,
+                &email.headers,
+            )
+            .await
+        {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "Failed to send a test issue.");
+            FlashMessage::error(format!("Failed to send the test issue to {}.", recipient)).send();
+            break;
+        }
+        delivered.push(recipient.as_ref().to_owned());
+    }
+
+    if !delivered.is_empty() {
+        record_test_send(pool, draft_id, &delivered, user_id)
+            .await
+            .map_err(e500)?;
+    }
+    if delivered.len() == recipients.len() {
+        FlashMessage::info(format!("Test issue sent to {}.", delivered.join(", "))).send();
+    }
+    Ok(draft_page(draft_id))
+}
+
+/// Test sends are meant for a few reviewers, not for mailing a list.
+const MAX_TEST_RECIPIENTS: usize = 5;
+
+/// Recipients are separated by commas, spaces or new lines.
+fn parse_recipients(s: &str) -> Result<Vec<SubscriberEmail>, String> {
+    let recipients = s
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|r| !r.is_empty())
+        .map(|r| SubscriberEmail::parse(r.to_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if recipients.is_empty() {
+        return Err("Provide at least one address to send the test issue to.".into());
+    }
+    if recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(format!(
+            "A test issue can be sent to {} addresses at most.",
+            MAX_TEST_RECIPIENTS
+        ));
+    }
+    Ok(recipients)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn record_test_send(
+    pool: &PgPool,
+    draft_id: Uuid,
+    recipients: &[String],
+    user_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_test_sends (
+            test_send_id,
+            newsletter_issue_id,
+            recipients,
+            sent_by,
+            sent_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        draft_id,
+        recipients,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record test send.")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::{parse_recipients, MAX_TEST_RECIPIENTS};
+
+    #[test]
+    fn recipients_can_be_separated_by_commas_spaces_and_new_lines() {
+        let recipients = assert_ok!(parse_recipients(
+            "a@example.com, b@example.com\nc@example.com"
+        ));
+        assert_eq!(recipients.len(), 3);
+    }
+
+    #[test]
+    fn a_single_invalid_recipient_rejects_the_whole_list() {
+        assert_err!(parse_recipients("a@example.com, not-an-email"));
+    }
+
+    #[test]
+    fn an_empty_list_is_rejected() {
+        assert_err!(parse_recipients(" , "));
+    }
+
+    #[test]
+    fn too_many_recipients_are_rejected() {
+        let recipients = (0..=MAX_TEST_RECIPIENTS)
+            .map(|i| format!("reviewer{}@example.com", i))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_err!(parse_recipients(&recipients));
+    }
+}
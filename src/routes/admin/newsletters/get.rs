@@ -9,7 +9,12 @@ pub async fn send_newsletter_form(
     let idempotency_key = uuid::Uuid::new_v4().to_string();
     let mut error_html = String::new();
     for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(
+            error_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
     }
 
     Ok(HttpResponse::Ok()
@@ -51,8 +56,33 @@ pub async fn send_newsletter_form(
                 name="text_content">
             </textarea>
             <br>
+            <label for="scheduled_for">
+                Send at (leave empty to send now)
+            </label> <br>
+            <input
+                id="scheduled_for"
+                name="scheduled_for"
+                type="datetime-local">
+            <label for="utc_offset">
+                UTC offset
+            </label>
+            <input
+                id="utc_offset"
+                name="utc_offset"
+                type="text"
+                placeholder="+00:00">
+            <br>
             <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
             <button type="submit">Send Newsletter</button>
+            <br>
+            <label for="recipients">
+                Send a test to (comma separated)
+            </label>
+            <input
+                id="recipients"
+                name="recipients"
+                type="text">
+            <button type="submit" formaction="/admin/newsletters/test">Save as draft and send test</button>
         </form>
         <p>
             <a href="/admin/dashboard">&lt;- Back</a>
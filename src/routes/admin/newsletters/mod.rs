@@ -1,5 +1,13 @@
+mod drafts;
 mod get;
 mod post;
+mod schedule;
+mod status;
 
+pub use drafts::*;
 pub use get::*;
 pub use post::publish_issue_form_submission;
+pub(crate) use post::{enqueue_delivery_tasks, insert_newsletter_issue};
+pub use schedule::*;
+pub use status::issue_delivery_status;
+pub(crate) use status::{get_issue, get_recipient_statuses, DeliveryCounts};
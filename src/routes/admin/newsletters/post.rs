@@ -6,7 +6,9 @@ use uuid::Uuid;
 
 use crate::{
     authentication::UserId,
+    domain::ScheduledFor,
     idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    issue_delivery_worker::{enqueue_due_issues, notify_workers},
     utils::{e400, e500},
 };
 
@@ -16,9 +18,11 @@ pub struct IssueFormContent {
     html_content: String,
     text_content: String,
     idempotency_key: String,
+    scheduled_for: Option<String>,
+    utc_offset: Option<String>,
 }
 
-fn success_message() -> FlashMessage {
+pub(super) fn success_message() -> FlashMessage {
     FlashMessage::info("The newsletter issue has been accepted and emails will go out shortly!")
 }
 
@@ -37,9 +41,14 @@ pub async fn publish_issue_form_submission(
         html_content,
         text_content,
         idempotency_key,
+        scheduled_for,
+        utc_offset,
     } = body.0;
 
     let idempotency_key: IdempotencyKey = idempotency_key.clone().try_into().map_err(e400)?;
+    let scheduled_for =
+        ScheduledFor::parse_optional(scheduled_for.as_deref(), utc_offset.as_deref())
+            .map_err(e400)?;
 
     let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
         .await
@@ -52,18 +61,43 @@ pub async fn publish_issue_form_submission(
         }
     };
 
-    let issue_id = insert_newsletter_issue(&mut transaction, &title, &text_content, &html_content)
-        .await
-        .context("Failed to store newsletter issue details")
-        .map_err(e500)?;
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &text_content,
+        &html_content,
+        scheduled_for,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
 
     enqueue_delivery_tasks(&mut transaction, issue_id)
         .await
         .context("Failed to enqueue delivery tasks")
         .map_err(e500)?;
 
-    let response = HttpResponse::Ok().content_type(ContentType::html()).body(
-        r#"
+    let response = published_page(issue_id, scheduled_for);
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
+        .await
+        .map_err(e500)?;
+
+    Ok(response)
+}
+
+/// Landing page after publishing, saved as the idempotent response.
+pub(super) fn published_page(issue_id: Uuid, scheduled_for: Option<ScheduledFor>) -> HttpResponse {
+    let headline = match scheduled_for {
+        Some(scheduled_for) => format!(
+            "Your issue has been scheduled for {}.",
+            scheduled_for.as_ref().to_rfc3339()
+        ),
+        None => "Your issue has been accepted and emails will go out shortly!".to_string(),
+    };
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
         <!DOCTYPE html>
         <html lang="en">
             <head>
@@ -71,27 +105,26 @@ pub async fn publish_issue_form_submission(
                 <title>Newsletter published!</title>
             </head>
             <body>
-                <h1>Your issue has been accepted and emails will go out shortly!</h1>
+                <h1>{headline}</h1>
+                <p>
+                    <a href="/admin/newsletters/{issue_id}">Follow the delivery</a>
+                </p>
                 <p>
                     <a href="/admin/dashboard">&lt;- Dashboard</a>
                     <a href="/admin/newsletters">&lt;- Send new Issue</a>
                 </p>
             </body>
         </html>"#,
-    );
-    let response = save_response(transaction, &idempotency_key, *user_id, response)
-        .await
-        .map_err(e500)?;
-
-    Ok(response)
+        ))
 }
 
 #[tracing::instrument(skip_all)]
-async fn insert_newsletter_issue(
+pub(crate) async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
     title: &str,
     text_content: &str,
     html_content: &str,
+    scheduled_for: Option<ScheduledFor>,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -101,38 +134,32 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
-            published_at
+            status,
+            published_at,
+            scheduled_for
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, 'published', now(), $5)
         "#,
         newsletter_issue_id,
         title,
         text_content,
-        html_content
+        html_content,
+        scheduled_for.map(|s| *s.as_ref())
     )
     .execute(&mut **transaction)
     .await?;
     Ok(newsletter_issue_id)
 }
 
+/// Picks the recipients of an issue that goes out right away. Scheduled
+/// issues are left to the workers, which enqueue them once due so that they
+/// reach whoever is subscribed at send time.
 #[tracing::instrument(skip_all)]
-async fn enqueue_delivery_tasks(
+pub(crate) async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"
-    INSERT INTO issue_delivery_queue (
-        newsletter_issue_id,
-        subscriber_email
-    )
-    SELECT $1, email
-    FROM subscriptions
-    WHERE status = 'confirmed'
-    "#,
-        newsletter_issue_id
-    )
-    .execute(&mut **transaction)
-    .await?;
-    Ok(())
+    enqueue_due_issues(&mut **transaction, Some(newsletter_issue_id)).await?;
+    // Idle workers also wait for the send time of scheduled issues.
+    notify_workers(&mut **transaction).await
 }
@@ -0,0 +1,131 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::ScheduledFor,
+    issue_delivery_worker::notify_workers,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct RescheduleFormData {
+    scheduled_for: String,
+    utc_offset: Option<String>,
+}
+
+fn status_page(newsletter_issue_id: Uuid) -> HttpResponse {
+    see_other(&format!("/admin/newsletters/{}", newsletter_issue_id))
+}
+
+fn already_sending_message() -> FlashMessage {
+    FlashMessage::error("The issue has already started sending or was cancelled.")
+}
+
+/// Moves the send time of an issue that has not started going out yet.
+#[tracing::instrument(name = "Reschedule newsletter issue", skip(form, pool))]
+pub async fn reschedule_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<RescheduleFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let scheduled_for = match ScheduledFor::parse(&form.scheduled_for, form.utc_offset.as_deref()) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(status_page(newsletter_issue_id));
+        }
+    };
+
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE
+            newsletter_issue_id = $1 AND
+            scheduled_for > now() AND
+            cancelled_at IS NULL
+        "#,
+        newsletter_issue_id,
+        scheduled_for.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to reschedule newsletter issue.")
+    .map_err(e500)?
+    .rows_affected();
+
+    if updated_rows == 0 {
+        already_sending_message().send();
+    } else {
+        // Idle workers are waiting for the previous send time.
+        notify_workers(pool.get_ref())
+            .await
+            .context("Failed to notify the delivery workers.")
+            .map_err(e500)?;
+        FlashMessage::info("The issue has been rescheduled.").send();
+    }
+    Ok(status_page(newsletter_issue_id))
+}
+
+/// Cancels an issue that has not started going out yet, dropping its
+/// delivery tasks.
+#[tracing::instrument(name = "Cancel newsletter issue", skip(pool))]
+pub async fn cancel_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let cancelled = cancel(&pool, newsletter_issue_id).await.map_err(e500)?;
+
+    if cancelled {
+        FlashMessage::info("The issue has been cancelled.").send();
+    } else {
+        already_sending_message().send();
+    }
+    Ok(status_page(newsletter_issue_id))
+}
+
+async fn cancel(pool: &PgPool, newsletter_issue_id: Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to start cancel transaction.")?;
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET cancelled_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            scheduled_for > now() AND
+            cancelled_at IS NULL
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to cancel newsletter issue.")?
+    .rows_affected();
+    if updated_rows == 0 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to remove delivery tasks.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit cancel transaction.")?;
+    Ok(true)
+}
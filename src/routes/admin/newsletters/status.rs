@@ -0,0 +1,342 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{configuration::WorkerSettings, utils::e500};
+
+/// Window over which the recent sending throughput is measured.
+const THROUGHPUT_WINDOW_MINUTES: i64 = 5;
+
+pub(crate) struct IssueRecord {
+    pub(crate) title: String,
+    pub(crate) scheduled_for: Option<DateTime<Utc>>,
+    pub(crate) cancelled_at: Option<DateTime<Utc>>,
+    pub(crate) enqueued_at: Option<DateTime<Utc>>,
+}
+
+impl IssueRecord {
+    pub(crate) fn waiting_for_schedule(&self) -> bool {
+        self.cancelled_at.is_none()
+            && self
+                .scheduled_for
+                .is_some_and(|scheduled_for| scheduled_for > Utc::now())
+    }
+
+    /// Due, but the workers have not picked its recipients yet: nothing is
+    /// queued or logged for it so far.
+    pub(crate) fn waiting_for_recipients(&self) -> bool {
+        self.cancelled_at.is_none() && self.enqueued_at.is_none() && !self.waiting_for_schedule()
+    }
+}
+
+pub(crate) struct RecipientStatus {
+    status: String,
+    n_retries: i16,
+    error_message: Option<String>,
+}
+
+/// How many recipients of an issue are in each delivery state.
+#[derive(serde::Serialize)]
+pub(crate) struct DeliveryCounts {
+    pub(crate) queued: usize,
+    pub(crate) sent: usize,
+    pub(crate) failed: usize,
+    pub(crate) skipped: usize,
+}
+
+impl DeliveryCounts {
+    pub(crate) fn of(recipients: &BTreeMap<String, RecipientStatus>) -> Self {
+        let count = |status: &str| recipients.values().filter(|r| r.status == status).count();
+        let queued = count("queued");
+        let sent = count("sent");
+        let failed = count("failed");
+        Self {
+            queued,
+            sent,
+            failed,
+            skipped: recipients.len() - queued - sent - failed,
+        }
+    }
+}
+
+/// Delivery progress of a published issue, refreshing itself until the
+/// queue has drained.
+#[tracing::instrument(
+    name = "Newsletter issue delivery status",
+    skip(pool, worker_settings, flash_messages)
+)]
+pub async fn issue_delivery_status(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    worker_settings: web::Data<WorkerSettings>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue(&pool, newsletter_issue_id).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let recipients = get_recipient_statuses(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+
+    let DeliveryCounts {
+        queued,
+        sent,
+        failed,
+        skipped,
+    } = DeliveryCounts::of(&recipients);
+
+    let waiting_for_schedule = issue.waiting_for_schedule();
+    let schedule_html = if let Some(cancelled_at) = issue.cancelled_at {
+        format!(
+            "<p>This issue was cancelled on {}.</p>",
+            cancelled_at.to_rfc3339()
+        )
+    } else if waiting_for_schedule {
+        format!(
+            r#"<p>Scheduled for {scheduled_for}. Recipients are picked when it goes out.</p>
+        <form action="/admin/newsletters/{newsletter_issue_id}/reschedule" method="post">
+            <label for="scheduled_for">New send time</label>
+            <input id="scheduled_for" name="scheduled_for" type="datetime-local">
+            <label for="utc_offset">UTC offset</label>
+            <input id="utc_offset" name="utc_offset" type="text" placeholder="+00:00">
+            <button type="submit">Reschedule</button>
+        </form>
+        <form action="/admin/newsletters/{newsletter_issue_id}/cancel" method="post">
+            <button type="submit">Cancel issue</button>
+        </form>"#,
+            scheduled_for = issue.scheduled_for.unwrap().to_rfc3339(),
+        )
+    } else {
+        String::new()
+    };
+
+    let sending = (queued > 0 || issue.waiting_for_recipients()) && !waiting_for_schedule;
+    let throughput_html = if issue.waiting_for_recipients() {
+        "<p>Due: the recipients are being picked.</p>".to_string()
+    } else if sending {
+        let throughput = get_recent_throughput(&pool).await.map_err(e500)?;
+        throughput_report(&throughput, queued, &worker_settings)
+    } else {
+        String::new()
+    };
+
+    // A scheduled issue may sit in the queue for days: no point refreshing.
+    let refresh_html = if sending {
+        r#"<meta http-equiv="refresh" content="5">"#
+    } else {
+        ""
+    };
+    let mut rows_html = String::new();
+    for (email, recipient) in &recipients {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(email),
+            recipient.status,
+            recipient.n_retries,
+            htmlescape::encode_minimal(recipient.error_message.as_deref().unwrap_or_default()),
+        )
+        .unwrap();
+    }
+    let title = htmlescape::encode_minimal(&issue.title);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        {refresh_html}
+        <title>Delivery status</title>
+    </head>
+    <body>
+        <h1>{title}</h1>
+        {msg_html}
+        {schedule_html}
+        <ul>
+            <li id="queued">Queued: {queued}</li>
+            <li id="sent">Sent: {sent}</li>
+            <li id="failed">Failed: {failed}</li>
+            <li id="skipped">Skipped: {skipped}</li>
+        </ul>
+        {throughput_html}
+        <table>
+            <tr>
+                <th>Subscriber</th>
+                <th>Status</th>
+                <th>Retries</th>
+                <th>Last error</th>
+            </tr>
+            {rows_html}
+        </table>
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+/// Deliveries across all issues, since they share the sending budget.
+struct Throughput {
+    sent: i64,
+    rate_limited: i64,
+}
+
+/// Explains how fast the queue is draining and what holds it back.
+fn throughput_report(throughput: &Throughput, queued: usize, settings: &WorkerSettings) -> String {
+    let per_minute = throughput.sent as f64 / THROUGHPUT_WINDOW_MINUTES as f64;
+    let limits = match (settings.max_sends_per_second, settings.max_sends_per_hour) {
+        (Some(per_second), Some(per_hour)) => {
+            format!(
+                "at most {} per second and {} per hour",
+                per_second, per_hour
+            )
+        }
+        (Some(per_second), None) => format!("at most {} per second", per_second),
+        (None, Some(per_hour)) => format!("at most {} per hour", per_hour),
+        (None, None) => "no limit".into(),
+    };
+    let mut html = format!(
+        r#"<p id="throughput">Sending {:.1} emails per minute across all issues over the last {} minutes (sending budget: {}).</p>"#,
+        per_minute, THROUGHPUT_WINDOW_MINUTES, limits
+    );
+    if per_minute > 0.0 {
+        let minutes_left = (queued as f64 / per_minute).ceil();
+        write!(
+            html,
+            "<p>About {} minutes left at this pace.</p>",
+            minutes_left
+        )
+        .unwrap();
+    }
+    if throughput.rate_limited > 0 {
+        write!(
+            html,
+            "<p>The email provider throttled {} deliveries over the last {} minutes.</p>",
+            throughput.rate_limited, THROUGHPUT_WINDOW_MINUTES
+        )
+        .unwrap();
+    }
+    html
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_recent_throughput(pool: &PgPool) -> Result<Throughput, anyhow::Error> {
+    let throughput = sqlx::query_as!(
+        Throughput,
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE outcome = 'sent') AS "sent!",
+            COUNT(*) FILTER (WHERE outcome = 'rate_limited') AS "rate_limited!"
+        FROM issue_delivery_log
+        WHERE logged_at > now() - $1::bigint * interval '1 minute'
+        "#,
+        THROUGHPUT_WINDOW_MINUTES
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to compute the recent throughput.")?;
+    Ok(throughput)
+}
+
+/// Only published issues have a delivery status: drafts are not found.
+#[tracing::instrument(skip(pool))]
+pub(crate) async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueRecord>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueRecord,
+        r#"
+        SELECT title, scheduled_for, cancelled_at, enqueued_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND status = 'published'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve newsletter issue.")?;
+    Ok(issue)
+}
+
+/// Recipients still in the queue are `queued`, the others take the outcome
+/// of their latest delivery attempt.
+#[tracing::instrument(skip(pool))]
+pub(crate) async fn get_recipient_statuses(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<BTreeMap<String, RecipientStatus>, anyhow::Error> {
+    let logged = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (subscriber_email)
+            subscriber_email,
+            outcome,
+            n_retries,
+            error_message
+        FROM issue_delivery_log
+        WHERE newsletter_issue_id = $1
+        ORDER BY subscriber_email, log_id DESC
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the delivery log.")?;
+    let queued = sqlx::query!(
+        r#"
+        SELECT subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve queued deliveries.")?;
+
+    let mut recipients = BTreeMap::new();
+    for r in logged {
+        recipients.insert(
+            r.subscriber_email,
+            RecipientStatus {
+                status: r.outcome,
+                n_retries: r.n_retries,
+                error_message: r.error_message,
+            },
+        );
+    }
+    for r in queued {
+        let error_message = recipients
+            .remove(&r.subscriber_email)
+            .and_then(|logged| logged.error_message);
+        recipients.insert(
+            r.subscriber_email,
+            RecipientStatus {
+                status: "queued".into(),
+                n_retries: r.n_retries,
+                error_message,
+            },
+        );
+    }
+    Ok(recipients)
+}
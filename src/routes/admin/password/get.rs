@@ -11,7 +11,12 @@ pub async fn change_password_form(
 ) -> Result<HttpResponse, actix_web::Error> {
     let mut error_html = String::new();
     for m in flash_messages.iter().filter(|m| m.level() == Level::Error) {
-        writeln!(error_html, "<p><i>{}</i></p>", m.content()).unwrap();
+        writeln!(
+            error_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
     }
 
     Ok(HttpResponse::Ok()
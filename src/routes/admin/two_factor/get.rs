@@ -0,0 +1,96 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{generate_totp_secret, get_totp_secret, otpauth_uri, UserId},
+    routes::get_username,
+    session_state::TypedSession,
+    utils::e500,
+};
+
+/// Enrols an authenticator app when two-factor authentication is off, or
+/// offers to turn it off.
+#[tracing::instrument(name = "Two-factor settings", skip(pool, session, flash_messages))]
+pub async fn two_factor_settings(
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let content_html = if get_totp_secret(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        r#"<p>Two-factor authentication is enabled.</p>
+        <form action="/admin/two-factor/disable" method="post">
+            <label>
+                Authentication code
+                <input type="text" placeholder="Or a recovery code" name="code">
+            </label>
+            <button type="submit">Disable two-factor authentication</button>
+        </form>"#
+            .to_string()
+    } else {
+        // Keep the secret across reloads: it may already be enrolled.
+        let secret = match session.get_pending_totp_secret().map_err(e500)? {
+            Some(secret) => secret,
+            None => {
+                let secret = generate_totp_secret();
+                session.insert_pending_totp_secret(&secret).map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(*user_id, &pool).await.map_err(e500)?;
+        format!(
+            r#"<p>Two-factor authentication is disabled.</p>
+        <p>To enable it, add this account to your authenticator app:</p>
+        <p><a href="{uri}">{uri}</a></p>
+        <p>Or enter the key manually: <code>{secret}</code></p>
+        <form action="/admin/two-factor" method="post">
+            <label>
+                Authentication code
+                <input type="text" placeholder="Shown by your authenticator app" name="code">
+            </label>
+            <button type="submit">Enable two-factor authentication</button>
+        </form>"#,
+            uri = htmlescape::encode_minimal(&otpauth_uri(&secret, &username)),
+            secret = secret.expose_secret(),
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Two-factor authentication</title>
+    </head>
+    <body>
+        <h1>Two-factor authentication</h1>
+        {msg_html}
+        {content_html}
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
@@ -0,0 +1,143 @@
+use actix_web::{http::header::ContentType, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    audit::{record_audit_event, AuditSubject},
+    authentication::{
+        disable_two_factor, enable_two_factor, get_totp_secret, verify_second_factor, UserId,
+    },
+    routes::get_username,
+    session_state::TypedSession,
+    utils::{client_ip, e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorCodeFormData {
+    code: String,
+}
+
+async fn audit_two_factor_change(
+    event: &str,
+    details: &str,
+    user_id: &UserId,
+    request: &HttpRequest,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let username = get_username(**user_id, pool).await?;
+    record_audit_event(
+        pool,
+        event,
+        AuditSubject {
+            username: Some(&username),
+            client_ip: Some(&client_ip(request)),
+        },
+        details,
+    )
+    .await
+}
+
+/// Confirms the enrolment with a first code, then shows the recovery codes.
+/// This is the only time they can be seen, hence no redirect.
+#[tracing::instrument(name = "Enable two-factor authentication", skip(form, pool, session))]
+pub async fn enable_two_factor_submission(
+    form: web::Form<TwoFactorCodeFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if get_totp_secret(*user_id, &pool)
+        .await
+        .map_err(e500)?
+        .is_some()
+    {
+        FlashMessage::error("Two-factor authentication is already enabled.").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+    let Some(secret) = session.get_pending_totp_secret().map_err(e500)? else {
+        FlashMessage::error("Please add the key below to your authenticator app first.").send();
+        return Ok(see_other("/admin/two-factor"));
+    };
+    let Some(recovery_codes) = enable_two_factor(*user_id, &secret, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    else {
+        FlashMessage::error("The authentication code is invalid.").send();
+        return Ok(see_other("/admin/two-factor"));
+    };
+    session.remove_pending_totp_secret();
+    audit_two_factor_change(
+        "two_factor_enabled",
+        "Enabled two-factor authentication.",
+        &user_id,
+        &request,
+        &pool,
+    )
+    .await
+    .map_err(e500)?;
+
+    let mut codes_html = String::new();
+    for code in &recovery_codes {
+        writeln!(codes_html, "<li><code>{code}</code></li>").unwrap();
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Two-factor authentication</title>
+    </head>
+    <body>
+        <h1>Two-factor authentication</h1>
+        <p>Two-factor authentication is enabled.</p>
+        <p>
+            Store these recovery codes somewhere safe: each of them lets you log in
+            once without your authenticator app. They will not be shown again.
+        </p>
+        <ul>
+            {codes_html}
+        </ul>
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+        )))
+}
+
+/// Requires a current code, so that a hijacked session is not enough to
+/// turn the second factor off.
+#[tracing::instrument(name = "Disable two-factor authentication", skip(form, pool))]
+pub async fn disable_two_factor_submission(
+    form: web::Form<TwoFactorCodeFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    if !verify_second_factor(*user_id, &form.code, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The authentication code is invalid.").send();
+        return Ok(see_other("/admin/two-factor"));
+    }
+    disable_two_factor(*user_id, &pool).await.map_err(e500)?;
+    audit_two_factor_change(
+        "two_factor_disabled",
+        "Disabled two-factor authentication.",
+        &user_id,
+        &request,
+        &pool,
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("Two-factor authentication has been disabled.").send();
+    Ok(see_other("/admin/two-factor"))
+}
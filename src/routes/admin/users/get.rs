@@ -0,0 +1,148 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::{list_users, Role, UserId},
+    utils::e500,
+};
+
+fn role_options(selected: Role) -> String {
+    let mut options_html = String::new();
+    for role in Role::ALL {
+        let selected = if role == selected { " selected" } else { "" };
+        writeln!(
+            options_html,
+            r#"<option value="{role}"{selected}>{role}</option>"#
+        )
+        .unwrap();
+    }
+    options_html
+}
+
+/// Lists every user with forms to change their role, deactivate them or
+/// reset their two-factor authentication, and a form to create a new user.
+#[tracing::instrument(name = "Users page", skip(pool, flash_messages))]
+pub async fn users_page(
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    let users = list_users(&pool).await.map_err(e500)?;
+    let mut rows_html = String::new();
+    for user in &users {
+        let target_id = user.user_id;
+        let actions_html = if target_id == **user_id {
+            "(you)".to_string()
+        } else if user.disabled {
+            String::new()
+        } else {
+            format!(
+                r#"<form action="/admin/users/{target_id}/role" method="post">
+                        <select name="role">
+                            {options}
+                        </select>
+                        <button type="submit">Change role</button>
+                    </form>
+                    <form action="/admin/users/{target_id}/deactivate" method="post">
+                        <button type="submit">Deactivate</button>
+                    </form>
+                    {reset_two_factor_html}"#,
+                options = role_options(user.role),
+                reset_two_factor_html = if user.two_factor {
+                    format!(
+                        r#"<form action="/admin/users/{target_id}/reset-two-factor" method="post">
+                        <button type="submit">Reset two-factor authentication</button>
+                    </form>"#
+                    )
+                } else {
+                    String::new()
+                },
+            )
+        };
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td>{username}</td>
+                <td>{role}</td>
+                <td>{status}</td>
+                <td>{two_factor}</td>
+                <td>
+                    {actions_html}
+                </td>
+            </tr>"#,
+            username = htmlescape::encode_minimal(&user.username),
+            role = user.role,
+            status = if user.disabled {
+                "deactivated"
+            } else {
+                "active"
+            },
+            two_factor = if user.two_factor { "on" } else { "off" },
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Users</title>
+    </head>
+    <body>
+        <h1>Users</h1>
+        {msg_html}
+        <table>
+            <tr>
+                <th>Username</th>
+                <th>Role</th>
+                <th>Status</th>
+                <th>Two-factor</th>
+                <th></th>
+            </tr>
+            {rows_html}
+        </table>
+        <h2>Create a user</h2>
+        <form action="/admin/users" method="post">
+            <label>
+                Username
+                <input type="text" placeholder="Enter a username" name="username">
+            </label>
+            <br>
+            <label>
+                Password
+                <input type="password" placeholder="Enter their initial password" name="password">
+            </label>
+            <br>
+            <label>
+                Role
+                <select name="role">
+                    {options}
+                </select>
+            </label>
+            <br>
+            <button type="submit">Create user</button>
+        </form>
+        <p>
+            <a href="/admin/dashboard">&lt;- Back</a>
+        </p>
+    </body>
+</html>"#,
+            options = role_options(Role::Viewer),
+        )))
+}
@@ -0,0 +1,146 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{record_audit_event, AuditSubject},
+    authentication::{
+        create_user, disable_two_factor, disable_user, get_user_id, set_user_role, Role, UserId,
+    },
+    routes::get_username,
+    utils::{client_ip, e500, see_other},
+};
+
+fn users_page() -> HttpResponse {
+    see_other("/admin/users")
+}
+
+#[derive(serde::Deserialize)]
+pub struct CreateUserFormData {
+    username: String,
+    password: Secret<String>,
+    role: Role,
+}
+
+#[tracing::instrument(
+    name = "Create user from the dashboard",
+    skip(form, pool),
+    fields(username = %form.username, role = %form.role)
+)]
+pub async fn create_user_submission(
+    form: web::Form<CreateUserFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let CreateUserFormData {
+        username,
+        password,
+        role,
+    } = form.0;
+    let username = username.trim();
+    if username.is_empty() || password.expose_secret().is_empty() {
+        FlashMessage::error("Both a username and a password are required.").send();
+        return Ok(users_page());
+    }
+    if get_user_id(username, &pool).await.map_err(e500)?.is_some() {
+        FlashMessage::error("The username is already taken.").send();
+        return Ok(users_page());
+    }
+
+    create_user(username, password, role, pool.get_ref())
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!("The user {} has been created.", username)).send();
+    Ok(users_page())
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ChangeRoleFormData {
+    role: Role,
+}
+
+/// Owners cannot change their own role or deactivate themselves: there is
+/// always at least one owner left to manage the others.
+#[tracing::instrument(name = "Change user role", skip(pool))]
+pub async fn change_user_role(
+    target_id: web::Path<Uuid>,
+    form: web::Form<ChangeRoleFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = target_id.into_inner();
+    if target_id == **user_id {
+        FlashMessage::error("You cannot change your own role.").send();
+        return Ok(users_page());
+    }
+
+    if set_user_role(target_id, form.role, &pool)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The role has been changed.").send();
+    } else {
+        FlashMessage::error("The user does not exist.").send();
+    }
+    Ok(users_page())
+}
+
+#[tracing::instrument(name = "Deactivate user", skip(pool))]
+pub async fn deactivate_user(
+    target_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = target_id.into_inner();
+    if target_id == **user_id {
+        FlashMessage::error("You cannot deactivate yourself.").send();
+        return Ok(users_page());
+    }
+
+    if disable_user(target_id, &pool).await.map_err(e500)? {
+        FlashMessage::info("The user has been deactivated.").send();
+    } else {
+        FlashMessage::error("The user does not exist or was already deactivated.").send();
+    }
+    Ok(users_page())
+}
+
+/// For users who lost both their authenticator and their recovery codes.
+/// They can log in with their password alone until they enrol again.
+#[tracing::instrument(name = "Reset two-factor authentication", skip(pool, request))]
+pub async fn reset_two_factor(
+    target_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let target_id = target_id.into_inner();
+    if target_id == **user_id {
+        FlashMessage::error(
+            "You cannot reset your own two-factor authentication. Disable it from its settings page.",
+        )
+        .send();
+        return Ok(users_page());
+    }
+
+    if !disable_two_factor(target_id, &pool).await.map_err(e500)? {
+        FlashMessage::error("The user does not have two-factor authentication enabled.").send();
+        return Ok(users_page());
+    }
+    let owner = get_username(**user_id, &pool).await.map_err(e500)?;
+    let username = get_username(target_id, &pool).await.map_err(e500)?;
+    record_audit_event(
+        &pool,
+        "two_factor_reset",
+        AuditSubject {
+            username: Some(&username),
+            client_ip: Some(&client_ip(&request)),
+        },
+        &format!("Two-factor authentication reset by {}.", owner),
+    )
+    .await
+    .map_err(e500)?;
+    FlashMessage::info("The two-factor authentication of the user has been reset.").send();
+    Ok(users_page())
+}
@@ -1,5 +1,21 @@
-use actix_web::{HttpRequest, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use sqlx::PgPool;
 
 pub async fn health_check(_req: HttpRequest) -> impl Responder {
     HttpResponse::Ok()
 }
+
+/// Health of a worker-only process: workers are of no use if they cannot
+/// reach the delivery queue.
+pub async fn worker_health_check(pool: web::Data<PgPool>) -> HttpResponse {
+    match sqlx::query!("SELECT 1 AS ping")
+        .fetch_one(pool.get_ref())
+        .await
+    {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, error.message = %e, "The database is unreachable.");
+            HttpResponse::ServiceUnavailable().finish()
+        }
+    }
+}
@@ -1,7 +1,14 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use chrono::Utc;
 
-pub async fn home() -> HttpResponse {
+use crate::{domain::SignupFormToken, startup::HmacSecret};
+
+pub async fn home(hmac_secret: web::Data<HmacSecret>) -> HttpResponse {
+    let form_token = SignupFormToken::new(Utc::now().timestamp(), &hmac_secret.0);
     HttpResponse::Ok()
         .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+        .body(format!(
+            include_str!("home.html"),
+            form_token = form_token.as_ref()
+        ))
 }
@@ -1,13 +1,16 @@
-use actix_web::{error::InternalError, web, HttpResponse, ResponseError};
+use actix_web::{error::InternalError, web, HttpRequest, HttpResponse, ResponseError};
 use actix_web_flash_messages::FlashMessage;
 use reqwest::{header::LOCATION, StatusCode};
 use secrecy::Secret;
 use sqlx::PgPool;
 
 use crate::{
-    authentication::{validate_credentials, AuthError, Credentials},
+    authentication::{
+        get_totp_secret, validate_credentials, AuthError, Credentials, LoginThrottle,
+    },
     routes::error_chain_fmt,
     session_state::TypedSession,
+    utils::{client_ip, whole_seconds},
 };
 
 #[derive(serde::Deserialize)]
@@ -25,25 +28,56 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 }
 
 #[tracing::instrument(
-    skip(form, pool, session),
+    skip(form, pool, session, throttle, request),
     fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 pub async fn login(
     form: web::Form<LoginFormData>,
     pool: web::Data<PgPool>,
     session: TypedSession,
+    throttle: web::Data<LoginThrottle>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
+    let username = credentials.username.clone();
+    let client_ip = client_ip(&request);
 
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", &tracing::field::display(&username));
+
+    if let Some(retry_after) = throttle
+        .locked_out_for(&username, &client_ip)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        return Err(login_redirect(LoginError::LockedOut(retry_after)));
+    }
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
             tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            let has_second_factor = get_totp_secret(user_id, &pool)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+                .is_some();
             session.renew();
+            if has_second_factor {
+                // Failed logins keep counting until the second factor is
+                // verified too.
+                session
+                    .insert_pending_user_id(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/two-factor"))
+                    .finish());
+            }
+
+            throttle
+                .record_success(&username)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session
                 .insert_user_id(user_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
@@ -54,7 +88,13 @@ pub async fn login(
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    match throttle.record_failure(&username, &client_ip, &pool).await {
+                        Ok(Some(lockout)) => LoginError::LockedOut(lockout),
+                        Ok(None) => LoginError::AuthError(e.into()),
+                        Err(e) => LoginError::UnexpectedError(e),
+                    }
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
 
@@ -67,6 +107,11 @@ pub async fn login(
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error(
+        "Too many failed login attempts. Please try again in {} seconds.",
+        whole_seconds(.0)
+    )]
+    LockedOut(std::time::Duration),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -81,6 +126,7 @@ impl ResponseError for LoginError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             LoginError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            LoginError::LockedOut(_) => StatusCode::TOO_MANY_REQUESTS,
             LoginError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
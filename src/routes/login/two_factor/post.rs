@@ -0,0 +1,83 @@
+use actix_web::{error::InternalError, web, HttpRequest, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use reqwest::header::LOCATION;
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{verify_second_factor, LoginThrottle},
+    routes::{get_username, LoginError},
+    session_state::TypedSession,
+    utils::{client_ip, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct TwoFactorFormData {
+    code: String,
+}
+
+fn two_factor_redirect(e: LoginError) -> InternalError<LoginError> {
+    FlashMessage::error(e.to_string()).send();
+    let response = HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/login/two-factor"))
+        .finish();
+    InternalError::from_response(e, response)
+}
+
+/// Completes the login started with a valid password. Wrong codes count as
+/// failed logins, so they are throttled the same way.
+#[tracing::instrument(
+    skip(form, pool, session, throttle, request),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+)]
+pub async fn verify_two_factor(
+    form: web::Form<TwoFactorFormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    throttle: web::Data<LoginThrottle>,
+    request: HttpRequest,
+) -> Result<HttpResponse, InternalError<LoginError>> {
+    let Some(user_id) = session
+        .get_pending_user_id()
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e.into())))?
+    else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+    let username = get_username(user_id, &pool)
+        .await
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+    tracing::Span::current().record("username", &tracing::field::display(&username));
+    let client_ip = client_ip(&request);
+
+    if let Some(retry_after) = throttle
+        .locked_out_for(&username, &client_ip)
+        .await
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?
+    {
+        return Err(two_factor_redirect(LoginError::LockedOut(retry_after)));
+    }
+
+    let verified = verify_second_factor(user_id, &form.code, &pool)
+        .await
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+    if !verified {
+        let e = match throttle.record_failure(&username, &client_ip, &pool).await {
+            Ok(Some(lockout)) => LoginError::LockedOut(lockout),
+            Ok(None) => LoginError::AuthError(anyhow::anyhow!("Invalid authentication code.")),
+            Err(e) => LoginError::UnexpectedError(e),
+        };
+        return Err(two_factor_redirect(e));
+    }
+
+    throttle
+        .record_success(&username)
+        .await
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e)))?;
+    session.remove_pending_user_id();
+    session.renew();
+    session
+        .insert_user_id(user_id)
+        .map_err(|e| two_factor_redirect(LoginError::UnexpectedError(e.into())))?;
+
+    Ok(see_other("/admin/dashboard"))
+}
@@ -3,16 +3,22 @@ mod health_check;
 mod home;
 mod login;
 mod newsletters;
+mod setup;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use newsletters::*;
+pub use setup::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_preferences::*;
+pub use subscriptions_unsubscribe::*;
 
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
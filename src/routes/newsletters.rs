@@ -1,12 +1,17 @@
-use actix_web::{web, HttpResponse, Responder};
-
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 use crate::{
     authentication::UserId,
-    domain::{publish_issue, Content, IssueContent},
-    email_client::EmailAPIClient,
-    idempotency::IdempotencyKey,
+    domain::{Content, ScheduledFor},
+    idempotency::{save_response, try_processing, IdempotencyKey, NextAction},
+    routes::{
+        enqueue_delivery_tasks, get_issue, get_recipient_statuses, insert_newsletter_issue,
+        DeliveryCounts,
+    },
+    startup::ApplicationBaseUrl,
     utils::{e400, e500},
 };
 
@@ -15,29 +20,113 @@ pub struct SendIssueContent {
     title: String,
     content: Content,
     idempotency_key: String,
+    scheduled_for: Option<String>,
 }
 
-impl From<SendIssueContent> for IssueContent {
-    fn from(val: SendIssueContent) -> Self {
-        IssueContent {
-            title: val.title,
-            content: val.content,
-        }
-    }
+#[derive(serde::Serialize)]
+struct AcceptedIssue {
+    newsletter_issue_id: Uuid,
+    status_url: String,
 }
 
-#[tracing::instrument(name = "Publish a newsletter issue", skip(body, pool, email_client))]
+/// Persists the issue and enqueues its deliveries, like the admin form: the
+/// emails go out from the delivery workers, not within the request. Retries
+/// with the same idempotency key get the original response back.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, base_url),
+    fields(idempotency_key = body.idempotency_key)
+)]
 pub async fn publish_newsletters(
     body: web::Json<SendIssueContent>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailAPIClient>,
-    _user_id: web::ReqData<UserId>,
-) -> Result<impl Responder, actix_web::Error> {
-    let _idempotency_key: IdempotencyKey =
-        body.0.idempotency_key.clone().try_into().map_err(e400)?;
+    base_url: web::Data<ApplicationBaseUrl>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = user_id.into_inner();
+    let SendIssueContent {
+        title,
+        content,
+        idempotency_key,
+        scheduled_for,
+    } = body.0;
+    let idempotency_key: IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+    let scheduled_for =
+        ScheduledFor::parse_optional(scheduled_for.as_deref(), None).map_err(e400)?;
+
+    let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
+        .await
+        .map_err(e500)?
+    {
+        NextAction::StartProcessing(t) => t,
+        NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+    };
+
+    let newsletter_issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &content.text,
+        &content.html,
+        scheduled_for,
+    )
+    .await
+    .context("Failed to store newsletter issue details")
+    .map_err(e500)?;
+    enqueue_delivery_tasks(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")
+        .map_err(e500)?;
 
-    publish_issue(&body.0.into(), &email_client, &pool)
+    let response = HttpResponse::Accepted().json(AcceptedIssue {
+        newsletter_issue_id,
+        status_url: format!("{}/newsletters/{}", base_url.0, newsletter_issue_id),
+    });
+    let response = save_response(transaction, &idempotency_key, *user_id, response)
         .await
         .map_err(e500)?;
-    Ok(HttpResponse::Ok())
+    Ok(response)
+}
+
+#[derive(serde::Serialize)]
+struct IssueStatus {
+    newsletter_issue_id: Uuid,
+    title: String,
+    /// One of `scheduled`, `sending`, `done` or `cancelled`.
+    status: &'static str,
+    scheduled_for: Option<String>,
+    #[serde(flatten)]
+    deliveries: DeliveryCounts,
+}
+
+/// Delivery progress of an issue, for API clients to poll.
+#[tracing::instrument(name = "Newsletter issue status", skip(pool))]
+pub async fn newsletter_issue_status(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let Some(issue) = get_issue(&pool, newsletter_issue_id).await.map_err(e500)? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let recipients = get_recipient_statuses(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?;
+    let deliveries = DeliveryCounts::of(&recipients);
+
+    let status = if issue.cancelled_at.is_some() {
+        "cancelled"
+    } else if issue.waiting_for_schedule() {
+        "scheduled"
+    } else if deliveries.queued > 0 || issue.waiting_for_recipients() {
+        "sending"
+    } else {
+        "done"
+    };
+    Ok(HttpResponse::Ok().json(IssueStatus {
+        newsletter_issue_id,
+        title: issue.title,
+        status,
+        scheduled_for: issue.scheduled_for.map(|s| s.to_rfc3339()),
+        deliveries,
+    }))
 }
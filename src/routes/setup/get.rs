@@ -0,0 +1,79 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::{
+    authentication::has_active_owner,
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct SetupQuery {
+    #[serde(default)]
+    token: String,
+}
+
+/// Lets whoever holds the setup token printed at startup create the first
+/// owner account. Closed as soon as there is one.
+#[tracing::instrument(name = "Setup form", skip(query, pool, flash_messages))]
+pub async fn setup_form(
+    query: web::Query<SetupQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    if has_active_owner(&pool).await.map_err(e500)? {
+        return Ok(see_other("/login"));
+    }
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Setup</title>
+    </head>
+    <body>
+        <h1>Create the owner account</h1>
+        {msg_html}
+        <form action="/setup" method="post">
+            <label>
+                Setup token
+                <input type="text" placeholder="Printed in the logs at startup" name="token" value="{token}">
+            </label>
+            <br>
+            <label>
+                Username
+                <input type="text" placeholder="Enter a username" name="username">
+            </label>
+            <br>
+            <label>
+                Password
+                <input type="password" placeholder="Enter a password" name="password">
+            </label>
+            <br>
+            <label>
+                Confirm password
+                <input type="password" placeholder="Again the password" name="password_check">
+            </label>
+            <br>
+            <button type="submit">Create owner account</button>
+        </form>
+    </body>
+</html>"#,
+            token = htmlescape::encode_attribute(&query.token),
+        )))
+}
@@ -0,0 +1,69 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+
+use crate::{
+    authentication::{consume_setup_token, create_user, get_user_id, has_active_owner, Role},
+    utils::{e500, see_other},
+};
+
+#[derive(serde::Deserialize)]
+pub struct SetupFormData {
+    token: Secret<String>,
+    username: String,
+    password: Secret<String>,
+    password_check: Secret<String>,
+}
+
+#[tracing::instrument(name = "Create owner account", skip(form, pool))]
+pub async fn setup(
+    form: web::Form<SetupFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if has_active_owner(&pool).await.map_err(e500)? {
+        return Ok(see_other("/login"));
+    }
+
+    let SetupFormData {
+        token,
+        username,
+        password,
+        password_check,
+    } = form.0;
+    // Keep the token in the form while fixing other mistakes.
+    let setup_page = see_other(&format!(
+        "/setup?token={}",
+        urlencoding::encode(token.expose_secret())
+    ));
+    let username = username.trim();
+    if username.is_empty() || password.expose_secret().is_empty() {
+        FlashMessage::error("Both a username and a password are required.").send();
+        return Ok(setup_page);
+    }
+    if password.expose_secret() != password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(setup_page);
+    }
+    if get_user_id(username, &pool).await.map_err(e500)?.is_some() {
+        FlashMessage::error("The username is already taken.").send();
+        return Ok(setup_page);
+    }
+
+    let mut transaction = pool.begin().await.map_err(e500)?;
+    if !consume_setup_token(&token, &mut *transaction)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("The setup token is invalid or was already used.").send();
+        return Ok(see_other("/setup"));
+    }
+    create_user(username, password, Role::Owner, &mut *transaction)
+        .await
+        .map_err(e500)?;
+    transaction.commit().await.map_err(e500)?;
+
+    FlashMessage::info("The owner account has been created. You can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,26 +1,49 @@
+use std::time::Duration;
+
 use crate::{
-    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriberStatus},
-    email_client::EmailAPIClient,
-    startup::ApplicationBaseUrl,
+    configuration::SubscriptionThrottlingSettings,
+    domain::{
+        NewSubscriber, PreferencesToken, SignupFormToken, SubscriberEmail, SubscriberName,
+        SubscriberStatus,
+    },
+    email_client::{EmailSender, SendEmailError},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscription_throttle::{confirmation_email_cooldown, SubscriptionThrottle},
+    utils::{client_ip, whole_seconds},
+};
+use actix_web::{
+    http::header::{ContentType, RETRY_AFTER},
+    web, HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use actix_web::{web, HttpResponse, Responder, ResponseError};
 use anyhow::Context;
 use chrono::Utc;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use reqwest::StatusCode;
+use secrecy::Secret;
 use sqlx::{types::Uuid, Executor, PgPool, Postgres, Transaction};
 
 use super::error_chain_fmt;
 
+/// How long confirmation links can be followed.
+pub(crate) const CONFIRMATION_TOKEN_VALIDITY_HOURS: i64 = 48;
+
 /// Route creates a new subscription and sends a confirmation email to the user.
 ///
 /// Requests for subscriptions that are in pending confirmation status send
 /// again confirmation emails.  
-/// Requests for other existing subscriptions are unauthorized until a better
-/// response kind is proposed.
+/// Subscribers that previously unsubscribed go back to pending confirmation
+/// and have to confirm again.  
+/// Confirmed subscribers get an email pointing to their subscription instead,
+/// while the caller gets the same response as for a new subscription: it does
+/// not tell whether the address is subscribed.
+///
+/// Every request sends an email, so they are rate limited per client IP, per
+/// email address and per subscriber, pending or confirmed alike. Requests that do not come from
+/// the signup form skip its bot checks, and get a stricter limit per client
+/// IP.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, email_client, base_url, hmac_secret, throttle, request),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -29,11 +52,32 @@ use super::error_chain_fmt;
 pub async fn subscribe(
     form: web::Form<SubscribeFormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailAPIClient>,
+    email_client: web::Data<dyn EmailSender>,
     base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    throttle: web::Data<SubscriptionThrottle>,
+    request: HttpRequest,
 ) -> Result<impl Responder, SubscribeError> {
+    reject_bots(&form.0, throttle.settings(), &hmac_secret.0)?;
+    let client_ip = client_ip(&request);
+    if form.form_token.is_none() {
+        if let Some(retry_after) = throttle.count_tokenless_request_from(&client_ip).await? {
+            return Err(SubscribeError::TooManyRequests(retry_after));
+        }
+    }
+    if let Some(retry_after) = throttle.count_request_from(&client_ip).await? {
+        return Err(SubscribeError::TooManyRequests(retry_after));
+    }
+
     // Confirm well formed new subscriber form.
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    if let Some(retry_after) = throttle
+        .count_request_for(new_subscriber.email.as_ref())
+        .await?
+    {
+        return Err(SubscribeError::TooManyRequests(retry_after));
+    }
 
     // Confirm that the status for the subscriber is correct for the endpoint.
     let subscriber_status = subscription_status(&new_subscriber, &pool)
@@ -47,17 +91,70 @@ pub async fn subscribe(
 
     // Create pending subscription or retrieve existing subscription id.
     let sub_id = match subscriber_status {
-        SubscriberStatus::Unsubscribed => insert_subscriber(&mut transaction, &new_subscriber)
+        None => insert_subscriber(&mut transaction, &new_subscriber)
             .await
             .context("Failed to insert subscriber.")?,
-        SubscriberStatus::PendingConfirmation => uuid_for_subscriber(&new_subscriber, &pool)
+        Some(SubscriberStatus::PendingConfirmation) => uuid_for_subscriber(&new_subscriber, &pool)
             .await
             .context("Failed to retrieve subscriber information.")?,
-        SubscriberStatus::Confirmed => return Ok(HttpResponse::InternalServerError().finish()),
+        Some(SubscriberStatus::Unsubscribed) => {
+            resubscribe_subscriber(&mut transaction, &new_subscriber)
+                .await
+                .context("Failed to restore unsubscribed subscriber.")?
+        }
+        Some(SubscriberStatus::Confirmed) => {
+            if let Some(retry_after) = throttle
+                .count_subscribed_notice_to(new_subscriber.email.as_ref())
+                .await?
+            {
+                return Err(SubscribeError::TooManyRequests(retry_after));
+            }
+            let subscriber_id = uuid_for_subscriber(&new_subscriber, &pool)
+                .await
+                .context("Failed to retrieve subscriber information.")?;
+            let manage_link =
+                PreferencesToken::new(subscriber_id, new_subscriber.email.as_ref(), &hmac_secret.0)
+                    .preferences_link(&base_url.0);
+            send_already_subscribed_email(
+                email_client.as_ref(),
+                &new_subscriber.email,
+                &manage_link,
+            )
+            .await
+            .context("Failed to send already subscribed email.")?;
+            return Err(SubscribeError::AlreadySubscribed);
+        }
     };
 
+    if let Some(retry_after) =
+        confirmation_email_cooldown(&mut transaction, sub_id, throttle.settings()).await?
+    {
+        return Err(SubscribeError::TooManyRequests(retry_after));
+    }
+
+    send_new_confirmation(
+        transaction,
+        sub_id,
+        &new_subscriber.email,
+        email_client.as_ref(),
+        &base_url.0,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Stores a fresh confirmation token for a pending subscriber, commits
+/// `transaction` and emails the confirmation link.
+pub(crate) async fn send_new_confirmation(
+    mut transaction: Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email: &SubscriberEmail,
+    email_client: &dyn EmailSender,
+    base_url: &str,
+) -> Result<(), anyhow::Error> {
     let subscription_token = generate_subscription_token();
-    store_subscription_token(&mut transaction, &subscription_token, sub_id)
+    store_subscription_token(&mut transaction, &subscription_token, subscriber_id)
         .await
         .context("Failed to store confirmation token.")?;
 
@@ -66,16 +163,10 @@ pub async fn subscribe(
         .await
         .context("Failed to commit subscription transaction.")?;
 
-    send_confirmation_email(
-        email_client.as_ref(),
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send confirmation email.")?;
-
-    Ok(HttpResponse::Ok().finish())
+    send_confirmation_email(email_client, email, base_url, &subscription_token)
+        .await
+        .context("Failed to send confirmation email.")?;
+    Ok(())
 }
 
 pub struct StoreTokenError(sqlx::Error);
@@ -112,6 +203,12 @@ impl std::fmt::Display for StoreTokenError {
 pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
+    /// Answered like a successful subscription, so that the response does not
+    /// disclose who is subscribed.
+    #[error("The email address is already subscribed.")]
+    AlreadySubscribed,
+    #[error("Too many subscription requests")]
+    TooManyRequests(Duration),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -126,15 +223,60 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SubscribeError::AlreadySubscribed => StatusCode::OK,
+            SubscribeError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            SubscribeError::AlreadySubscribed => HttpResponse::Ok().finish(),
+            SubscribeError::TooManyRequests(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, whole_seconds(retry_after)))
+                .finish(),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct SubscribeFormData {
     email: String,
     name: String,
+    /// Honeypot: hidden from people, so only bots fill it in.
+    #[serde(default)]
+    website: String,
+    /// Sent by the signup form, but not by API clients.
+    form_token: Option<String>,
+}
+
+/// Turns away the submissions of the signup form that give a bot away: a
+/// filled in honeypot, or a form submitted too quickly after it was served.
+fn reject_bots(
+    form: &SubscribeFormData,
+    settings: &SubscriptionThrottlingSettings,
+    hmac_secret: &Secret<String>,
+) -> Result<(), SubscribeError> {
+    if !form.website.is_empty() {
+        return Err(SubscribeError::TooManyRequests(settings.window()));
+    }
+    let Some(form_token) = &form.form_token else {
+        return Ok(());
+    };
+    let now = Utc::now().timestamp();
+    let served_at = SignupFormToken::verify(form_token, now, hmac_secret)
+        .map_err(SubscribeError::ValidationError)?;
+    let fill_seconds = now - served_at;
+    let min_form_fill_seconds = settings.min_form_fill_seconds as i64;
+    if fill_seconds < min_form_fill_seconds {
+        return Err(SubscribeError::TooManyRequests(Duration::from_secs(
+            (min_form_fill_seconds - fill_seconds) as u64,
+        )));
+    }
+    Ok(())
 }
 
 impl TryFrom<SubscribeFormData> for NewSubscriber {
@@ -172,6 +314,29 @@ async fn insert_subscriber(
     Ok(subscriber_id)
 }
 
+#[tracing::instrument(
+    name = "Restoring unsubscribed subscriber",
+    skip(transaction, new_subscriber)
+)]
+async fn resubscribe_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+) -> Result<Uuid, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', name = $2
+        WHERE email = $1
+        RETURNING id
+        "#,
+        new_subscriber.email.as_ref(),
+        new_subscriber.name.as_ref(),
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    Ok(record.id)
+}
+
 #[tracing::instrument(name = "Saving subscription token to db", skip(transaction))]
 async fn store_subscription_token(
     transaction: &mut Transaction<'_, Postgres>,
@@ -180,11 +345,12 @@ async fn store_subscription_token(
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscriptions_tokens (subscription_token, subscriber_id)
-        VALUES ($1, $2)
+        INSERT INTO subscriptions_tokens (subscription_token, subscriber_id, expires_at)
+        VALUES ($1, $2, now() + $3::bigint * interval '1 hour')
         "#,
         subscription_token,
         subscriber_id,
+        CONFIRMATION_TOKEN_VALIDITY_HOURS,
     );
     transaction.execute(query).await?;
     Ok(())
@@ -192,14 +358,14 @@ async fn store_subscription_token(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, email, base_url)
 )]
 async fn send_confirmation_email(
-    email_client: &EmailAPIClient,
-    new_subscriber: NewSubscriber,
+    email_client: &dyn EmailSender,
+    email: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     // TODO change the confirmation link logic:
     // link should get a domain from app configuration
     // link should get a registration token baked-in.
@@ -216,10 +382,33 @@ async fn send_confirmation_email(
         confirmation_link
     );
 
+    email_client
+        .send_email(email, "Welcome", &html_content, &text_content)
+        .await
+}
+
+#[tracing::instrument(
+    name = "Send an already subscribed email to a confirmed subscriber",
+    skip(email_client, email, manage_link)
+)]
+async fn send_already_subscribed_email(
+    email_client: &dyn EmailSender,
+    email: &SubscriberEmail,
+    manage_link: &str,
+) -> Result<(), SendEmailError> {
+    let html_content = format!(
+        "You're already subscribed to our newsletter! <br/> Click <a href=\"{}\">here</a> to manage your subscription.",
+        manage_link
+    );
+    let text_content = format!(
+        "You're already subscribed to our newsletter! Visit {} to manage your subscription.",
+        manage_link
+    );
+
     email_client
         .send_email(
-            &new_subscriber.email,
-            "Welcome",
+            email,
+            "You're already subscribed",
             &html_content,
             &text_content,
         )
@@ -239,7 +428,7 @@ pub fn generate_subscription_token() -> String {
 async fn subscription_status(
     subscriber: &NewSubscriber,
     pool: &PgPool,
-) -> Result<SubscriberStatus, sqlx::Error> {
+) -> Result<Option<SubscriberStatus>, sqlx::Error> {
     let subscription_record = sqlx::query!(
         "SELECT status 
         FROM subscriptions 
@@ -249,7 +438,7 @@ async fn subscription_status(
     .fetch_optional(pool)
     .await?;
     if subscription_record.is_none() {
-        return Ok(SubscriberStatus::Unsubscribed);
+        return Ok(None);
     }
     let subscription_status = SubscriberStatus::parse(&subscription_record.unwrap().status)
         .map_err(|e| {
@@ -258,8 +447,8 @@ async fn subscription_status(
             e
         });
     match subscription_status {
-        Ok(status) => Ok(status),
-        Err(_) => Ok(SubscriberStatus::Unsubscribed),
+        Ok(status) => Ok(Some(status)),
+        Err(_) => Ok(None),
     }
 }
 
@@ -1,15 +1,24 @@
+use std::time::Duration;
+
 use actix_web::{
-    web::{Data, Query},
-    HttpResponse, Responder, ResponseError,
+    http::header::{ContentType, RETRY_AFTER},
+    web::{Data, Form, Query},
+    HttpRequest, HttpResponse, Responder, ResponseError,
 };
 use anyhow::{anyhow, Context};
 use reqwest::StatusCode;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
-use crate::domain::SubscriberStatus;
+use crate::{
+    domain::{SubscriberEmail, SubscriberStatus},
+    email_client::EmailSender,
+    startup::ApplicationBaseUrl,
+    subscription_throttle::{confirmation_email_cooldown, SubscriptionThrottle},
+    utils::{client_ip, whole_seconds},
+};
 
-use super::error_chain_fmt;
+use super::{error_chain_fmt, send_new_confirmation};
 
 /// Endpoint for the subscription confirmation token. Checks if the subscription token is associated with a subscription and confirms is.
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
@@ -24,20 +33,26 @@ pub async fn confirm(
         .await
         .context("Failed to acquire database transaction.")?;
 
-    let subscriber_id = subscriber_id_from_token(subscription_token, &mut transaction).await?;
+    let subscriber_id = subscriber_id_from_token(&subscription_token, &mut transaction).await?;
 
     let status = subscriber_status_from_id(subscriber_id, &mut transaction).await?;
 
+    // Only pending subscribers can be confirmed: an old link must not bring
+    // back someone who has unsubscribed since.
     match status {
+        SubscriberStatus::PendingConfirmation => Ok(()),
         SubscriberStatus::Confirmed => Err(ConfirmationError::AlreadySubscribed(
             "Subscription already confirmed".to_string(),
         )),
-        _ => Ok(()),
+        SubscriberStatus::Unsubscribed => Err(ConfirmationError::Unsubscribed),
     }?;
 
     confirm_subscriber(subscriber_id, &mut transaction)
         .await
         .context("Failed to change subscription status.")?;
+    mark_token_as_used(&subscription_token, &mut transaction)
+        .await
+        .context("Failed to mark the confirmation token as used.")?;
 
     transaction
         .commit()
@@ -52,6 +67,93 @@ pub struct ConfirmationParameters {
     subscription_token: String,
 }
 
+/// Emails a fresh confirmation link to the subscriber an expired or used
+/// token was issued for, if they are still pending confirmation.
+///
+/// A form rather than a link, so that mail scanners prefetching the error
+/// page do not trigger emails.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, base_url, throttle, request),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn resend_confirmation(
+    form: Form<ConfirmationParameters>,
+    pool: Data<PgPool>,
+    email_client: Data<dyn EmailSender>,
+    base_url: Data<ApplicationBaseUrl>,
+    throttle: Data<SubscriptionThrottle>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ConfirmationError> {
+    if let Some(retry_after) = throttle.count_request_from(&client_ip(&request)).await? {
+        return Err(ConfirmationError::TooManyRequests(retry_after));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire database transaction.")?;
+
+    let record = sqlx::query!(
+        r#"
+        SELECT subscriptions.id, subscriptions.email, subscriptions.status
+        FROM subscriptions_tokens
+        JOIN subscriptions ON subscriptions.id = subscriptions_tokens.subscriber_id
+        WHERE subscriptions_tokens.subscription_token = $1
+        FOR UPDATE OF subscriptions
+        "#,
+        form.0.subscription_token
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the subscriber of the confirmation token.")?
+    .ok_or_else(|| ConfirmationError::ValidationError("No subscription found!".into()))?;
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(&record.id));
+
+    match SubscriberStatus::parse(&record.status).map_err(|e| anyhow!(e))? {
+        SubscriberStatus::PendingConfirmation => {}
+        SubscriberStatus::Confirmed => {
+            return Err(ConfirmationError::AlreadySubscribed(
+                "Subscription already confirmed".to_string(),
+            ))
+        }
+        SubscriberStatus::Unsubscribed => {
+            return Err(ConfirmationError::ValidationError(
+                "No pending subscription found!".into(),
+            ))
+        }
+    }
+    if let Some(retry_after) =
+        confirmation_email_cooldown(&mut transaction, record.id, throttle.settings()).await?
+    {
+        return Err(ConfirmationError::TooManyRequests(retry_after));
+    }
+    let email = SubscriberEmail::parse(record.email).map_err(|e| anyhow!(e))?;
+
+    send_new_confirmation(
+        transaction,
+        record.id,
+        &email,
+        email_client.as_ref(),
+        &base_url.0,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Confirmation link sent</title>
+    </head>
+    <body>
+        <p>A new confirmation link is on its way to your inbox.</p>
+    </body>
+</html>"#,
+    ))
+}
+
 #[tracing::instrument(
     name = "Get the subscriber status given the subscription id!",
     skip(transaction, subscription_id)
@@ -81,13 +183,17 @@ async fn subscriber_status_from_id(
     skip(transaction, subscription_token)
 )]
 async fn subscriber_id_from_token(
-    subscription_token: String,
+    subscription_token: &str,
     transaction: &mut Transaction<'_, Postgres>,
 ) -> Result<Uuid, ConfirmationError> {
     let result = sqlx::query!(
-        "SELECT subscriber_id
+        r#"SELECT
+        subscriber_id,
+        used_at IS NOT NULL AS "used!",
+        expires_at <= now() AS "expired!"
     FROM subscriptions_tokens
-    WHERE subscription_token = $1",
+    WHERE subscription_token = $1
+    FOR UPDATE"#,
         subscription_token
     )
     .fetch_optional(&mut **transaction)
@@ -95,6 +201,14 @@ async fn subscriber_id_from_token(
     .context("Failed to retrieve subscription confirmation.")?;
 
     match result {
+        Some(record) if record.used => Err(ConfirmationError::ExpiredToken {
+            message: "This confirmation link has already been used.".into(),
+            subscription_token: subscription_token.into(),
+        }),
+        Some(record) if record.expired => Err(ConfirmationError::ExpiredToken {
+            message: "This confirmation link has expired.".into(),
+            subscription_token: subscription_token.into(),
+        }),
         Some(record) => Ok(record.subscriber_id),
         None => Err(ConfirmationError::ValidationError(
             "No subscription found!".into(),
@@ -102,6 +216,25 @@ async fn subscriber_id_from_token(
     }
 }
 
+#[tracing::instrument(
+    name = "Marking the confirmation token as used",
+    skip(subscription_token, transaction)
+)]
+async fn mark_token_as_used(
+    subscription_token: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions_tokens SET used_at = now() WHERE subscription_token = $1
+        "#,
+        subscription_token
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
 #[tracing::instrument(name = "Changing subscriber status", skip(subscriber_id, transaction))]
 async fn confirm_subscriber(
     subscriber_id: Uuid,
@@ -109,7 +242,8 @@ async fn confirm_subscriber(
 ) -> Result<(), sqlx::Error> {
     sqlx::query!(
         r#"
-        UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
         "#,
         subscriber_id
     )
@@ -124,6 +258,18 @@ pub enum ConfirmationError {
     ValidationError(String),
     #[error("{0}")]
     AlreadySubscribed(String),
+    /// Sending a new confirmation link would not help: they have to sign up
+    /// again.
+    #[error("You have unsubscribed since. Sign up again to get the newsletter.")]
+    Unsubscribed,
+    /// The token exists, but has expired or was already used.
+    #[error("{message}")]
+    ExpiredToken {
+        message: String,
+        subscription_token: String,
+    },
+    #[error("Too many confirmation requests")]
+    TooManyRequests(Duration),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -140,6 +286,49 @@ impl ResponseError for ConfirmationError {
             ConfirmationError::ValidationError(_) => StatusCode::UNAUTHORIZED,
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ConfirmationError::AlreadySubscribed(_) => StatusCode::GONE,
+            ConfirmationError::Unsubscribed => StatusCode::GONE,
+            ConfirmationError::ExpiredToken { .. } => StatusCode::GONE,
+            ConfirmationError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ConfirmationError::ExpiredToken {
+                message,
+                subscription_token,
+            } => HttpResponse::build(self.status_code())
+                .content_type(ContentType::html())
+                .body(expired_token_page(message, subscription_token)),
+            ConfirmationError::TooManyRequests(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, whole_seconds(retry_after)))
+                .finish(),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
+
+/// Offers to send a fresh confirmation link instead of the expired one.
+fn expired_token_page(message: &str, subscription_token: &str) -> String {
+    format!(
+        r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Confirmation link expired</title>
+    </head>
+    <body>
+        <p>{message}</p>
+        <form action="/subscriptions/confirm/resend" method="post">
+            <input hidden type="text" name="subscription_token" value="{subscription_token}">
+            <button type="submit">Resend confirmation</button>
+        </form>
+    </body>
+</html>"#,
+        message = htmlescape::encode_minimal(message),
+        subscription_token = htmlescape::encode_attribute(subscription_token),
+    )
 }
@@ -0,0 +1,557 @@
+use std::{fmt::Write, time::Duration};
+
+use actix_web::{
+    http::header::{ContentType, RETRY_AFTER},
+    web::{Data, Form, Query},
+    HttpRequest, HttpResponse, ResponseError,
+};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::{
+        PreferencesToken, SubscriberEmail, SubscriberName, SubscriberStatus, UnsubscribeToken,
+    },
+    email_client::{EmailSender, SendEmailError},
+    startup::{ApplicationBaseUrl, HmacSecret},
+    subscription_throttle::SubscriptionThrottle,
+    utils::{client_ip, see_other, whole_seconds},
+};
+
+use super::{error_chain_fmt, generate_subscription_token, CONFIRMATION_TOKEN_VALIDITY_HOURS};
+
+/// Pause lengths offered on the preferences page. Pausing for 0 days resumes
+/// delivery.
+const PAUSE_OPTIONS_DAYS: [i64; 3] = [7, 30, 90];
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+struct Subscriber {
+    email: String,
+    name: String,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+/// Preference center of a confirmed subscriber, reached through the signed
+/// link sent with every issue.
+///
+/// Every form carries the token back, so the link is the only credential.
+#[tracing::instrument(
+    name = "Subscriber preferences form",
+    skip(parameters, pool, hmac_secret, flash_messages),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn preferences_form(
+    parameters: Query<PreferencesParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, PreferencesError> {
+    let token = parameters.0.token;
+    let (subscriber_id, subscriber) = confirmed_subscriber(&token, &pool, &hmac_secret).await?;
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(
+            msg_html,
+            "<p><i>{}</i></p>",
+            htmlescape::encode_minimal(m.content())
+        )
+        .unwrap();
+    }
+    let pause_status = match subscriber.paused_until {
+        Some(paused_until) if paused_until > Utc::now() => format!(
+            "Delivery is paused until {}.",
+            paused_until.format("%Y-%m-%d %H:%M UTC")
+        ),
+        _ => "You receive every issue.".to_string(),
+    };
+    let mut pause_options = String::new();
+    for days in PAUSE_OPTIONS_DAYS {
+        writeln!(
+            pause_options,
+            r#"<option value="{days}">Pause for {days} days</option>"#
+        )
+        .unwrap();
+    }
+    let unsubscribe_token = UnsubscribeToken::new(subscriber_id, &hmac_secret.0);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Your subscription</title>
+    </head>
+    <body>
+        {msg_html}
+        <h1>Your subscription</h1>
+        <form action="/subscriptions/preferences/name" method="post">
+            <input hidden type="text" name="token" value="{token}">
+            <label>
+                Name
+                <input type="text" name="name" value="{name}">
+            </label>
+            <button type="submit">Change name</button>
+        </form>
+        <form action="/subscriptions/preferences/email" method="post">
+            <input hidden type="text" name="token" value="{token}">
+            <label>
+                Email
+                <input type="email" name="email" value="{email}">
+            </label>
+            <button type="submit">Change email</button>
+        </form>
+        <p>{pause_status}</p>
+        <form action="/subscriptions/preferences/pause" method="post">
+            <input hidden type="text" name="token" value="{token}">
+            <select name="days">
+                {pause_options}
+                <option value="0">Resume delivery</option>
+            </select>
+            <button type="submit">Update delivery</button>
+        </form>
+        <form action="/subscriptions/unsubscribe" method="post">
+            <input hidden type="text" name="token" value="{unsubscribe_token}">
+            <button type="submit">Unsubscribe</button>
+        </form>
+    </body>
+</html>"#,
+            token = htmlescape::encode_attribute(&token),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            email = htmlescape::encode_minimal(&subscriber.email),
+            unsubscribe_token = unsubscribe_token.as_ref(),
+        )))
+}
+
+#[derive(serde::Deserialize)]
+pub struct NameFormData {
+    token: String,
+    name: String,
+}
+
+#[tracing::instrument(
+    name = "Change the subscriber name",
+    skip(form, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn change_subscriber_name(
+    form: Form<NameFormData>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let NameFormData { token, name } = form.0;
+    let (subscriber_id, _) = confirmed_subscriber(&token, &pool, &hmac_secret).await?;
+
+    match SubscriberName::parse(name) {
+        Ok(name) => {
+            sqlx::query!(
+                "UPDATE subscriptions SET name = $2 WHERE id = $1",
+                subscriber_id,
+                name.as_ref()
+            )
+            .execute(pool.get_ref())
+            .await
+            .context("Failed to update the subscriber name.")?;
+            FlashMessage::info("Your name has been changed.").send();
+        }
+        Err(e) => FlashMessage::error(e).send(),
+    }
+    Ok(see_other(&preferences_path(&token)))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailFormData {
+    token: String,
+    email: String,
+}
+
+/// Emails a confirmation link to the new address: it replaces the current
+/// one only once the subscriber proves they own it.
+///
+/// Addresses that already belong to a subscriber get the same answer but no
+/// email, so that the form does not disclose who is subscribed.
+#[tracing::instrument(
+    name = "Request an email address change",
+    skip(form, pool, email_client, base_url, hmac_secret, throttle, request),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn change_subscriber_email(
+    form: Form<EmailFormData>,
+    pool: Data<PgPool>,
+    email_client: Data<dyn EmailSender>,
+    base_url: Data<ApplicationBaseUrl>,
+    hmac_secret: Data<HmacSecret>,
+    throttle: Data<SubscriptionThrottle>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let (subscriber_id, subscriber) =
+        confirmed_subscriber(&form.token, &pool, &hmac_secret).await?;
+    let redirect_path = preferences_path(&form.token);
+
+    let new_email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&redirect_path));
+        }
+    };
+    if new_email.as_ref() == subscriber.email {
+        FlashMessage::error("This is already your email address.").send();
+        return Ok(see_other(&redirect_path));
+    }
+    if let Some(retry_after) = throttle.count_request_from(&client_ip(&request)).await? {
+        return Err(PreferencesError::TooManyRequests(retry_after));
+    }
+    if let Some(retry_after) = throttle.count_request_for(new_email.as_ref()).await? {
+        return Err(PreferencesError::TooManyRequests(retry_after));
+    }
+
+    if !email_is_taken(&new_email, &pool)
+        .await
+        .context("Failed to check whether the email address is taken.")?
+    {
+        let email_change_token = generate_subscription_token();
+        store_email_change_token(&pool, &email_change_token, subscriber_id, &new_email)
+            .await
+            .context("Failed to store the email change token.")?;
+        send_email_change_confirmation(
+            email_client.as_ref(),
+            &new_email,
+            &base_url.0,
+            &email_change_token,
+        )
+        .await
+        .context("Failed to send the email change confirmation.")?;
+    }
+
+    FlashMessage::info(format!(
+        "We sent a confirmation link to {}. Your email address changes once you follow it.",
+        new_email.as_ref()
+    ))
+    .send();
+    Ok(see_other(&redirect_path))
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParameters {
+    token: String,
+}
+
+/// Landing page of the link sent to the new address of an email change.
+#[tracing::instrument(
+    name = "Confirm an email address change",
+    skip(parameters, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn confirm_email_change(
+    parameters: Query<EmailChangeParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let email_change_token = parameters.0.token;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire database transaction.")?;
+
+    let record = sqlx::query!(
+        r#"SELECT
+        subscriber_id,
+        new_email,
+        used_at IS NOT NULL AS "used!",
+        expires_at <= now() AS "expired!"
+    FROM email_change_tokens
+    WHERE email_change_token = $1
+    FOR UPDATE"#,
+        email_change_token
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve the email change token.")?
+    .ok_or_else(|| PreferencesError::ValidationError("No email change found!".into()))?;
+    tracing::Span::current().record(
+        "subscriber_id",
+        &tracing::field::display(&record.subscriber_id),
+    );
+    if record.used {
+        return Err(PreferencesError::Gone(
+            "This confirmation link has already been used.".into(),
+        ));
+    }
+    if record.expired {
+        return Err(PreferencesError::Gone(
+            "This confirmation link has expired.".into(),
+        ));
+    }
+
+    let old_email = change_email(&mut transaction, record.subscriber_id, &record.new_email).await?;
+    sqlx::query!(
+        "UPDATE email_change_tokens SET used_at = now() WHERE email_change_token = $1",
+        email_change_token
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to mark the email change token as used.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit transaction.")?;
+    tracing::info!(%old_email, "Changed the subscriber email address.");
+
+    FlashMessage::info("Your email address has been changed.").send();
+    // Links sent to the old address stop working: here is one for the new.
+    let token = PreferencesToken::new(record.subscriber_id, &record.new_email, &hmac_secret.0);
+    Ok(see_other(&preferences_path(token.as_ref())))
+}
+
+#[derive(serde::Deserialize)]
+pub struct PauseFormData {
+    token: String,
+    days: i64,
+}
+
+/// Pauses delivery for one of the offered periods, or resumes it.
+#[tracing::instrument(
+    name = "Pause delivery to a subscriber",
+    skip(form, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn pause_delivery(
+    form: Form<PauseFormData>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let (subscriber_id, _) = confirmed_subscriber(&form.token, &pool, &hmac_secret).await?;
+
+    if form.days != 0 && !PAUSE_OPTIONS_DAYS.contains(&form.days) {
+        FlashMessage::error("Choose one of the offered pause lengths.").send();
+        return Ok(see_other(&preferences_path(&form.token)));
+    }
+    let paused_until = (form.days != 0).then(|| Utc::now() + chrono::Duration::days(form.days));
+    sqlx::query!(
+        "UPDATE subscriptions SET paused_until = $2 WHERE id = $1",
+        subscriber_id,
+        paused_until
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber pause.")?;
+
+    if form.days == 0 {
+        FlashMessage::info("Delivery has been resumed.").send();
+    } else {
+        FlashMessage::info(format!("Delivery is paused for {} days.", form.days)).send();
+    }
+    Ok(see_other(&preferences_path(&form.token)))
+}
+
+fn preferences_path(token: &str) -> String {
+    format!("/subscriptions/preferences?token={}", token)
+}
+
+/// Only confirmed subscribers have preferences: the others get no issues.
+///
+/// The token is bound to the subscriber email, which has to be looked up
+/// before the token can be verified. Records the subscriber id on the span
+/// of the calling handler.
+async fn confirmed_subscriber(
+    token: &str,
+    pool: &PgPool,
+    hmac_secret: &HmacSecret,
+) -> Result<(Uuid, Subscriber), PreferencesError> {
+    let claimed_subscriber_id = PreferencesToken::claimed_subscriber_id(token)
+        .map_err(PreferencesError::ValidationError)?;
+    let record = sqlx::query!(
+        "SELECT email, name, status, paused_until FROM subscriptions WHERE id = $1",
+        claimed_subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber.")?
+    .ok_or_else(|| PreferencesError::ValidationError("No subscription found!".into()))?;
+    let subscriber_id = PreferencesToken::verify(token, &record.email, &hmac_secret.0)
+        .map_err(PreferencesError::ValidationError)?;
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(&subscriber_id));
+
+    match SubscriberStatus::parse(&record.status).map_err(|e| anyhow!(e))? {
+        SubscriberStatus::Confirmed => Ok((
+            subscriber_id,
+            Subscriber {
+                email: record.email,
+                name: record.name,
+                paused_until: record.paused_until,
+            },
+        )),
+        _ => Err(PreferencesError::Gone(
+            "You are not subscribed to our newsletter.".into(),
+        )),
+    }
+}
+
+#[tracing::instrument(name = "Check whether an email address is taken", skip(email, pool))]
+async fn email_is_taken(email: &SubscriberEmail, pool: &PgPool) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE email = $1) AS "taken!""#,
+        email.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Saving email change token to db",
+    skip(pool, email_change_token, new_email)
+)]
+async fn store_email_change_token(
+    pool: &PgPool,
+    email_change_token: &str,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_change_tokens (email_change_token, subscriber_id, new_email, expires_at)
+        VALUES ($1, $2, $3, now() + $4::bigint * interval '1 hour')
+        "#,
+        email_change_token,
+        subscriber_id,
+        new_email.as_ref(),
+        CONFIRMATION_TOKEN_VALIDITY_HOURS,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Moves the subscriber, and the issues queued for them, to the new address.
+/// Returns the previous address.
+#[tracing::instrument(name = "Changing subscriber email", skip(transaction, new_email))]
+async fn change_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_email: &str,
+) -> Result<String, PreferencesError> {
+    let old_email = sqlx::query_scalar!(
+        "SELECT email FROM subscriptions WHERE id = $1 AND status = 'confirmed' FOR UPDATE",
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to retrieve the subscriber email.")?
+    .ok_or_else(|| PreferencesError::Gone("You are not subscribed to our newsletter.".into()))?;
+
+    // The address may have been taken since the link was sent.
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET email = $2
+        WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM subscriptions WHERE email = $2)
+        "#,
+        subscriber_id,
+        new_email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to change the subscriber email.")?
+    .rows_affected();
+    if updated_rows == 0 {
+        return Err(PreferencesError::Gone(
+            "This email address can no longer be used.".into(),
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET subscriber_email = $2 WHERE subscriber_email = $1",
+        old_email,
+        new_email
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to move queued deliveries to the new email.")?;
+    Ok(old_email)
+}
+
+#[tracing::instrument(
+    name = "Send an email change confirmation",
+    skip(email_client, email, base_url, email_change_token)
+)]
+async fn send_email_change_confirmation(
+    email_client: &dyn EmailSender,
+    email: &SubscriberEmail,
+    base_url: &str,
+    email_change_token: &str,
+) -> Result<(), SendEmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/preferences/email/confirm?token={}",
+        base_url, email_change_token
+    );
+    let html_content = format!(
+        "Click <a href=\"{}\">here</a> to receive our newsletter at this address.",
+        confirmation_link
+    );
+    let text_content = format!(
+        "Visit {} to receive our newsletter at this address.",
+        confirmation_link
+    );
+
+    email_client
+        .send_email(
+            email,
+            "Confirm your new email address",
+            &html_content,
+            &text_content,
+        )
+        .await
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    /// The subscription, or the email change link, cannot be used anymore.
+    #[error("{0}")]
+    Gone(String),
+    #[error("Too many requests")]
+    TooManyRequests(Duration),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            PreferencesError::ValidationError(_) => StatusCode::UNAUTHORIZED,
+            PreferencesError::Gone(_) => StatusCode::GONE,
+            PreferencesError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            PreferencesError::TooManyRequests(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((RETRY_AFTER, whole_seconds(retry_after)))
+                .finish(),
+            _ => HttpResponse::build(self.status_code())
+                .content_type(ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Form, Query},
+    HttpResponse, ResponseError,
+};
+use anyhow::Context;
+use reqwest::StatusCode;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{domain::UnsubscribeToken, startup::HmacSecret};
+
+use super::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+/// Landing page of the unsubscribe link sent with every issue.
+///
+/// Following the link does not change anything: mail scanners prefetch
+/// links, so the subscriber has to confirm through the form.
+#[tracing::instrument(name = "Unsubscribe form", skip(parameters, hmac_secret))]
+pub async fn unsubscribe_form(
+    parameters: Query<UnsubscribeParameters>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let token = parameters.0.token;
+    UnsubscribeToken::verify(&token, &hmac_secret.0).map_err(UnsubscribeError::ValidationError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribe</title>
+    </head>
+    <body>
+        <p>Do you want to stop receiving our newsletter?</p>
+        <form action="/subscriptions/unsubscribe" method="post">
+            <input hidden type="text" name="token" value="{token}">
+            <button type="submit">Unsubscribe</button>
+        </form>
+    </body>
+</html>"#,
+        )))
+}
+
+/// Marks the subscriber identified by the signed token as unsubscribed.
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(form, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe(
+    form: Form<UnsubscribeParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = UnsubscribeToken::verify(&form.0.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::ValidationError)?;
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(&subscriber_id));
+
+    unsubscribe_subscriber(subscriber_id, &pool).await?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"
+            <!DOCTYPE html>
+<html lang="en">
+    <head>
+        <meta http-equiv="content-type" content="text/html; charset=utf-8">
+        <title>Unsubscribed</title>
+    </head>
+    <body>
+        <p>You have been unsubscribed and will not receive further issues.</p>
+    </body>
+</html>"#,
+    ))
+}
+
+#[derive(serde::Deserialize)]
+pub struct OneClickUnsubscribeForm {
+    #[serde(rename = "List-Unsubscribe")]
+    list_unsubscribe: String,
+}
+
+/// RFC 8058 one-click unsubscribe, invoked by mailbox providers through the
+/// `List-Unsubscribe` header.
+///
+/// The request comes from the provider rather than the subscriber's browser,
+/// so the signed token in the query string is the only credential.
+#[tracing::instrument(
+    name = "One-click unsubscribe",
+    skip(parameters, form, pool, hmac_secret),
+    fields(subscriber_id = tracing::field::Empty)
+)]
+pub async fn unsubscribe_one_click(
+    parameters: Query<UnsubscribeParameters>,
+    form: Form<OneClickUnsubscribeForm>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    if form.0.list_unsubscribe != "One-Click" {
+        return Err(UnsubscribeError::MalformedRequest(
+            "Expected `List-Unsubscribe=One-Click` in the request body.".into(),
+        ));
+    }
+    let subscriber_id = UnsubscribeToken::verify(&parameters.0.token, &hmac_secret.0)
+        .map_err(UnsubscribeError::ValidationError)?;
+    tracing::Span::current().record("subscriber_id", &tracing::field::display(&subscriber_id));
+
+    unsubscribe_subscriber(subscriber_id, &pool).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Changing subscriber status to unsubscribed", skip(pool))]
+async fn unsubscribe_subscriber(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<(), UnsubscribeError> {
+    let updated_rows = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to change subscription status.")?
+    .rows_affected();
+
+    if updated_rows == 0 {
+        return Err(UnsubscribeError::ValidationError(
+            "No subscription found!".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("{0}")]
+    MalformedRequest(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            UnsubscribeError::ValidationError(_) => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::MalformedRequest(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::configuration::WorkerSettings;
+
+/// Refills continuously at `limit` tokens per `period`, holding at most
+/// `limit` of them.
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: u32, period: Duration, now: Instant) -> Self {
+        let capacity = f64::from(limit.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_second: capacity / period.as_secs_f64(),
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    fn available(&self) -> usize {
+        self.tokens.floor() as usize
+    }
+
+    fn time_until_next_token(&self) -> Duration {
+        let missing = (1.0 - self.tokens).max(0.0);
+        Duration::from_secs_f64(missing / self.refill_per_second)
+    }
+}
+
+struct LimiterState {
+    buckets: Vec<TokenBucket>,
+    paused_until: Option<Instant>,
+}
+
+/// Sending budget shared by every worker of the process, so that bursts
+/// stay within what the email provider tolerates.
+pub struct SendRateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+impl SendRateLimiter {
+    pub fn new(settings: &WorkerSettings) -> Self {
+        let now = Instant::now();
+        let buckets = [
+            (settings.max_sends_per_second, Duration::from_secs(1)),
+            (settings.max_sends_per_hour, Duration::from_secs(60 * 60)),
+        ]
+        .into_iter()
+        .filter_map(|(limit, period)| limit.map(|limit| TokenBucket::new(limit, period, now)))
+        .collect();
+        Self::with_buckets(buckets)
+    }
+
+    pub fn unlimited() -> Self {
+        Self::with_buckets(vec![])
+    }
+
+    fn with_buckets(buckets: Vec<TokenBucket>) -> Self {
+        Self {
+            state: Mutex::new(LimiterState {
+                buckets,
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Takes up to `max` sends out of the budget. When nothing can be sent
+    /// right now, returns how long to wait instead.
+    pub fn try_acquire(&self, max: usize) -> Result<usize, Duration> {
+        self.try_acquire_at(max, Instant::now())
+    }
+
+    fn try_acquire_at(&self, max: usize, now: Instant) -> Result<usize, Duration> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(paused_until) = state.paused_until {
+            if paused_until > now {
+                return Err(paused_until - now);
+            }
+            state.paused_until = None;
+        }
+
+        for bucket in &mut state.buckets {
+            bucket.refill(now);
+        }
+        let available = state
+            .buckets
+            .iter()
+            .map(TokenBucket::available)
+            .fold(max, usize::min);
+        if available == 0 {
+            let wait = state
+                .buckets
+                .iter()
+                .map(TokenBucket::time_until_next_token)
+                .max()
+                .unwrap_or_default();
+            return Err(wait);
+        }
+        for bucket in &mut state.buckets {
+            bucket.tokens -= available as f64;
+        }
+        Ok(available)
+    }
+
+    /// Same as `try_acquire`, with the sends held by a permit that gives
+    /// back the unused ones when dropped, early returns and errors included.
+    pub fn try_acquire_permit(&self, max: usize) -> Result<SendPermit<'_>, Duration> {
+        let granted = self.try_acquire(max)?;
+        Ok(SendPermit {
+            limiter: self,
+            unused: granted,
+        })
+    }
+
+    /// Gives back sends that were acquired but not used.
+    pub fn release(&self, n: usize) {
+        let mut state = self.state.lock().unwrap();
+        for bucket in &mut state.buckets {
+            bucket.tokens = (bucket.tokens + n as f64).min(bucket.capacity);
+        }
+    }
+
+    /// Stops handing out sends for `duration`, e.g. when the email provider
+    /// asks us to back off.
+    pub fn pause_for(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + duration;
+        state.paused_until = Some(state.paused_until.map_or(until, |p| p.max(until)));
+    }
+}
+
+/// Sends taken out of a `SendRateLimiter` budget.
+pub struct SendPermit<'a> {
+    limiter: &'a SendRateLimiter,
+    unused: usize,
+}
+
+impl SendPermit<'_> {
+    /// Sends granted and not used yet.
+    pub fn available(&self) -> usize {
+        self.unused
+    }
+
+    /// Marks `n` sends as used: they do not go back to the budget.
+    pub fn use_sends(&mut self, n: usize) {
+        self.unused = self.unused.saturating_sub(n);
+    }
+}
+
+impl Drop for SendPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release(self.unused);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use claims::{assert_err, assert_ok_eq};
+
+    use super::{SendRateLimiter, TokenBucket};
+
+    fn limiter(limits: &[(u32, Duration)], now: Instant) -> SendRateLimiter {
+        SendRateLimiter::with_buckets(
+            limits
+                .iter()
+                .map(|&(limit, period)| TokenBucket::new(limit, period, now))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn an_unlimited_limiter_grants_everything_asked() {
+        let limiter = SendRateLimiter::unlimited();
+        assert_ok_eq!(limiter.try_acquire(500), 500);
+    }
+
+    #[test]
+    fn the_budget_refills_over_time() {
+        let now = Instant::now();
+        let limiter = limiter(&[(10, Duration::from_secs(1))], now);
+
+        assert_ok_eq!(limiter.try_acquire_at(100, now), 10);
+        let wait = assert_err!(limiter.try_acquire_at(1, now));
+        assert!(wait <= Duration::from_millis(100), "{:?}", wait);
+        assert_ok_eq!(
+            limiter.try_acquire_at(100, now + Duration::from_millis(500)),
+            5
+        );
+    }
+
+    #[test]
+    fn the_tightest_limit_wins() {
+        let now = Instant::now();
+        let limiter = limiter(
+            &[
+                (10, Duration::from_secs(1)),
+                (15, Duration::from_secs(60 * 60)),
+            ],
+            now,
+        );
+
+        assert_ok_eq!(limiter.try_acquire_at(100, now), 10);
+        assert_ok_eq!(limiter.try_acquire_at(100, now + Duration::from_secs(1)), 5);
+        let wait = assert_err!(limiter.try_acquire_at(100, now + Duration::from_secs(2)));
+        assert!(wait > Duration::from_secs(60), "{:?}", wait);
+    }
+
+    #[test]
+    fn released_sends_can_be_acquired_again() {
+        let now = Instant::now();
+        let limiter = limiter(&[(10, Duration::from_secs(1))], now);
+
+        assert_ok_eq!(limiter.try_acquire_at(10, now), 10);
+        limiter.release(4);
+        assert_ok_eq!(limiter.try_acquire_at(10, now), 4);
+    }
+
+    #[test]
+    fn nothing_is_granted_while_paused() {
+        let limiter = SendRateLimiter::unlimited();
+
+        limiter.pause_for(Duration::from_secs(30));
+
+        let wait = assert_err!(limiter.try_acquire(10));
+        assert!(wait > Duration::from_secs(29), "{:?}", wait);
+    }
+
+    #[test]
+    fn dropped_permits_give_back_the_unused_sends() {
+        let now = Instant::now();
+        let limiter = limiter(&[(10, Duration::from_secs(1))], now);
+
+        {
+            let mut permit = limiter.try_acquire_permit(10).unwrap();
+            assert_eq!(permit.available(), 10);
+            permit.use_sends(6);
+        }
+        assert_ok_eq!(limiter.try_acquire_at(10, now), 4);
+    }
+}
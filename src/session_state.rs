@@ -2,6 +2,7 @@ use std::future::{ready, Ready};
 
 use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
 use actix_web::FromRequest;
+use secrecy::{ExposeSecret, Secret};
 use uuid::Uuid;
 
 /// Concerned with handling [Session] storage with appropriate types and
@@ -10,6 +11,10 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    /// Set once the password is verified, until the second factor is.
+    const PENDING_USER_ID_KEY: &'static str = "pending_user_id";
+    /// A TOTP secret shown to the user but not confirmed with a code yet.
+    const PENDING_TOTP_SECRET_KEY: &'static str = "pending_totp_secret";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -23,6 +28,37 @@ impl TypedSession {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    pub fn insert_pending_user_id(&self, user_id: Uuid) -> Result<(), SessionInsertError> {
+        self.0.insert(Self::PENDING_USER_ID_KEY, user_id)
+    }
+
+    pub fn get_pending_user_id(&self) -> Result<Option<Uuid>, SessionGetError> {
+        self.0.get(Self::PENDING_USER_ID_KEY)
+    }
+
+    pub fn remove_pending_user_id(&self) {
+        self.0.remove(Self::PENDING_USER_ID_KEY);
+    }
+
+    pub fn insert_pending_totp_secret(
+        &self,
+        secret: &Secret<String>,
+    ) -> Result<(), SessionInsertError> {
+        self.0
+            .insert(Self::PENDING_TOTP_SECRET_KEY, secret.expose_secret())
+    }
+
+    pub fn get_pending_totp_secret(&self) -> Result<Option<Secret<String>>, SessionGetError> {
+        Ok(self
+            .0
+            .get::<String>(Self::PENDING_TOTP_SECRET_KEY)?
+            .map(Secret::new))
+    }
+
+    pub fn remove_pending_totp_secret(&self) {
+        self.0.remove(Self::PENDING_TOTP_SECRET_KEY);
+    }
+
     pub fn log_out(&self) {
         self.0.purge()
     }
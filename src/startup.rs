@@ -1,21 +1,38 @@
 use crate::{
-    authentication::{users_basic_authentication, users_session_authentication},
+    authentication::{
+        ensure_no_default_credentials, require_editor, require_owner, require_publish_scope,
+        require_read_scope, users_api_authentication, users_session_authentication, LoginThrottle,
+    },
     configuration::{DatabaseSettings, Settings},
-    email_client::EmailAPIClient,
+    email_client::EmailSender,
     routes::{
-        admin_dashboard, change_password, change_password_form, confirm, health_check, home,
-        log_out, login, login_form, publish_issue_form_submission, publish_newsletters,
-        send_newsletter_form, subscribe,
+        admin_dashboard, api_tokens_page, cancel_issue, change_password, change_password_form,
+        change_subscriber_email, change_subscriber_name, change_user_role, confirm,
+        confirm_email_change, create_api_token_submission, create_draft, create_user_submission,
+        deactivate_user, delete_draft, delivery_failures, disable_two_factor_submission,
+        edit_draft_form, enable_two_factor_submission, health_check, home, issue_delivery_status,
+        list_drafts, log_out, login, login_form, new_draft_form, newsletter_issue_status,
+        pause_delivery, preferences_form, preview_draft, publish_draft,
+        publish_issue_form_submission, publish_newsletters, requeue_delivery_failure,
+        reschedule_issue, resend_confirmation, reset_two_factor, revoke_api_token_submission,
+        send_newsletter_form, send_test_issue, send_test_issue_form_submission, setup, setup_form,
+        subscribe, two_factor_form, two_factor_settings, unsubscribe, unsubscribe_form,
+        unsubscribe_one_click, update_draft, users_page, verify_two_factor, worker_health_check,
     },
+    send_rate_limiter::SendRateLimiter,
+    subscription_throttle::SubscriptionThrottle,
+};
+use std::{
+    net::{IpAddr, TcpListener},
+    sync::Arc,
 };
-use std::net::TcpListener;
 
 use actix_session::{storage::RedisSessionStore, SessionMiddleware};
 use actix_web::{
     cookie::Key,
     dev::Server,
     web::{self},
-    App, HttpServer,
+    App, HttpServer, Route,
 };
 use actix_web_flash_messages::{storage::CookieMessageStore, FlashMessagesFramework};
 use actix_web_lab::middleware::from_fn;
@@ -26,13 +43,16 @@ use tracing_actix_web::TracingLogger;
 pub struct Application {
     port: u16,
     server: Server,
+    send_rate_limiter: Arc<SendRateLimiter>,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, anyhow::Error> {
         let connection = get_connection_pool(&configuration.database);
 
-        let email_client = configuration.email_client.client();
+        ensure_no_default_credentials(&connection, configuration.environment).await?;
+
+        let email_client = configuration.email_client.clone().email_sender()?;
 
         let address = format!(
             "{}:{}",
@@ -41,16 +61,69 @@ impl Application {
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr()?.port();
 
+        let send_rate_limiter = Arc::new(SendRateLimiter::new(&configuration.worker));
+
         let server = run(
             listener,
             connection,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
-            configuration.redis_uri,
+            send_rate_limiter.clone(),
+            configuration,
         )
         .await?;
 
+        Ok(Self {
+            port,
+            server,
+            send_rate_limiter,
+        })
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        self.server.await
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Budget the test sends are taken from, for workers running in the
+    /// same process to share.
+    pub fn send_rate_limiter(&self) -> Arc<SendRateLimiter> {
+        self.send_rate_limiter.clone()
+    }
+}
+
+/// Serves `/health_check` for processes running the delivery workers only.
+pub struct WorkerHealthCheck {
+    port: u16,
+    server: Server,
+}
+
+impl WorkerHealthCheck {
+    pub fn build(configuration: &Settings) -> Result<Self, anyhow::Error> {
+        // Health checks must answer quickly, even when the database is down.
+        let db_pool = PgPoolOptions::new()
+            .acquire_timeout(std::time::Duration::from_secs(2))
+            .connect_lazy_with(configuration.database.with_db());
+        let db_pool = web::Data::new(db_pool);
+
+        let address = format!(
+            "{}:{}",
+            configuration.application.host, configuration.worker.health_check_port
+        );
+        let listener = TcpListener::bind(address)?;
+        let port = listener.local_addr()?.port();
+
+        let server = HttpServer::new(move || {
+            App::new()
+                .wrap(TracingLogger::default())
+                .route("/health_check", web::get().to(worker_health_check))
+                .app_data(db_pool.clone())
+        })
+        .listen(listener)?
+        .run();
+
         Ok(Self { port, server })
     }
 
@@ -63,27 +136,60 @@ impl Application {
     }
 }
 
+/// Viewers can look around the admin pages, but changing newsletter issues
+/// requires the editor role.
+fn editor_only(route: Route) -> Route {
+    route.wrap(from_fn(require_editor))
+}
+
+fn owner_only(route: Route) -> Route {
+    route.wrap(from_fn(require_owner))
+}
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> Pool<Postgres> {
     PgPoolOptions::new().connect_lazy_with(configuration.with_db())
 }
 
+/// Applies the migrations the database is missing.
+pub async fn migrate_database(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
+    sqlx::migrate!("./migrations").run(pool).await
+}
+
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct HmacSecret(pub Secret<String>);
+
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailAPIClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
-    redis_uri: Secret<String>,
+    email_client: Arc<dyn EmailSender>,
+    send_rate_limiter: Arc<SendRateLimiter>,
+    configuration: Settings,
 ) -> Result<Server, anyhow::Error> {
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
+    let send_rate_limiter = web::Data::from(send_rate_limiter);
+    let base_url = web::Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let hmac_secret = configuration.application.hmac_secret;
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
+    let hmac_secret = web::Data::new(HmacSecret(hmac_secret));
+    let trusted_proxies = web::Data::new(TrustedProxies(configuration.application.trusted_proxies));
+    let worker_settings = web::Data::new(configuration.worker);
     let message_storage_backend = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework = FlashMessagesFramework::builder(message_storage_backend).build();
-    let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    let redis_store = RedisSessionStore::new(configuration.redis_uri.expose_secret()).await?;
+    let login_throttle = web::Data::new(
+        LoginThrottle::new(&configuration.redis_uri, configuration.login_throttling).await?,
+    );
+    let subscription_throttle = web::Data::new(
+        SubscriptionThrottle::new(
+            &configuration.redis_uri,
+            configuration.subscription_throttling,
+        )
+        .await?,
+    );
 
     let server = HttpServer::new(move || {
         App::new()
@@ -100,26 +206,168 @@ async fn run(
                     .route("/dashboard", web::get().to(admin_dashboard))
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
+                    .route("/two-factor", web::get().to(two_factor_settings))
+                    .route("/two-factor", web::post().to(enable_two_factor_submission))
+                    .route(
+                        "/two-factor/disable",
+                        web::post().to(disable_two_factor_submission),
+                    )
                     .route("/logout", web::post().to(log_out))
-                    .route("/newsletters", web::get().to(send_newsletter_form))
                     .route(
                         "/newsletters",
-                        web::post().to(publish_issue_form_submission),
+                        editor_only(web::get().to(send_newsletter_form)),
+                    )
+                    .route(
+                        "/newsletters",
+                        editor_only(web::post().to(publish_issue_form_submission)),
+                    )
+                    .route(
+                        "/newsletters/test",
+                        editor_only(web::post().to(send_test_issue_form_submission)),
+                    )
+                    .route("/newsletters/drafts", web::get().to(list_drafts))
+                    .route(
+                        "/newsletters/drafts",
+                        editor_only(web::post().to(create_draft)),
+                    )
+                    .route(
+                        "/newsletters/drafts/new",
+                        editor_only(web::get().to(new_draft_form)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}",
+                        editor_only(web::get().to(edit_draft_form)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}",
+                        editor_only(web::post().to(update_draft)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/preview",
+                        web::get().to(preview_draft),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/delete",
+                        editor_only(web::post().to(delete_draft)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/publish",
+                        editor_only(web::post().to(publish_draft)),
+                    )
+                    .route(
+                        "/newsletters/drafts/{draft_id}/test",
+                        editor_only(web::post().to(send_test_issue)),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(issue_delivery_status),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/reschedule",
+                        editor_only(web::post().to(reschedule_issue)),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        editor_only(web::post().to(cancel_issue)),
+                    )
+                    .route("/delivery-failures", web::get().to(delivery_failures))
+                    .route(
+                        "/delivery-failures/requeue",
+                        editor_only(web::post().to(requeue_delivery_failure)),
+                    )
+                    .route("/api-tokens", editor_only(web::get().to(api_tokens_page)))
+                    .route(
+                        "/api-tokens",
+                        editor_only(web::post().to(create_api_token_submission)),
+                    )
+                    .route(
+                        "/api-tokens/{api_token_id}/revoke",
+                        editor_only(web::post().to(revoke_api_token_submission)),
+                    )
+                    .route("/users", owner_only(web::get().to(users_page)))
+                    .route("/users", owner_only(web::post().to(create_user_submission)))
+                    .route(
+                        "/users/{user_id}/role",
+                        owner_only(web::post().to(change_user_role)),
+                    )
+                    .route(
+                        "/users/{user_id}/deactivate",
+                        owner_only(web::post().to(deactivate_user)),
+                    )
+                    .route(
+                        "/users/{user_id}/reset-two-factor",
+                        owner_only(web::post().to(reset_two_factor)),
                     ),
             )
             .route("/health_check", web::get().to(health_check))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/login/two-factor", web::get().to(two_factor_form))
+            .route("/login/two-factor", web::post().to(verify_two_factor))
+            .route("/setup", web::get().to(setup_form))
+            .route("/setup", web::post().to(setup))
             .service(
                 web::scope("/newsletters")
-                    .wrap(from_fn(users_basic_authentication))
-                    .route("", web::post().to(publish_newsletters)),
+                    .wrap(from_fn(users_api_authentication))
+                    .route(
+                        "",
+                        editor_only(
+                            web::post()
+                                .to(publish_newsletters)
+                                .wrap(from_fn(require_publish_scope)),
+                        ),
+                    )
+                    .route(
+                        "/{newsletter_issue_id}",
+                        web::get()
+                            .to(newsletter_issue_status)
+                            .wrap(from_fn(require_read_scope)),
+                    ),
             )
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/confirm/resend",
+                web::post().to(resend_confirmation),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_form),
+            )
+            .route(
+                "/subscriptions/preferences/name",
+                web::post().to(change_subscriber_name),
+            )
+            .route(
+                "/subscriptions/preferences/email",
+                web::post().to(change_subscriber_email),
+            )
+            .route(
+                "/subscriptions/preferences/email/confirm",
+                web::get().to(confirm_email_change),
+            )
+            .route(
+                "/subscriptions/preferences/pause",
+                web::post().to(pause_delivery),
+            )
+            .route(
+                "/subscriptions/unsubscribe/one-click",
+                web::post().to(unsubscribe_one_click),
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
+            .app_data(send_rate_limiter.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(worker_settings.clone())
+            .app_data(login_throttle.clone())
+            .app_data(subscription_throttle.clone())
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use redis::aio::ConnectionManager;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::SubscriptionThrottlingSettings;
+
+/// Counts subscription requests per client IP and per email address in
+/// Redis, so that every replica of the API shares them. Subscribing sends an
+/// email: the limits keep the signup form from being used to spam arbitrary
+/// addresses.
+pub struct SubscriptionThrottle {
+    connection: ConnectionManager,
+    settings: SubscriptionThrottlingSettings,
+}
+
+impl SubscriptionThrottle {
+    pub async fn new(
+        redis_uri: &Secret<String>,
+        settings: SubscriptionThrottlingSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let client = redis::Client::open(redis_uri.expose_secret().as_str())
+            .context("Invalid Redis URI.")?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis.")?;
+        Ok(Self {
+            connection,
+            settings,
+        })
+    }
+
+    pub fn settings(&self) -> &SubscriptionThrottlingSettings {
+        &self.settings
+    }
+
+    /// Counts a request from `client_ip`. Returns how long until it may
+    /// make another one, if it is over its limit.
+    pub async fn count_request_from(
+        &self,
+        client_ip: &str,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        self.count(
+            "ip",
+            client_ip,
+            self.settings.max_requests_per_ip,
+            self.settings.window_seconds,
+        )
+        .await
+    }
+
+    /// Counts a request from `client_ip` that did not come through the
+    /// signup form, on a budget of its own.
+    pub async fn count_tokenless_request_from(
+        &self,
+        client_ip: &str,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        self.count(
+            "tokenless_ip",
+            client_ip,
+            self.settings.max_tokenless_requests_per_ip,
+            self.settings.window_seconds,
+        )
+        .await
+    }
+
+    /// Counts a request for `email`, whoever makes it.
+    pub async fn count_request_for(&self, email: &str) -> Result<Option<Duration>, anyhow::Error> {
+        self.count(
+            "email",
+            &email.to_lowercase(),
+            self.settings.max_requests_per_email,
+            self.settings.window_seconds,
+        )
+        .await
+    }
+
+    /// Counts a notice sent to an already subscribed `email`. It is capped
+    /// like the confirmation emails of pending subscribers, so that repeated
+    /// signups get the same answers whether the address is subscribed or not.
+    pub async fn count_subscribed_notice_to(
+        &self,
+        email: &str,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        self.count(
+            "subscribed_notice",
+            &email.to_lowercase(),
+            self.settings.max_confirmation_emails,
+            u64::from(self.settings.confirmation_email_window_hours) * 60 * 60,
+        )
+        .await
+    }
+
+    /// Fixed window counter: the window starts with the first request.
+    async fn count(
+        &self,
+        scope: &str,
+        id: &str,
+        max_requests: u32,
+        window_seconds: u64,
+    ) -> Result<Option<Duration>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let key = format!(
+            "{}:subscriptions:{}:{}",
+            self.settings.redis_key_prefix, scope, id
+        );
+        // `INCR` keeps the expiry set along with the counter. The commands
+        // run in a transaction, so that the counter cannot expire between
+        // them and be recreated without an expiry.
+        let (requests, remaining_ms): (u64, i64) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(window_seconds)
+            .arg("NX")
+            .ignore()
+            .cmd("INCR")
+            .arg(&key)
+            .cmd("PTTL")
+            .arg(&key)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to count a subscription request.")?;
+        if requests <= u64::from(max_requests) {
+            return Ok(None);
+        }
+        Ok(Some(Duration::from_millis(remaining_ms as u64)))
+    }
+}
+
+/// How long until `subscriber_id` may be sent another confirmation email, if
+/// they were sent too many already. Every email carries a new token: they are
+/// counted in the database.
+pub async fn confirmation_email_cooldown(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    settings: &SubscriptionThrottlingSettings,
+) -> Result<Option<Duration>, anyhow::Error> {
+    let window = chrono::Duration::hours(i64::from(settings.confirmation_email_window_hours));
+    let record = sqlx::query!(
+        r#"
+        SELECT count(*) AS "sent!", min(created_at) AS oldest
+        FROM subscriptions_tokens
+        WHERE subscriber_id = $1 AND created_at > $2
+        "#,
+        subscriber_id,
+        Utc::now() - window
+    )
+    .fetch_one(&mut **transaction)
+    .await
+    .context("Failed to count the confirmation emails of the subscriber.")?;
+    if record.sent < i64::from(settings.max_confirmation_emails) {
+        return Ok(None);
+    }
+    let Some(oldest) = record.oldest else {
+        return Ok(None);
+    };
+    Ok(Some(
+        (oldest + window - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::from_secs(1)),
+    ))
+}
@@ -1,6 +1,10 @@
-use actix_web::HttpResponse;
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::{web, HttpRequest, HttpResponse};
 use reqwest::header::LOCATION;
 
+use crate::startup::TrustedProxies;
+
 pub fn e500<ErrorType>(e: ErrorType) -> actix_web::Error
 where
     ErrorType: std::fmt::Debug + std::fmt::Display + 'static,
@@ -15,8 +19,122 @@ where
     actix_web::error::ErrorBadRequest(e)
 }
 
+pub fn e403<ErrorType>(e: ErrorType) -> actix_web::Error
+where
+    ErrorType: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorForbidden(e)
+}
+
 pub fn see_other(route: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((LOCATION, route))
         .finish()
 }
+
+/// The address the request came from.
+///
+/// Behind trusted proxies, `X-Forwarded-For` is walked from the right: each
+/// proxy appends the address it got the request from, while the entries on
+/// the left are whatever the client sent. The client is the first address
+/// that is not one of the trusted proxies.
+///
+/// Falls back to a placeholder when the peer address is unknown, so that
+/// those requests share one identity.
+pub fn client_ip(request: &HttpRequest) -> String {
+    let Some(peer_ip) = request.peer_addr().map(|addr| addr.ip()) else {
+        return "unknown".into();
+    };
+    let trusted_proxies = match request.app_data::<web::Data<TrustedProxies>>() {
+        Some(proxies) => &proxies.0,
+        None => return peer_ip.to_string(),
+    };
+    let forwarded_for = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|entry| entry.trim())
+        .collect::<Vec<_>>();
+    forwarded_client_ip(peer_ip, &forwarded_for, trusted_proxies).to_string()
+}
+
+fn forwarded_client_ip(
+    peer_ip: IpAddr,
+    forwarded_for: &[&str],
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    let mut client_ip = peer_ip;
+    for entry in forwarded_for.iter().rev() {
+        if !trusted_proxies.contains(&client_ip) {
+            break;
+        }
+        match parse_ip(entry) {
+            Some(ip) => client_ip = ip,
+            // Whatever is left of a garbled entry cannot be trusted.
+            None => break,
+        }
+    }
+    client_ip
+}
+
+/// Forwarded addresses come with or without a port, IPv6 ones possibly in
+/// brackets.
+fn parse_ip(s: &str) -> Option<IpAddr> {
+    s.parse::<IpAddr>()
+        .or_else(|_| s.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| s.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+/// Rounded up, so that retrying after that many seconds always works.
+pub fn whole_seconds(duration: &std::time::Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::forwarded_client_ip;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_headers_from_untrusted_peers_are_ignored() {
+        let client = forwarded_client_ip(ip("198.51.100.7"), &["203.0.113.1"], &[ip("10.0.0.1")]);
+        assert_eq!(client, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn the_entry_appended_by_the_proxy_is_used() {
+        let client = forwarded_client_ip(
+            ip("10.0.0.1"),
+            &["203.0.113.1", "198.51.100.7"],
+            &[ip("10.0.0.1")],
+        );
+        assert_eq!(client, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn chains_of_trusted_proxies_are_walked_from_the_right() {
+        let client = forwarded_client_ip(
+            ip("10.0.0.1"),
+            &["203.0.113.1", "198.51.100.7:4321", "10.0.0.2"],
+            &[ip("10.0.0.1"), ip("10.0.0.2")],
+        );
+        assert_eq!(client, ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn a_garbled_entry_stops_the_walk() {
+        let client = forwarded_client_ip(
+            ip("10.0.0.1"),
+            &["203.0.113.1", "not-an-ip"],
+            &[ip("10.0.0.1")],
+        );
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+}
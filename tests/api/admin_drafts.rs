@@ -0,0 +1,407 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn draft_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft title",
+        "html_content": "<p>Draft HTML body</p>",
+        "text_content": "Draft plain text body",
+    })
+}
+
+/// Saves a draft and returns its id, taken from the redirect to its edit page.
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app.post_create_draft(&draft_body()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/newsletters/drafts/")
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn publish_body() -> serde_json::Value {
+    serde_json::json!({
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": "",
+    })
+}
+
+#[tokio::test]
+async fn unauthenticated_request_to_drafts_should_bounce() {
+    let app = spawn_app().await;
+
+    let response = app.post_create_draft(&draft_body()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn saved_drafts_are_listed_and_not_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    let draft_id = create_draft(&app).await;
+
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/drafts/{}">Draft title</a>"#,
+        draft_id
+    )));
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    let response = app
+        .post_update_draft(
+            draft_id,
+            &serde_json::json!({
+                "title": "Edited title",
+                "html_content": "<p>Edited HTML body</p>",
+                "text_content": "Edited plain text body",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    assert!(html_page.contains(r#"value="Edited title""#));
+    assert!(html_page.contains("&lt;p&gt;Edited HTML body&lt;/p&gt;"));
+    assert!(html_page.contains("Edited plain text body"));
+}
+
+#[tokio::test]
+async fn drafts_can_be_previewed() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    let response = app.get_preview_draft(draft_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let page_document = scraper::Html::parse_document(&html_page);
+    let html_selector = scraper::Selector::parse("#html_preview p").unwrap();
+    let html_preview = page_document.select(&html_selector).next().unwrap();
+    assert_eq!(html_preview.inner_html(), "Draft HTML body");
+    assert!(html_page.contains(r#"<pre id="text_preview">Draft plain text body</pre>"#));
+}
+
+#[tokio::test]
+async fn deleted_drafts_are_gone() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    let response = app.post_delete_draft(draft_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The draft has been deleted.</i></p>"));
+    assert!(!html_page.contains("Draft title"));
+    assert_eq!(app.get_edit_draft(draft_id).await.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn published_drafts_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_publish_draft(draft_id, &publish_body()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+    assert!(!app.get_drafts_html().await.contains("Draft title"));
+    assert_eq!(app.get_edit_draft(draft_id).await.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn publishing_a_draft_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = publish_body();
+    let response1 = app.post_publish_draft(draft_id, &body).await;
+    let response2 = app.post_publish_draft(draft_id, &body).await;
+
+    assert_eq!(response1.status().as_u16(), 200);
+    assert_eq!(response2.status().as_u16(), 200);
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_draft_cannot_be_published_twice() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_publish_draft(draft_id, &publish_body()).await;
+    let response = app.post_publish_draft(draft_id, &publish_body()).await;
+
+    assert_is_redirect_to(&response, "/admin/newsletters/drafts");
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains("<p><i>The draft does not exist or was already published.</i></p>"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn test_issues_are_sent_only_to_the_given_addresses() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+    let response = app
+        .post_send_test_issue(draft_id, "editor@example.com, reviewer@example.com")
+        .await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+    let mut recipients: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .skip(confirmation_emails)
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["To"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    recipients.sort();
+    assert_eq!(
+        recipients,
+        vec!["editor@example.com", "reviewer@example.com"]
+    );
+
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(html_page
+        .contains("<p><i>Test issue sent to editor@example.com, reviewer@example.com.</i></p>"));
+    assert!(html_page.contains(&format!(
+        "by {} to editor@example.com, reviewer@example.com</li>",
+        app.test_user.username
+    )));
+
+    // Nothing got enqueued or published.
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.get_edit_draft(draft_id).await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn test_issues_are_sent_as_subscribers_get_them() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_send_test_issue(draft_id, "editor@example.com")
+        .await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "[Test] Draft title");
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<p>Draft HTML body</p>"));
+    app.get_unsubscribe_link(&email_request);
+    app.get_preferences_link(&email_request);
+    assert!(app
+        .get_email_header(&email_request, "List-Unsubscribe")
+        .contains("/subscriptions/unsubscribe"));
+}
+
+#[tokio::test]
+async fn issues_can_be_test_sent_from_the_publish_form() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_send_test_issue_from_form(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>HTML body!</p>",
+            "text_content": "Plain text body",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "recipients": "editor@example.com",
+        }))
+        .await;
+
+    // The content is kept as a draft, and nothing goes to subscribers.
+    let draft_id = sqlx::query_scalar!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE status = 'draft'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Test issue sent to editor@example.com.</i></p>"));
+    assert!(html_page.contains("Newsletter title"));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn test_issues_are_not_sent_if_an_address_is_invalid() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_send_test_issue(draft_id, "editor@example.com, not-an-email")
+        .await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>Invalid email: not-an-email</i></p>"));
+    assert!(!html_page.contains("Test sends:"));
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_escaped_in_the_error_message() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    app.post_send_test_issue(draft_id, "<script>alert(1)</script>")
+        .await;
+
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(!html_page.contains("<script>"));
+    assert!(html_page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+}
+
+#[tokio::test]
+async fn test_issues_are_sent_to_a_few_addresses_at_most() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let recipients = (0..6)
+        .map(|i| format!("reviewer{}@example.com", i))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let response = app.post_send_test_issue(draft_id, &recipients).await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+    let html_page = app.get_edit_draft(draft_id).await.text().await.unwrap();
+    assert!(html_page.contains("<p><i>A test issue can be sent to 5 addresses at most.</i></p>"));
+    assert!(!html_page.contains("Test sends:"));
+}
+
+#[tokio::test]
+async fn drafts_have_no_delivery_status() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let draft_id = create_draft(&app).await;
+
+    let response = app.get_issue_delivery_status(draft_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.get_newsletter_issue_status(&draft_id.to_string()).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -1,10 +1,12 @@
+use chrono::{Duration, Utc};
 use uuid::Uuid;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{any, method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::{issue_delivery_worker::try_execute_delivery, send_rate_limiter::SendRateLimiter};
 
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
 
 #[tokio::test]
 async fn unauthenticated_request_to_send_newsletter_should_bounce() {
@@ -131,3 +133,593 @@ async fn concurrent_form_submission_is_handled_gracefully() {
 
     app.dispatch_all_pending_emails().await;
 }
+
+/// Publishes an issue through the admin form and returns its id.
+async fn publish_issue(app: &TestApp) -> Uuid {
+    publish_issue_scheduled_for(app, "").await
+}
+
+async fn publish_issue_scheduled_for(app: &TestApp, scheduled_for: &str) -> Uuid {
+    let newsletter_request_body = serde_json::json!(
+    {
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": scheduled_for,
+    });
+    let html_page = app
+        .post_form_newsletters(newsletter_request_body)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}">"#,
+        newsletter_issue_id
+    )));
+    newsletter_issue_id
+}
+
+fn status_counts(html_page: &str) -> Vec<String> {
+    let page_document = scraper::Html::parse_document(html_page);
+    ["queued", "sent", "failed", "skipped"]
+        .iter()
+        .map(|id| {
+            let selector = scraper::Selector::parse(&format!("li#{}", id)).unwrap();
+            page_document
+                .select(&selector)
+                .next()
+                .unwrap()
+                .text()
+                .collect()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn unauthenticated_request_to_issue_delivery_status_should_bounce() {
+    let app = spawn_app().await;
+
+    let response = app.get_issue_delivery_status(Uuid::new_v4()).await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn delivery_status_of_an_unknown_issue_is_not_found() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let response = app.get_issue_delivery_status(Uuid::new_v4()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn delivery_status_refreshes_while_deliveries_are_queued() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    let response = app.get_issue_delivery_status(newsletter_issue_id).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"<meta http-equiv="refresh""#));
+    assert_eq!(
+        status_counts(&html_page),
+        vec!["Queued: 1", "Sent: 0", "Failed: 0", "Skipped: 0"]
+    );
+}
+
+#[tokio::test]
+async fn delivery_status_reports_the_outcome_of_each_delivery() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!html_page.contains(r#"<meta http-equiv="refresh""#));
+    assert_eq!(
+        status_counts(&html_page),
+        vec!["Queued: 0", "Sent: 1", "Failed: 0", "Skipped: 0"]
+    );
+    assert!(html_page.contains("<td>example@gmail.com</td><td>sent</td>"));
+}
+
+#[tokio::test]
+async fn every_delivery_attempt_is_logged() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    {
+        let _guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let outcomes: Vec<_> = sqlx::query!(
+        r#"
+        SELECT outcome, n_retries
+        FROM issue_delivery_log
+        WHERE newsletter_issue_id = $1
+        ORDER BY log_id
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| (r.outcome, r.n_retries))
+    .collect();
+    assert_eq!(
+        outcomes,
+        vec![("retrying".to_string(), 0), ("sent".to_string(), 1)]
+    );
+}
+
+fn next_week() -> String {
+    (Utc::now() + Duration::days(7)).to_rfc3339()
+}
+
+async fn make_due(app: &TestApp) {
+    sqlx::query!("UPDATE newsletter_issues SET scheduled_for = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_their_send_time() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    publish_issue_scheduled_for(&app, &next_week()).await;
+
+    let _guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_due() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    publish_issue_scheduled_for(&app, &next_week()).await;
+
+    make_due(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn due_issues_are_sending_until_their_recipients_are_picked() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue_scheduled_for(&app, &next_week()).await;
+    make_due(&app).await;
+
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(r#"<meta http-equiv="refresh""#));
+    assert!(html_page.contains("the recipients are being picked"));
+    let status: serde_json::Value = app
+        .get_newsletter_issue_status(&newsletter_issue_id.to_string())
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["status"], "sending");
+}
+
+#[tokio::test]
+async fn scheduled_issues_go_to_whoever_is_subscribed_when_they_are_due() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    publish_issue_scheduled_for(&app, &next_week()).await;
+    // Confirms after the issue was published.
+    app.create_confirmed_subscriber().await;
+
+    make_due(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn send_times_are_read_at_the_given_utc_offset() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    app.post_form_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": "2099-01-01T08:00",
+        "utc_offset": "+02:00",
+    }))
+    .await;
+
+    let scheduled_for = sqlx::query!("SELECT scheduled_for FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .scheduled_for
+        .unwrap();
+    assert_eq!(scheduled_for.to_rfc3339(), "2099-01-01T06:00:00+00:00");
+}
+
+#[tokio::test]
+async fn issues_scheduled_in_the_past_are_rejected() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let response = app
+        .post_form_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>HTML body!</p>",
+            "text_content": "Plain text body",
+            "idempotency_key": Uuid::new_v4().to_string(),
+            "scheduled_for": (Utc::now() - Duration::days(1)).to_rfc3339(),
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue_scheduled_for(&app, &next_week()).await;
+
+    let new_time = Utc::now() + Duration::days(14);
+    let response = app
+        .post_reschedule_issue(newsletter_issue_id, &new_time.to_rfc3339())
+        .await;
+
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>The issue has been rescheduled.</i></p>"));
+    assert!(!html_page.contains(r#"<meta http-equiv="refresh""#));
+    let scheduled_for = sqlx::query!("SELECT scheduled_for FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .scheduled_for
+        .unwrap();
+    assert_eq!(scheduled_for.timestamp(), new_time.timestamp());
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue_scheduled_for(&app, &next_week()).await;
+
+    let response = app.post_cancel_issue(newsletter_issue_id).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>The issue has been cancelled.</i></p>"));
+
+    make_due(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_that_started_sending_cannot_be_cancelled_or_rescheduled() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    app.post_cancel_issue(newsletter_issue_id).await;
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html_page.contains("<p><i>The issue has already started sending or was cancelled.</i></p>")
+    );
+
+    app.post_reschedule_issue(newsletter_issue_id, &next_week())
+        .await;
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html_page.contains("<p><i>The issue has already started sending or was cancelled.</i></p>")
+    );
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+/// Adds confirmed subscribers straight to the database, skipping the
+/// confirmation emails.
+async fn insert_confirmed_subscribers(app: &TestApp, n: usize) {
+    for i in 0..n {
+        sqlx::query!(
+            r#"
+            INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+            VALUES ($1, $2, $3, now(), 'confirmed')
+            "#,
+            Uuid::new_v4(),
+            format!("subscriber{}@example.com", i),
+            format!("Subscriber {}", i),
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn the_worker_delivers_queued_issues_in_batches() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, 3).await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 0, "Message": "OK" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body.len(), 3);
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(
+        status_counts(&html_page),
+        vec!["Queued: 0", "Sent: 3", "Failed: 0", "Skipped: 0"]
+    );
+}
+
+#[tokio::test]
+async fn batches_are_no_larger_than_the_configured_size() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, 3).await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 0, "Message": "OK" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    try_execute_delivery(
+        &app.db_pool,
+        &app.email_client,
+        &SendRateLimiter::unlimited(),
+        2,
+        app.base_url.as_str(),
+        &app.hmac_secret,
+    )
+    .await
+    .unwrap();
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body.len(), 2);
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(
+        status_counts(&html_page),
+        vec!["Queued: 1", "Sent: 2", "Failed: 0", "Skipped: 0"]
+    );
+}
+
+#[tokio::test]
+async fn rejected_messages_do_not_fail_the_rest_of_the_batch() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, 3).await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            { "ErrorCode": 0, "Message": "OK" },
+            { "ErrorCode": 406, "Message": "Inactive recipient." },
+            { "ErrorCode": 0, "Message": "OK" },
+        ])))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(
+        status_counts(&html_page),
+        vec!["Queued: 0", "Sent: 2", "Failed: 1", "Skipped: 0"]
+    );
+    let failure = sqlx::query!("SELECT last_error FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(failure.last_error.contains("Inactive recipient."));
+}
+
+#[tokio::test]
+async fn rate_limited_deliveries_are_postponed_without_using_up_a_retry() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let task = sqlx::query!(
+        r#"SELECT n_retries, execute_after > now() AS "postponed!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(task.n_retries, 0);
+    assert!(task.postponed);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Wait for the delay asked for by `Retry-After`.
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    app.dispatch_all_pending_emails().await;
+
+    let outcomes: Vec<_> = sqlx::query!(
+        "SELECT outcome FROM issue_delivery_log WHERE newsletter_issue_id = $1 ORDER BY log_id",
+        newsletter_issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.outcome)
+    .collect();
+    assert_eq!(outcomes, vec!["rate_limited", "sent"]);
+}
+
+#[tokio::test]
+async fn delivery_status_reports_the_sending_throughput() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    let newsletter_issue_id = publish_issue(&app).await;
+
+    let html_page = app
+        .get_issue_delivery_status(newsletter_issue_id)
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html_page.contains(r#"<p id="throughput">Sending 0.0 emails per minute"#));
+    assert!(html_page.contains("at most 50 per second and 100000 per hour"));
+}
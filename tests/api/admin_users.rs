@@ -0,0 +1,283 @@
+use secrecy::Secret;
+use uuid::Uuid;
+use zero2prod::authentication::{change_password, create_user, disable_user, Role};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn created_users_can_log_in() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    create_user(
+        &username,
+        Secret::new(password.clone()),
+        Role::Editor,
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn usernames_must_be_unique() {
+    let app = spawn_app().await;
+
+    let outcome = create_user(
+        &app.test_user.username,
+        Secret::new(Uuid::new_v4().to_string()),
+        Role::Viewer,
+        &app.db_pool,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+}
+
+#[tokio::test]
+async fn disabled_users_cannot_log_in() {
+    let app = spawn_app().await;
+
+    assert!(disable_user(app.test_user.user_id, &app.db_pool)
+        .await
+        .unwrap());
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+}
+
+#[tokio::test]
+async fn disabling_a_user_ends_their_sessions() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    disable_user(app.test_user.user_id, &app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    // The session is gone for good.
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn disabling_a_user_twice_is_a_no_op() {
+    let app = spawn_app().await;
+
+    disable_user(app.test_user.user_id, &app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(!disable_user(app.test_user.user_id, &app.db_pool)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn users_can_log_in_with_a_reset_password() {
+    let app = spawn_app().await;
+    let new_password = Uuid::new_v4().to_string();
+
+    change_password(
+        app.test_user.user_id,
+        Secret::new(new_password.clone()),
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn viewers_can_look_around_but_cannot_publish() {
+    let app = spawn_app().await;
+    let viewer = app.create_user_with_role("viewer").await;
+    app.login(&viewer).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {} (viewer)", viewer.username)));
+    assert!(!html_page.contains(r#"href="/admin/newsletters""#));
+    assert_eq!(app.get_delivery_failures().await.status().as_u16(), 200);
+
+    let response = app
+        .post_form_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>HTML body!</p>",
+            "text_content": "Plain text body",
+            "idempotency_key": Uuid::new_v4().to_string(),
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    let response = app
+        .post_create_draft(&serde_json::json!({
+            "title": "Draft title",
+            "html_content": "<p>HTML body!</p>",
+            "text_content": "Plain text body",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn viewers_cannot_publish_through_the_api() {
+    let app = spawn_app().await;
+    let viewer = app.create_user_with_role("viewer").await;
+
+    let response = app
+        .api_client
+        .post(&format!("{}/newsletters", app.address))
+        .basic_auth(&viewer.username, Some(&viewer.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_users() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(r#"href="/admin/newsletters""#));
+    assert!(!html_page.contains(r#"href="/admin/users""#));
+    assert_eq!(app.get_users().await.status().as_u16(), 403);
+    let response = app
+        .post_change_user_role(app.test_user.user_id, "viewer")
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn owners_can_create_users_from_the_dashboard() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_create_user(&serde_json::json!({
+            "username": username,
+            "password": password,
+            "role": "editor",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>The user {} has been created.</i></p>",
+        username
+    )));
+    assert!(html_page.contains(&format!("<td>{}</td>", username)));
+
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {} (editor)", username)));
+}
+
+#[tokio::test]
+async fn usernames_already_taken_are_rejected() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let response = app
+        .post_create_user(&serde_json::json!({
+            "username": app.test_user.username,
+            "password": Uuid::new_v4().to_string(),
+            "role": "viewer",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The username is already taken.</i></p>"));
+}
+
+#[tokio::test]
+async fn owners_can_change_the_role_of_other_users() {
+    let app = spawn_app().await;
+    let viewer = app.create_user_with_role("viewer").await;
+    app.login_with_test_user().await;
+
+    let response = app.post_change_user_role(viewer.user_id, "editor").await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The role has been changed.</i></p>"));
+
+    app.post_logout().await;
+    app.login(&viewer).await;
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {} (editor)", viewer.username)));
+}
+
+#[tokio::test]
+async fn owners_can_deactivate_other_users() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login_with_test_user().await;
+
+    let response = app.post_deactivate_user(editor.user_id).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The user has been deactivated.</i></p>"));
+
+    app.post_logout().await;
+    let response = app.login(&editor).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn owners_cannot_demote_or_deactivate_themselves() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    app.post_change_user_role(app.test_user.user_id, "viewer")
+        .await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot change your own role.</i></p>"));
+
+    app.post_deactivate_user(app.test_user.user_id).await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>You cannot deactivate yourself.</i></p>"));
+
+    assert_eq!(app.get_admin_dashboard().await.status().as_u16(), 200);
+}
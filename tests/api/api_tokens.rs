@@ -0,0 +1,269 @@
+use uuid::Uuid;
+use zero2prod::authentication::{create_api_token, ApiScope};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+/// Creates an API token for the test user, then logs them out.
+async fn test_user_api_token(app: &TestApp) -> String {
+    app.login_with_test_user().await;
+    let token = app.create_api_token().await;
+    app.post_logout().await;
+    token
+}
+
+async fn only_api_token_id(app: &TestApp) -> Uuid {
+    sqlx::query_scalar!("SELECT api_token_id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn api_tokens_can_publish_newsletters() {
+    let app = spawn_app().await;
+    let token = test_user_api_token(&app).await;
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let last_used_at = sqlx::query_scalar!("SELECT last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(last_used_at.is_some());
+}
+
+#[tokio::test]
+async fn api_tokens_are_stored_hashed() {
+    let app = spawn_app().await;
+    let token = test_user_api_token(&app).await;
+
+    let token_hash = sqlx::query_scalar!("SELECT token_hash FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(token.starts_with("z2p_"));
+    assert_ne!(token_hash, token);
+}
+
+#[tokio::test]
+async fn api_tokens_are_listed_without_their_value() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let token = app.create_api_token().await;
+
+    let html_page = app.get_api_tokens_html().await;
+
+    assert!(html_page.contains("<td>Test script</td>"));
+    assert!(html_page.contains("<td>newsletters:publish, newsletters:read</td>"));
+    assert!(html_page.contains("<td>never</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn unknown_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters_with_token("z2p_not-a-token", newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        r#"Bearer realm="publish", error="invalid_token""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn revoked_api_tokens_are_rejected() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let token = app.create_api_token().await;
+
+    let response = app
+        .post_revoke_api_token(only_api_token_id(&app).await)
+        .await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The token has been revoked.</i></p>"));
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_the_api_tokens_of_others() {
+    let app = spawn_app().await;
+    let token = test_user_api_token(&app).await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+
+    let response = app
+        .post_revoke_api_token(only_api_token_id(&app).await)
+        .await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn api_tokens_of_disabled_users_are_rejected() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+    let token = app.create_api_token().await;
+    app.post_logout().await;
+    app.login_with_test_user().await;
+    app.post_deactivate_user(editor.user_id).await;
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn api_tokens_act_with_the_role_of_their_user() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+    let token = app.create_api_token().await;
+    app.post_logout().await;
+    app.login_with_test_user().await;
+    app.post_change_user_role(editor.user_id, "viewer").await;
+
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn api_tokens_without_the_publish_scope_are_rejected() {
+    let app = spawn_app().await;
+    let token = create_api_token(app.test_user.user_id, "No scopes", &[], &app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_newsletters_with_token(
+            secrecy::ExposeSecret::expose_secret(&token),
+            newsletter_request_body(),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(
+        r#"Bearer realm="publish", error="insufficient_scope", scope="newsletters:publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn api_tokens_need_a_name_and_a_scope() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let response = app
+        .post_create_api_token(&[("name", " "), ("scope", "newsletters:publish")])
+        .await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The token needs a name.</i></p>"));
+
+    let response = app.post_create_api_token(&[("name", "Test script")]).await;
+    assert_is_redirect_to(&response, "/admin/api-tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The token needs at least one scope.</i></p>"));
+}
+
+#[tokio::test]
+async fn viewers_cannot_manage_api_tokens() {
+    let app = spawn_app().await;
+    let viewer = app.create_user_with_role("viewer").await;
+    app.login(&viewer).await;
+
+    let response = app.get_api_tokens().await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn requests_without_credentials_are_offered_both_schemes() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(&format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body())
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+    let challenges: Vec<_> = response
+        .headers()
+        .get_all("WWW-Authenticate")
+        .iter()
+        .collect();
+    assert_eq!(
+        challenges,
+        [r#"Basic realm="publish""#, r#"Bearer realm="publish""#]
+    );
+}
+
+#[tokio::test]
+async fn following_an_issue_requires_the_read_scope() {
+    let app = spawn_app().await;
+    let token = create_api_token(
+        app.test_user.user_id,
+        "Publish only",
+        &[ApiScope::PublishNewsletters],
+        &app.db_pool,
+    )
+    .await
+    .unwrap();
+    let token = secrecy::ExposeSecret::expose_secret(&token);
+
+    let response = app
+        .post_newsletters_with_token(token, newsletter_request_body())
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    let response = app
+        .api_client
+        .get(
+            body["status_url"]
+                .as_str()
+                .unwrap()
+                .replace(app.base_url.as_str().trim_end_matches('/'), &app.address),
+        )
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
@@ -0,0 +1,235 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::issue_delivery_worker::{
+    list_queued_deliveries, requeue_delivery_failures, MAX_RETRIES,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Publishes an issue to a single confirmed subscriber without delivering it.
+async fn enqueue_issue(app: &TestApp) {
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_form_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+/// Attempts every due delivery with the email API answering `status_code`.
+async fn dispatch_with_status(app: &TestApp, status_code: u16) {
+    let _guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(status_code))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+struct QueuedTask {
+    n_retries: i16,
+    is_due: bool,
+}
+
+async fn queued_tasks(app: &TestApp) -> Vec<QueuedTask> {
+    sqlx::query_as!(
+        QueuedTask,
+        r#"
+        SELECT n_retries, execute_after <= now() as "is_due!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn failed_deliveries(app: &TestApp) -> Vec<i16> {
+    sqlx::query!("SELECT n_retries FROM issue_delivery_failures")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.n_retries)
+        .collect()
+}
+
+#[tokio::test]
+async fn transient_failures_are_rescheduled() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+
+    dispatch_with_status(&app, 500).await;
+
+    let tasks = queued_tasks(&app).await;
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].n_retries, 1);
+    assert!(!tasks[0].is_due);
+    assert!(failed_deliveries(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn rate_limited_deliveries_are_rescheduled() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+
+    dispatch_with_status(&app, 429).await;
+
+    let tasks = queued_tasks(&app).await;
+    assert_eq!(tasks.len(), 1);
+    // Being throttled does not use up a retry.
+    assert_eq!(tasks[0].n_retries, 0);
+    assert!(!tasks[0].is_due);
+}
+
+#[tokio::test]
+async fn rescheduled_deliveries_are_retried_once_due() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    dispatch_with_status(&app, 500).await;
+
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    dispatch_with_status(&app, 200).await;
+
+    assert!(queued_tasks(&app).await.is_empty());
+    assert!(failed_deliveries(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn deliveries_exhausting_their_retries_are_moved_to_failures() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_retries = $1",
+        MAX_RETRIES
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    dispatch_with_status(&app, 500).await;
+
+    assert!(queued_tasks(&app).await.is_empty());
+    assert_eq!(failed_deliveries(&app).await, vec![MAX_RETRIES]);
+}
+
+#[tokio::test]
+async fn permanent_failures_are_moved_to_failures_without_retrying() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+
+    dispatch_with_status(&app, 422).await;
+
+    assert!(queued_tasks(&app).await.is_empty());
+    assert_eq!(failed_deliveries(&app).await, vec![0]);
+}
+
+#[tokio::test]
+async fn unauthenticated_request_to_delivery_failures_should_bounce() {
+    let app = spawn_app().await;
+
+    let response = app.get_delivery_failures().await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn delivery_failures_are_listed_on_the_admin_page() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    dispatch_with_status(&app, 422).await;
+
+    let html_page = app.get_delivery_failures_html().await;
+
+    let page_document = scraper::Html::parse_document(&html_page);
+    let requeue_selector = scraper::Selector::parse(
+        r#"form[action="/admin/delivery-failures/requeue"] input[name="subscriber_email"]"#,
+    )
+    .unwrap();
+    let emails: Vec<_> = page_document
+        .select(&requeue_selector)
+        .map(|input| input.value().attr("value").unwrap().to_owned())
+        .collect();
+    assert_eq!(emails, vec!["example@gmail.com"]);
+}
+
+#[tokio::test]
+async fn requeued_failures_are_delivered_again() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    dispatch_with_status(&app, 422).await;
+    let newsletter_issue_id =
+        sqlx::query!("SELECT newsletter_issue_id FROM issue_delivery_failures")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .newsletter_issue_id;
+
+    let response = app
+        .post_requeue_delivery_failure(&serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "subscriber_email": "example@gmail.com",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/delivery-failures");
+
+    let html_page = app.get_delivery_failures_html().await;
+    assert!(html_page.contains("<p><i>The delivery has been requeued.</i></p>"));
+    assert!(failed_deliveries(&app).await.is_empty());
+
+    let tasks = queued_tasks(&app).await;
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].n_retries, 0);
+    dispatch_with_status(&app, 200).await;
+    assert!(queued_tasks(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn failures_can_be_requeued_in_bulk() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    dispatch_with_status(&app, 422).await;
+    assert_eq!(failed_deliveries(&app).await.len(), 1);
+
+    // Failures of other issues are left alone.
+    let requeued = requeue_delivery_failures(&app.db_pool, Some(Uuid::new_v4()))
+        .await
+        .unwrap();
+    assert_eq!(requeued, 0);
+
+    let requeued = requeue_delivery_failures(&app.db_pool, None).await.unwrap();
+    assert_eq!(requeued, 1);
+    assert!(failed_deliveries(&app).await.is_empty());
+    let tasks = queued_tasks(&app).await;
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].n_retries, 0);
+}
+
+#[tokio::test]
+async fn queued_deliveries_are_listed_earliest_due_first() {
+    let app = spawn_app().await;
+    enqueue_issue(&app).await;
+    dispatch_with_status(&app, 500).await;
+
+    let deliveries = list_queued_deliveries(&app.db_pool, 10).await.unwrap();
+
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].title, "Newsletter title");
+    assert_eq!(deliveries[0].subscriber_email, "example@gmail.com");
+    assert_eq!(deliveries[0].n_retries, 1);
+    assert!(deliveries[0].due_at > chrono::Utc::now());
+}
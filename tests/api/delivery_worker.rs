@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{
+    issue_delivery_worker::{run_workers_until, try_execute_delivery},
+    send_rate_limiter::SendRateLimiter,
+};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish_issue(app: &TestApp) {
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    });
+    app.post_form_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn n_queued_tasks(app: &TestApp) -> i64 {
+    sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+async fn wait_for_email_requests(app: &TestApp, n: usize) {
+    for _ in 0..100 {
+        if app.email_server.received_requests().await.unwrap().len() >= n {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("The workers did not send {} emails in time.", n);
+}
+
+#[tokio::test]
+async fn the_worker_pool_delivers_queued_issues() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    publish_issue(&app).await;
+    let n_requests_before = app.email_server.received_requests().await.unwrap().len();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (shutdown_sender, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let mut configuration = app.configuration.clone();
+    configuration.worker.concurrency = 3;
+    let workers = tokio::spawn(run_workers_until(configuration, async {
+        let _ = shutdown.await;
+    }));
+
+    wait_for_email_requests(&app, n_requests_before + 1).await;
+    shutdown_sender.send(()).unwrap();
+
+    workers.await.unwrap().unwrap();
+    assert_eq!(n_queued_tasks(&app).await, 0);
+}
+
+#[tokio::test]
+async fn shutting_down_lets_in_flight_deliveries_finish() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+    publish_issue(&app).await;
+    let n_requests_before = app.email_server.received_requests().await.unwrap().len();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(150)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (shutdown_sender, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let workers = tokio::spawn(run_workers_until(app.configuration.clone(), async {
+        let _ = shutdown.await;
+    }));
+
+    // Stop the workers while the email is still being sent.
+    wait_for_email_requests(&app, n_requests_before + 1).await;
+    shutdown_sender.send(()).unwrap();
+
+    workers.await.unwrap().unwrap();
+    assert_eq!(n_queued_tasks(&app).await, 0);
+    let outcome = sqlx::query!("SELECT outcome FROM issue_delivery_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .outcome;
+    assert_eq!(outcome, "sent");
+}
+
+#[tokio::test]
+async fn idle_workers_wake_up_as_soon_as_an_issue_is_published() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (shutdown_sender, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let mut configuration = app.configuration.clone();
+    // Long enough for the test to time out if the workers only polled.
+    configuration.worker.idle_poll_interval_milliseconds = 60_000;
+    let workers = tokio::spawn(run_workers_until(configuration, async {
+        let _ = shutdown.await;
+    }));
+    // Let the workers find the queue empty and go idle.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let n_requests_before = app.email_server.received_requests().await.unwrap().len();
+    publish_issue(&app).await;
+
+    wait_for_email_requests(&app, n_requests_before + 1).await;
+    shutdown_sender.send(()).unwrap();
+    workers.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn failed_delivery_attempts_give_the_send_budget_back() {
+    let app = spawn_app().await;
+    let mut settings = app.configuration.worker.clone();
+    settings.max_sends_per_second = None;
+    settings.max_sends_per_hour = Some(10);
+    let rate_limiter = SendRateLimiter::new(&settings);
+    sqlx::query!("ALTER TABLE issue_delivery_queue RENAME TO broken_queue")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let outcome = try_execute_delivery(
+        &app.db_pool,
+        &app.email_client,
+        &rate_limiter,
+        settings.delivery_batch_size,
+        app.base_url.as_str(),
+        &app.hmac_secret,
+    )
+    .await;
+
+    assert!(outcome.is_err());
+    assert_eq!(rate_limiter.try_acquire(100), Ok(10));
+}
@@ -1,3 +1,5 @@
+use zero2prod::startup::WorkerHealthCheck;
+
 use crate::helpers::spawn_app;
 
 #[tokio::test]
@@ -18,3 +20,36 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn worker_health_check_works() {
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.worker.health_check_port = 0;
+    let health_check = WorkerHealthCheck::build(&configuration).unwrap();
+    let address = format!("http://127.0.0.1:{}", health_check.port());
+    tokio::spawn(health_check.run_until_stopped());
+
+    let response = reqwest::get(&format!("{}/health_check", address))
+        .await
+        .expect("Failed to execute request");
+
+    assert!(response.status().is_success());
+}
+
+#[tokio::test]
+async fn worker_health_check_fails_when_the_database_is_unreachable() {
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.worker.health_check_port = 0;
+    configuration.database.port = 1;
+    let health_check = WorkerHealthCheck::build(&configuration).unwrap();
+    let address = format!("http://127.0.0.1:{}", health_check.port());
+    tokio::spawn(health_check.run_until_stopped());
+
+    let response = reqwest::get(&format!("{}/health_check", address))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status().as_u16(), 503);
+}
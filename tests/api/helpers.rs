@@ -2,6 +2,7 @@ use argon2::{password_hash::SaltString, Argon2, Params, PasswordHasher};
 use once_cell::sync::Lazy;
 
 use reqwest::{Response, Url};
+use secrecy::Secret;
 use serde_json::Value;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
@@ -10,9 +11,12 @@ use wiremock::{
     Mock, MockServer, ResponseTemplate,
 };
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    authentication::totp_code,
+    configuration::{get_configuration, DatabaseSettings, Settings},
+    domain::SignupFormToken,
     email_client::EmailAPIClient,
     issue_delivery_worker::{try_execute_delivery, ExecutionOutcome},
+    send_rate_limiter::SendRateLimiter,
     startup::{get_connection_pool, Application},
     telemetry::{get_subscriber, init_subscriber},
 };
@@ -22,6 +26,16 @@ pub struct ConfirmationLinks {
     pub plain_text: reqwest::Url,
 }
 
+impl ConfirmationLinks {
+    pub fn subscription_token(&self) -> String {
+        self.html
+            .query_pairs()
+            .find(|(name, _)| name == "subscription_token")
+            .map(|(_, value)| value.into_owned())
+            .unwrap()
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub api_client: reqwest::Client,
@@ -31,39 +45,179 @@ pub struct TestApp {
     pub port: u16,
     pub test_user: TestUser,
     pub email_client: EmailAPIClient,
+    pub hmac_secret: Secret<String>,
+    pub configuration: Settings,
 }
 
 impl TestApp {
     pub async fn login_with_test_user(&self) -> Response {
+        self.login(&self.test_user).await
+    }
+
+    pub async fn login(&self, user: &TestUser) -> Response {
         let login_body = serde_json::json!({
-            "username": &self.test_user.username,
-            "password": &self.test_user.password,
+            "username": &user.username,
+            "password": &user.password,
         });
 
         // Login
         self.post_login(&login_body).await
     }
 
-    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+    /// Stores another user, besides the test user who is an owner.
+    pub async fn create_user_with_role(&self, role: &str) -> TestUser {
+        let user = TestUser::generate(role);
+        user.store(&self.db_pool).await;
+        user
+    }
+
+    /// Extracts the only link in an email body, pointing it to the test app.
+    fn get_link(&self, s: &str) -> Url {
+        let links = self.get_links(s);
+        assert_eq!(links.len(), 1);
+        links.into_iter().next().unwrap()
+    }
+
+    /// Extracts every link in an email body, pointing them to the test app.
+    fn get_links(&self, s: &str) -> Vec<Url> {
+        linkify::LinkFinder::new()
+            .links(s)
+            .filter(|link| *link.kind() == linkify::LinkKind::Url)
+            .map(|link| {
+                let mut link = Url::parse(link.as_str()).unwrap();
+                link.set_port(Some(self.port)).unwrap();
+                link
+            })
+            .collect()
+    }
+
+    /// Extracts the link to `path` from both bodies of an email, checking
+    /// they agree.
+    fn get_link_to(&self, email_request: &wiremock::Request, path: &str) -> Url {
         let email_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        let get_link = |s: &str| {
-            let links: Vec<_> = linkify::LinkFinder::new()
-                .links(s)
-                .filter(|link| *link.kind() == linkify::LinkKind::Url)
+        let [html, plain_text] = ["HtmlBody", "TextBody"].map(|body| {
+            let links: Vec<_> = self
+                .get_links(email_body[body].as_str().unwrap())
+                .into_iter()
+                .filter(|link| link.path() == path)
                 .collect();
             assert_eq!(links.len(), 1);
-            let raw_confirmation_link = links[0].as_str().to_owned();
-            let mut confirmation_link = Url::parse(&raw_confirmation_link).unwrap();
-            confirmation_link.set_port(Some(self.port)).unwrap();
-            confirmation_link
-        };
+            links.into_iter().next().unwrap()
+        });
+        assert_eq!(html, plain_text);
+        html
+    }
+
+    pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        let email_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        let html = get_link(email_body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(email_body["TextBody"].as_str().unwrap());
+        let html = self.get_link(email_body["HtmlBody"].as_str().unwrap());
+        let plain_text = self.get_link(email_body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
+    pub fn get_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+        self.get_link_to(email_request, "/subscriptions/unsubscribe")
+    }
+
+    pub fn get_preferences_link(&self, email_request: &wiremock::Request) -> Url {
+        self.get_link_to(email_request, "/subscriptions/preferences")
+    }
+
+    /// Returns the value of a custom header attached to the email.
+    pub fn get_email_header(&self, email_request: &wiremock::Request, name: &str) -> String {
+        let email_body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+        email_body["Headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|header| header["Name"] == name)
+            .and_then(|header| header["Value"].as_str())
+            .unwrap_or_else(|| panic!("The email has no {} header.", name))
+            .to_owned()
+    }
+
+    /// Extracts the one-click unsubscribe link from the `List-Unsubscribe`
+    /// header, pointing it to the test app.
+    pub fn get_one_click_unsubscribe_link(&self, email_request: &wiremock::Request) -> Url {
+        let header = self.get_email_header(email_request, "List-Unsubscribe");
+        let raw_link = header
+            .strip_prefix('<')
+            .and_then(|h| h.strip_suffix('>'))
+            .expect("List-Unsubscribe should be enclosed in angle brackets.");
+        let mut link = Url::parse(raw_link).unwrap();
+        link.set_port(Some(self.port)).unwrap();
+        link
+    }
+
+    /// Mailbox providers send the one-click request without any cookie, so
+    /// this does not go through `api_client`.
+    pub async fn post_one_click_unsubscribe(&self, link: Url, body: &str) -> Response {
+        reqwest::Client::new()
+            .post(link)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute one-click unsubscribe request.")
+    }
+
+    pub async fn get_unsubscribe(&self, unsubscribe_link: Url) -> Response {
+        self.api_client
+            .get(unsubscribe_link)
+            .send()
+            .await
+            .expect("Failed to execute request to the unsubscribe link.")
+    }
+
+    pub async fn post_unsubscribe(&self, token: &str) -> Response {
+        self.api_client
+            .post(&format!("{}/subscriptions/unsubscribe", &self.address))
+            .form(&serde_json::json!({ "token": token }))
+            .send()
+            .await
+            .expect("Failed to execute unsubscribe request.")
+    }
+
+    pub async fn get_preferences(&self, preferences_link: Url) -> Response {
+        self.api_client
+            .get(preferences_link)
+            .send()
+            .await
+            .expect("Failed to execute request to the preferences link.")
+    }
+
+    pub async fn get_preferences_html(&self, token: &str) -> String {
+        self.api_client
+            .get(&format!("{}/subscriptions/preferences", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Submits one of the forms of the preferences page: `name`, `email` or
+    /// `pause`.
+    pub async fn post_preferences<Body>(&self, form: &str, body: &Body) -> Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!(
+                "{}/subscriptions/preferences/{}",
+                &self.address, form
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute preferences request.")
+    }
+
     pub async fn confirm_token(&self, confirmation_token: String) -> Response {
         let confirmation_link = format!(
             "{}/subscriptions/confirm?subscription_token={}",
@@ -72,12 +226,50 @@ impl TestApp {
         self.api_client.get(confirmation_link).send().await.unwrap()
     }
 
+    pub async fn post_resend_confirmation(&self, confirmation_token: &str) -> Response {
+        self.api_client
+            .post(&format!("{}/subscriptions/confirm/resend", &self.address))
+            .form(&serde_json::json!({ "subscription_token": confirmation_token }))
+            .send()
+            .await
+            .expect("Failed to execute resend confirmation request.")
+    }
+
+    /// Submits the signup form: a form token is added, as if the form had
+    /// been served long enough ago, unless `body` has one already.
     pub async fn post_subscriptions(&self, body: String) -> Response {
+        self.post_subscriptions_from(body, "127.0.0.1").await
+    }
+
+    /// Same as `post_subscriptions`, with the request forwarded by a proxy
+    /// on behalf of `client_ip`.
+    pub async fn post_subscriptions_from(&self, body: String, client_ip: &str) -> Response {
+        let body = if body.contains("form_token=") {
+            body
+        } else {
+            let min_form_fill_seconds = self
+                .configuration
+                .subscription_throttling
+                .min_form_fill_seconds as i64;
+            let served_at = chrono::Utc::now().timestamp() - min_form_fill_seconds;
+            let form_token = SignupFormToken::new(served_at, &self.hmac_secret);
+            format!("{}&form_token={}", body, form_token.as_ref())
+        };
+        self.send_subscriptions(body, client_ip).await
+    }
+
+    /// Subscribes the way API clients do, without a signup form token.
+    pub async fn post_subscriptions_without_form_token(&self, body: String) -> Response {
+        self.send_subscriptions(body, "127.0.0.1").await
+    }
+
+    async fn send_subscriptions(&self, body: String, client_ip: &str) -> Response {
         let expect_body = format!("Failed to execute subscriptions request for body {}.", body);
 
         self.api_client
             .post(&format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client_ip)
             .body(body)
             .send()
             .await
@@ -94,6 +286,28 @@ impl TestApp {
             .expect("Request failed!")
     }
 
+    pub async fn post_newsletters_with_token(&self, token: &str, body: Value) -> Response {
+        self.api_client
+            .post(&format!("{}/newsletters", self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Request failed!")
+    }
+
+    pub async fn get_newsletter_issue_status(&self, newsletter_issue_id: &str) -> Response {
+        self.api_client
+            .get(&format!(
+                "{}/newsletters/{}",
+                self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Request failed!")
+    }
+
     pub async fn post_form_newsletters(&self, body: Value) -> Response {
         self.api_client
             .post(&format!("{}/admin/newsletters", self.address))
@@ -115,6 +329,102 @@ impl TestApp {
             .expect("Failed to execute login request.")
     }
 
+    pub async fn post_setup<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/setup", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute setup request.")
+    }
+
+    pub async fn get_login_two_factor(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/login/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to the two-factor login page.")
+    }
+
+    pub async fn get_login_two_factor_html(&self) -> String {
+        self.get_login_two_factor().await.text().await.unwrap()
+    }
+
+    pub async fn post_login_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/login/two-factor", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute two-factor login request.")
+    }
+
+    pub async fn get_two_factor_settings_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to the two-factor settings.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_enable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/two-factor", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to post enable two-factor request.")
+    }
+
+    pub async fn post_disable_two_factor(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/two-factor/disable", &self.address))
+            .form(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to post disable two-factor request.")
+    }
+
+    /// Enrols an authenticator for the logged in user, returning the TOTP
+    /// secret and the recovery codes.
+    pub async fn enable_two_factor(&self) -> (Secret<String>, Vec<String>) {
+        let html_page = self.get_two_factor_settings_html().await;
+        let secret = html_page
+            .split("secret=")
+            .nth(1)
+            .and_then(|rest| rest.split('&').next())
+            .expect("No TOTP secret on the two-factor settings page.")
+            .to_string();
+        let secret = Secret::new(secret);
+
+        let response = self.post_enable_two_factor(&totp_code_in(&secret, 0)).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let html_page = response.text().await.unwrap();
+        let recovery_codes = html_page
+            .split("<li><code>")
+            .skip(1)
+            .map(|s| s.split("</code>").next().unwrap().to_string())
+            .collect();
+        (secret, recovery_codes)
+    }
+
+    pub async fn get_home_html(&self) -> String {
+        self.api_client
+            .get(&self.address)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
             .get(&format!("{}/login", &self.address))
@@ -166,6 +476,292 @@ impl TestApp {
             .unwrap()
     }
 
+    pub async fn get_issue_delivery_status(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request to get the issue delivery status.")
+    }
+
+    pub async fn post_reschedule_issue(
+        &self,
+        newsletter_issue_id: Uuid,
+        scheduled_for: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/{}/reschedule",
+                &self.address, newsletter_issue_id
+            ))
+            .form(&serde_json::json!({ "scheduled_for": scheduled_for }))
+            .send()
+            .await
+            .expect("Failed to post reschedule issue request.")
+    }
+
+    pub async fn post_cancel_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/{}/cancel",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to post cancel issue request.")
+    }
+
+    pub async fn get_drafts_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/newsletters/drafts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to list drafts.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_draft(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/newsletters/drafts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post create draft request.")
+    }
+
+    pub async fn get_edit_draft(&self, draft_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request to get the edit draft form.")
+    }
+
+    pub async fn post_update_draft(
+        &self,
+        draft_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/drafts/{}",
+                &self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post update draft request.")
+    }
+
+    pub async fn get_preview_draft(&self, draft_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/newsletters/drafts/{}/preview",
+                &self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request to preview a draft.")
+    }
+
+    pub async fn post_delete_draft(&self, draft_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/drafts/{}/delete",
+                &self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to post delete draft request.")
+    }
+
+    pub async fn post_publish_draft(
+        &self,
+        draft_id: Uuid,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/drafts/{}/publish",
+                &self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post publish draft request.")
+    }
+
+    pub async fn post_send_test_issue(
+        &self,
+        draft_id: Uuid,
+        recipients: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/drafts/{}/test",
+                &self.address, draft_id
+            ))
+            .form(&serde_json::json!({ "recipients": recipients }))
+            .send()
+            .await
+            .expect("Failed to post send test issue request.")
+    }
+
+    pub async fn post_send_test_issue_from_form<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/newsletters/test", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post send test issue request.")
+    }
+
+    pub async fn get_delivery_failures(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/delivery-failures", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to get the delivery failures page.")
+    }
+
+    pub async fn get_delivery_failures_html(&self) -> String {
+        self.get_delivery_failures().await.text().await.unwrap()
+    }
+
+    pub async fn post_requeue_delivery_failure<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!(
+                "{}/admin/delivery-failures/requeue",
+                &self.address
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post requeue delivery failure request.")
+    }
+
+    pub async fn get_users(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to get the users page.")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.get_users().await.text().await.unwrap()
+    }
+
+    pub async fn post_create_user<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/users", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to post create user request.")
+    }
+
+    pub async fn post_change_user_role(&self, user_id: Uuid, role: &str) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/users/{}/role", &self.address, user_id))
+            .form(&serde_json::json!({ "role": role }))
+            .send()
+            .await
+            .expect("Failed to post change user role request.")
+    }
+
+    pub async fn post_deactivate_user(&self, user_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/users/{}/deactivate",
+                &self.address, user_id
+            ))
+            .send()
+            .await
+            .expect("Failed to post deactivate user request.")
+    }
+
+    pub async fn post_reset_two_factor(&self, user_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/users/{}/reset-two-factor",
+                &self.address, user_id
+            ))
+            .send()
+            .await
+            .expect("Failed to post reset two-factor request.")
+    }
+
+    pub async fn get_api_tokens(&self) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/admin/api-tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request to get the API tokens page.")
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.get_api_tokens().await.text().await.unwrap()
+    }
+
+    /// `fields` are sent in order, so that `scope` can be repeated.
+    pub async fn post_create_api_token(&self, fields: &[(&str, &str)]) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/admin/api-tokens", &self.address))
+            .form(fields)
+            .send()
+            .await
+            .expect("Failed to post create API token request.")
+    }
+
+    /// Creates an API token with every scope for the logged in user.
+    pub async fn create_api_token(&self) -> String {
+        let html_page = self
+            .post_create_api_token(&[
+                ("name", "Test script"),
+                ("scope", "newsletters:publish"),
+                ("scope", "newsletters:read"),
+            ])
+            .await
+            .text()
+            .await
+            .unwrap();
+        html_page
+            .split("<code>")
+            .nth(1)
+            .and_then(|rest| rest.split("</code>").next())
+            .expect("No API token on the page.")
+            .to_string()
+    }
+
+    pub async fn post_revoke_api_token(&self, api_token_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/api-tokens/{}/revoke",
+                &self.address, api_token_id
+            ))
+            .send()
+            .await
+            .expect("Failed to post revoke API token request.")
+    }
+
     pub async fn post_change_password<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -221,14 +817,24 @@ impl TestApp {
             .unwrap();
     }
 
+    /// Delivers every due task, stopping early if the email API throttles us.
     pub async fn dispatch_all_pending_emails(&self) {
+        let base_url = self.base_url.as_str().trim_end_matches('/');
+        let rate_limiter = SendRateLimiter::unlimited();
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_delivery(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            match try_execute_delivery(
+                &self.db_pool,
+                &self.email_client,
+                &rate_limiter,
+                self.configuration.worker.delivery_batch_size,
+                base_url,
+                &self.hmac_secret,
+            )
+            .await
+            .unwrap()
             {
-                break;
+                ExecutionOutcome::EmptyQueue | ExecutionOutcome::Throttled(_) => break,
+                ExecutionOutcome::TaskCompleted => {}
             }
         }
     }
@@ -238,14 +844,16 @@ pub struct TestUser {
     pub password: String,
     pub username: String,
     pub user_id: Uuid,
+    pub role: String,
 }
 
 impl TestUser {
-    fn generate() -> Self {
+    fn generate(role: &str) -> Self {
         TestUser {
             password: Uuid::new_v4().to_string(),
             username: Uuid::new_v4().to_string(),
             user_id: Uuid::new_v4(),
+            role: role.to_string(),
         }
     }
 
@@ -263,11 +871,12 @@ impl TestUser {
 
         sqlx::query!(
             "
-        INSERT INTO users (user_id, username, password_hash)
-        VALUES ($1, $2, $3)",
+        INSERT INTO users (user_id, username, password_hash, role)
+        VALUES ($1, $2, $3, $4)",
             self.user_id,
             self.username,
             password_hash,
+            self.role,
         )
         .execute(pool)
         .await
@@ -275,6 +884,15 @@ impl TestUser {
     }
 }
 
+/// The code an authenticator shows for `secret`, `offset_seconds` from now.
+pub fn totp_code_in(secret: &Secret<String>, offset_seconds: i64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    totp_code(secret, now.saturating_add_signed(offset_seconds)).unwrap()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -296,6 +914,11 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
 
         c.email_client.api_base_url = email_server.uri();
+        // Tests share Redis: keep their failed login counters apart.
+        c.login_throttling.redis_key_prefix = Uuid::new_v4().to_string();
+        c.subscription_throttling.redis_key_prefix = Uuid::new_v4().to_string();
+        // Tests stand in for the proxy to pick the client IP.
+        c.application.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
         c
     };
 
@@ -323,8 +946,10 @@ pub async fn spawn_app() -> TestApp {
         db_pool: get_connection_pool(&configuration.database),
         email_server,
         port: application_port,
-        test_user: TestUser::generate(),
-        email_client: configuration.email_client.client(),
+        test_user: TestUser::generate("owner"),
+        email_client: configuration.email_client.clone().client(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        configuration,
     };
     test_app.test_user.store(&test_app.db_pool).await;
 
@@ -1,4 +1,6 @@
-use crate::helpers::{assert_is_redirect_to, spawn_app};
+use uuid::Uuid;
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -36,3 +38,165 @@ async fn redirect_to_admin_dashboard_after_login_success() {
     let html_page = app.get_admin_dashboard_html().await;
     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
 }
+
+/// Fails to log in as `username` `n` times, returning the last response.
+async fn fail_to_log_in(app: &TestApp, username: &str, n: usize) -> reqwest::Response {
+    let mut response = None;
+    for _ in 0..n {
+        response = Some(
+            app.post_login(&serde_json::json!({
+                "username": username,
+                "password": "wrong-password"
+            }))
+            .await,
+        );
+    }
+    response.unwrap()
+}
+
+#[tokio::test]
+async fn users_are_locked_out_after_repeated_failures() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_user as usize;
+    let lockout_message = format!(
+        "<p><i>Too many failed login attempts. Please try again in {} seconds.</i></p>",
+        app.configuration.login_throttling.base_lockout_seconds
+    );
+
+    fail_to_log_in(&app, &app.test_user.username, max_failures - 1).await;
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+
+    let response = fail_to_log_in(&app, &app.test_user.username, 1).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(&lockout_message), "{}", html_page);
+
+    // Even the right password is turned away during the lockout.
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts."));
+}
+
+#[tokio::test]
+async fn a_successful_login_resets_the_failure_count() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_user as usize;
+
+    fail_to_log_in(&app, &app.test_user.username, max_failures - 1).await;
+    app.login_with_test_user().await;
+    fail_to_log_in(&app, &app.test_user.username, max_failures - 1).await;
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn client_ips_are_locked_out_after_failures_across_usernames() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_ip as usize;
+
+    for _ in 0..max_failures {
+        fail_to_log_in(&app, &Uuid::new_v4().to_string(), 1).await;
+    }
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts."));
+}
+
+#[tokio::test]
+async fn forwarded_client_ips_are_locked_out_separately() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_ip as usize;
+    let post_login_from = |client_ip: &'static str, username: String, password: String| {
+        app.api_client
+            .post(&format!("{}/login", &app.address))
+            .header("X-Forwarded-For", client_ip)
+            .form(&serde_json::json!({ "username": username, "password": password }))
+            .send()
+    };
+
+    for _ in 0..max_failures {
+        post_login_from(
+            "203.0.113.1",
+            Uuid::new_v4().to_string(),
+            "wrong-password".into(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let response = post_login_from(
+        "203.0.113.2",
+        app.test_user.username.clone(),
+        app.test_user.password.clone(),
+    )
+    .await
+    .unwrap();
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let events = sqlx::query!("SELECT client_ip FROM audit_log WHERE event = 'login_lockout'")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].client_ip.as_deref(), Some("203.0.113.1"));
+}
+
+#[tokio::test]
+async fn client_supplied_forwarded_ips_do_not_escape_lockouts() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_ip as usize;
+    // The client writes the leftmost entry, the proxy appends the address it
+    // got the request from.
+    let post_login_spoofing = |spoofed_ip: String, username: String, password: String| {
+        app.api_client
+            .post(&format!("{}/login", &app.address))
+            .header("X-Forwarded-For", format!("{}, 203.0.113.1", spoofed_ip))
+            .form(&serde_json::json!({ "username": username, "password": password }))
+            .send()
+    };
+
+    for i in 0..max_failures {
+        post_login_spoofing(
+            format!("198.51.100.{}", i),
+            Uuid::new_v4().to_string(),
+            "wrong-password".into(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let response = post_login_spoofing(
+        "198.51.100.250".into(),
+        app.test_user.username.clone(),
+        app.test_user.password.clone(),
+    )
+    .await
+    .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts."));
+}
+
+#[tokio::test]
+async fn lockouts_are_recorded_in_the_audit_log() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_user as usize;
+
+    fail_to_log_in(&app, &app.test_user.username, max_failures).await;
+
+    let events = sqlx::query!("SELECT event, username, client_ip FROM audit_log")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event, "login_lockout");
+    assert_eq!(
+        events[0].username.as_deref(),
+        Some(app.test_user.username.as_str())
+    );
+    assert_eq!(events[0].client_ip.as_deref(), Some("127.0.0.1"));
+}
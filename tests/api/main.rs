@@ -1,10 +1,20 @@
 mod admin_dashboard;
+mod admin_drafts;
 mod admin_newsletter;
 mod admin_password;
+mod admin_users;
+mod api_tokens;
 mod change_password;
+mod delivery_failures;
+mod delivery_worker;
 mod health_check;
 mod helpers;
 mod login;
+mod maintenance;
 mod newsletter;
+mod setup;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_preferences;
+mod subscriptions_unsubscribe;
+mod two_factor;
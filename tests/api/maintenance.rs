@@ -0,0 +1,173 @@
+use serde_json::json;
+use zero2prod::maintenance::{purge_stale_rows, PurgeReport};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn purge(app: &TestApp) -> PurgeReport {
+    purge_stale_rows(&app.db_pool, &app.configuration.maintenance)
+        .await
+        .unwrap()
+}
+
+/// Moves everything about the current subscriptions `days` into the past.
+async fn age_subscriptions(app: &TestApp, days: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = subscribed_at - make_interval(days => $1)",
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions_tokens
+        SET created_at = created_at - make_interval(days => $1),
+            expires_at = expires_at - make_interval(days => $1)
+        "#,
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn nothing_recent_is_purged() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+
+    let report = purge(&app).await;
+
+    assert_eq!(report, PurgeReport::default());
+}
+
+#[tokio::test]
+async fn pending_subscriptions_are_purged_with_their_tokens_after_the_retention_window() {
+    let app = spawn_app().await;
+    let retention_days = app
+        .configuration
+        .maintenance
+        .pending_subscription_retention_days as i32;
+    app.create_unconfirmed_subscriber().await;
+    age_subscriptions(&app, retention_days + 1).await;
+
+    let report = purge(&app).await;
+
+    assert_eq!(report.pending_subscriptions, 1);
+    let tokens = sqlx::query_scalar!("SELECT count(*) FROM subscriptions_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, Some(0));
+}
+
+#[tokio::test]
+async fn a_fresh_confirmation_email_keeps_a_pending_subscription() {
+    let app = spawn_app().await;
+    let retention_days = app
+        .configuration
+        .maintenance
+        .pending_subscription_retention_days as i32;
+    app.create_unconfirmed_subscriber().await;
+    age_subscriptions(&app, retention_days + 1).await;
+    // Subscribing again sends a new confirmation email.
+    app.create_unconfirmed_subscriber().await;
+
+    let report = purge(&app).await;
+
+    assert_eq!(report.pending_subscriptions, 0);
+}
+
+#[tokio::test]
+async fn confirmed_subscriptions_are_never_purged() {
+    let app = spawn_app().await;
+    let retention_days = app
+        .configuration
+        .maintenance
+        .pending_subscription_retention_days as i32;
+    app.create_confirmed_subscriber().await;
+    age_subscriptions(&app, retention_days + 1).await;
+
+    let report = purge(&app).await;
+
+    assert_eq!(report.pending_subscriptions, 0);
+    assert_eq!(report.expired_tokens, 1);
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+#[tokio::test]
+async fn expired_tokens_are_kept_for_their_retention_window() {
+    let app = spawn_app().await;
+    let retention_hours = app.configuration.maintenance.expired_token_retention_hours as i32;
+    app.create_unconfirmed_subscriber().await;
+    sqlx::query!(
+        "UPDATE subscriptions_tokens SET expires_at = now() - make_interval(hours => $1 - 1)",
+        retention_hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(purge(&app).await.expired_tokens, 0);
+
+    sqlx::query!(
+        "UPDATE subscriptions_tokens SET expires_at = now() - make_interval(hours => $1 + 1)",
+        retention_hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(purge(&app).await.expired_tokens, 1);
+}
+
+#[tokio::test]
+async fn idempotency_keys_are_purged_after_their_retention_window() {
+    let app = spawn_app().await;
+    let retention_hours = app.configuration.maintenance.idempotency_retention_hours as i32;
+    app.login_with_test_user().await;
+    app.post_form_newsletters(json!({
+        "title": "Newsletter title",
+        "text_content": "Newsletter body as plain text",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    }))
+    .await;
+    assert_eq!(purge(&app).await.idempotency_keys, 0);
+
+    sqlx::query!(
+        "UPDATE idempotency SET created_at = now() - make_interval(hours => $1 + 1)",
+        retention_hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(purge(&app).await.idempotency_keys, 1);
+}
+
+#[tokio::test]
+async fn expired_email_change_tokens_are_purged() {
+    let app = spawn_app().await;
+    let retention_hours = app.configuration.maintenance.expired_token_retention_hours as i32;
+    app.create_confirmed_subscriber().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO email_change_tokens (email_change_token, subscriber_id, new_email, expires_at)
+        SELECT 'email-change-token', id, 'new@example.com', now() - make_interval(hours => $1 + 1)
+        FROM subscriptions
+        "#,
+        retention_hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(purge(&app).await.expired_tokens, 1);
+    let tokens = sqlx::query_scalar!("SELECT count(*) FROM email_change_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, Some(0));
+}
@@ -4,7 +4,26 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+/// Publishes an issue through the API, returning its id.
+async fn publish_through_the_api(app: &TestApp) -> String {
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
 
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
@@ -29,7 +48,8 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -54,7 +74,8 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
 
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
 }
 
 #[tokio::test]
@@ -141,3 +162,183 @@ async fn non_existing_user_is_rejected() {
         response.headers()["WWW-Authenticate"]
     );
 }
+
+#[tokio::test]
+async fn scheduled_newsletters_are_not_enqueued_before_their_send_time() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text" : "Plain text body",
+            "html": "<p>HTML body!</p>"
+        },
+        "idempotency_key": Uuid::new_v4().to_string(),
+        "scheduled_for": (chrono::Utc::now() + chrono::Duration::days(3)).to_rfc3339(),
+    });
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let queued = sqlx::query!(r#"SELECT count(*) as "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(queued, 0);
+}
+
+#[tokio::test]
+async fn repeated_basic_auth_failures_are_throttled() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_user;
+    let body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let post_with_password = |password: String| {
+        app.api_client
+            .post(&format!("{}/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some(password))
+            .json(&body)
+            .send()
+    };
+
+    for _ in 1..max_failures {
+        let response = post_with_password(Uuid::new_v4().to_string())
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+    let response = post_with_password(Uuid::new_v4().to_string())
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    assert_eq!(
+        response.headers()["Retry-After"],
+        app.configuration
+            .login_throttling
+            .base_lockout_seconds
+            .to_string()
+    );
+
+    let response = post_with_password(app.test_user.password.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn accepted_issues_come_with_their_id_and_a_status_url() {
+    let app = spawn_app().await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let newsletter_issue_id = body["newsletter_issue_id"].as_str().unwrap();
+    let status_url = body["status_url"].as_str().unwrap();
+    assert!(status_url.ends_with(&format!("/newsletters/{}", newsletter_issue_id)));
+    let stored = sqlx::query!(
+        "SELECT title FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(newsletter_issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored.title, "Newsletter title");
+}
+
+#[tokio::test]
+async fn publishing_through_the_api_is_idempotent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = newsletter_request_body();
+    let first = app.post_newsletters(body.clone()).await;
+    let second = app.post_newsletters(body).await;
+
+    assert_eq!(first.status().as_u16(), 202);
+    assert_eq!(second.status().as_u16(), 202);
+    assert_eq!(
+        first.text().await.unwrap(),
+        second.text().await.unwrap(),
+        "The retry should get the original response back."
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn email_failures_do_not_fail_the_api_request() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // The emails are only sent later, by the delivery workers.
+    assert_eq!(response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn the_status_url_reports_the_delivery_progress() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let newsletter_issue_id = publish_through_the_api(&app).await;
+
+    let response = app.get_newsletter_issue_status(&newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let status: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(status["status"], "sending");
+    assert_eq!(status["queued"], 1);
+
+    app.dispatch_all_pending_emails().await;
+    let status: serde_json::Value = app
+        .get_newsletter_issue_status(&newsletter_issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["status"], "done");
+    assert_eq!(status["queued"], 0);
+    assert_eq!(status["sent"], 1);
+}
+
+#[tokio::test]
+async fn the_status_of_unknown_issues_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_newsletter_issue_status(&Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
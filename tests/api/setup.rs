@@ -0,0 +1,219 @@
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+use zero2prod::{
+    authentication::issue_setup_token, configuration::Environment, startup::Application,
+};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Demotes the test user, so that the app is back to its first-run state,
+/// and returns a fresh setup token.
+async fn without_owner(app: &TestApp) -> String {
+    sqlx::query!("UPDATE users SET role = 'editor'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    issue_setup_token(&app.db_pool)
+        .await
+        .unwrap()
+        .unwrap()
+        .expose_secret()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn the_seeded_default_admin_is_gone() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "admin",
+            "password": "admin_password"
+        }))
+        .await;
+
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn no_setup_token_is_issued_once_there_is_an_owner() {
+    let app = spawn_app().await;
+
+    assert!(issue_setup_token(&app.db_pool).await.unwrap().is_none());
+    let response = app
+        .api_client
+        .get(&format!("{}/setup", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_setup_token_creates_the_first_owner() {
+    let app = spawn_app().await;
+    let token = without_owner(&app).await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let html_page = app
+        .api_client
+        .get(&format!("{}/setup?token={}", &app.address, token))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(&format!(r#"value="{}""#, token)));
+
+    let response = app
+        .post_setup(&serde_json::json!({
+            "token": token,
+            "username": username,
+            "password": password,
+            "password_check": password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(
+        html_page.contains("<p><i>The owner account has been created. You can now log in.</i></p>")
+    );
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": username,
+            "password": password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {} (owner)", username)));
+}
+
+#[tokio::test]
+async fn an_invalid_setup_token_is_rejected() {
+    let app = spawn_app().await;
+    without_owner(&app).await;
+    let password = Uuid::new_v4().to_string();
+
+    let response = app
+        .post_setup(&serde_json::json!({
+            "token": "not-the-token",
+            "username": Uuid::new_v4().to_string(),
+            "password": password,
+            "password_check": password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/setup");
+
+    let html_page = app
+        .api_client
+        .get(&format!("{}/setup", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>The setup token is invalid or was already used.</i></p>"));
+}
+
+#[tokio::test]
+async fn the_setup_token_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let token = without_owner(&app).await;
+    let password = Uuid::new_v4().to_string();
+    let body = |username: String| {
+        serde_json::json!({
+            "token": token,
+            "username": username,
+            "password": password,
+            "password_check": password,
+        })
+    };
+    app.post_setup(&body(Uuid::new_v4().to_string())).await;
+    // Demote the new owner to reopen the setup page.
+    sqlx::query!("UPDATE users SET role = 'editor'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.post_setup(&body(Uuid::new_v4().to_string())).await;
+
+    assert_is_redirect_to(&response, "/setup");
+}
+
+#[tokio::test]
+async fn an_outstanding_setup_token_is_not_replaced() {
+    let app = spawn_app().await;
+    let token = without_owner(&app).await;
+
+    assert!(issue_setup_token(&app.db_pool).await.unwrap().is_none());
+    let password = Uuid::new_v4().to_string();
+    let response = app
+        .post_setup(&serde_json::json!({
+            "token": token,
+            "username": Uuid::new_v4().to_string(),
+            "password": password,
+            "password_check": password,
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_setup_token_survives_a_failure_to_create_the_owner() {
+    let app = spawn_app().await;
+    let token = without_owner(&app).await;
+    let password = Uuid::new_v4().to_string();
+    let body = |username: String| {
+        serde_json::json!({
+            "token": token,
+            "username": username,
+            "password": password,
+            "password_check": password,
+        })
+    };
+    sqlx::query!(
+        "ALTER TABLE users ADD CONSTRAINT short_usernames CHECK (length(username) < 40) NOT VALID"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let response = app.post_setup(&body("a".repeat(50))).await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    let response = app.post_setup(&body(Uuid::new_v4().to_string())).await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_app_refuses_to_start_in_production_with_the_default_credentials() {
+    let app = spawn_app().await;
+    // Give the test user the seeded `admin_password` hash.
+    sqlx::query!(
+        "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+        "$argon2id$v=19$m=15000,t=2,p=1$ebHcL7T2mvxGsBWVOGmCsw$3gYd6cCSiEU9wuhVZ5YmVK+pzh3pMqGgNCp3KyaeW7Y",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let mut configuration = app.configuration.clone();
+
+    assert!(Application::build(configuration.clone()).await.is_ok());
+    configuration.environment = Environment::Production;
+    assert!(Application::build(configuration).await.is_err());
+}
+
+#[tokio::test]
+async fn the_app_starts_in_production_without_the_default_credentials() {
+    let app = spawn_app().await;
+    let mut configuration = app.configuration.clone();
+    configuration.environment = Environment::Production;
+
+    assert!(Application::build(configuration).await.is_ok());
+}
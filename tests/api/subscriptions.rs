@@ -2,6 +2,10 @@ use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
+use zero2prod::{
+    domain::{SignupFormToken, SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS},
+    subscription_throttle::SubscriptionThrottle,
+};
 
 use crate::helpers::{spawn_app, TestApp};
 
@@ -211,3 +215,349 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let text_link = get_link(body["TextBody"].as_str().unwrap());
     assert_eq!(html_link, text_link);
 }
+
+#[tokio::test]
+async fn subscribing_a_confirmed_email_returns_the_same_200_as_a_new_subscription() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let confirmed = app
+        .post_subscriptions("name=gregory&email=example@gmail.com".into())
+        .await;
+    let new = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(confirmed.status().as_u16(), 200);
+    assert_eq!(new.status().as_u16(), 200);
+    assert_eq!(confirmed.text().await.unwrap(), new.text().await.unwrap());
+}
+
+#[tokio::test]
+async fn subscribing_a_confirmed_email_sends_a_link_to_manage_the_subscription() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=gregory&email=example@gmail.com".into())
+        .await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "example@gmail.com");
+    assert_eq!(body["Subject"], "You're already subscribed");
+    let manage_link = app.get_preferences_link(&email_request);
+    let response = app.get_preferences(manage_link).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // No new confirmation link, and the subscription is left alone.
+    let tokens = sqlx::query_scalar!("SELECT count(*) FROM subscriptions_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens, Some(1));
+    let status = sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "confirmed");
+}
+
+fn assert_is_throttled(response: &reqwest::Response, max_retry_after: u64) {
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= max_retry_after);
+}
+
+#[tokio::test]
+async fn subscription_requests_are_rate_limited_per_client_ip() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(u64::from(settings.max_requests_per_ip))
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..settings.max_requests_per_ip {
+        let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app
+        .post_subscriptions("name=le%20guin&email=another%40gmail.com".into())
+        .await;
+
+    assert_is_throttled(&response, settings.window_seconds);
+}
+
+#[tokio::test]
+async fn forwarded_client_ips_get_separate_budgets() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..settings.max_requests_per_ip {
+        let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+        app.post_subscriptions_from(body, "203.0.113.1").await;
+    }
+    let response = app
+        .post_subscriptions_from(
+            "name=le%20guin&email=another%40gmail.com".into(),
+            "203.0.113.1",
+        )
+        .await;
+    assert_is_throttled(&response, settings.window_seconds);
+
+    let response = app
+        .post_subscriptions_from(
+            "name=le%20guin&email=another%40gmail.com".into(),
+            "203.0.113.2",
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn requests_without_a_form_token_have_a_stricter_budget() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    assert!(settings.max_tokenless_requests_per_ip < settings.max_requests_per_ip);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(u64::from(settings.max_tokenless_requests_per_ip) + 1)
+        .mount(&app.email_server)
+        .await;
+
+    for i in 0..settings.max_tokenless_requests_per_ip {
+        let body = format!("name=le%20guin&email=ursula{}%40gmail.com", i);
+        let response = app.post_subscriptions_without_form_token(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    let response = app
+        .post_subscriptions_without_form_token("name=le%20guin&email=another%40gmail.com".into())
+        .await;
+    assert_is_throttled(&response, settings.window_seconds);
+
+    // The signup form is still open.
+    let response = app
+        .post_subscriptions("name=le%20guin&email=another%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscription_requests_are_rate_limited_per_email() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(u64::from(settings.max_requests_per_email))
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    for _ in 0..settings.max_requests_per_email {
+        app.post_subscriptions(body.into()).await;
+    }
+    // The limit does not depend on the case of the address.
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    assert_is_throttled(&response, settings.window_seconds);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_capped_per_pending_subscriber() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    // As if the subscriber had asked for the other ones already.
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions_tokens (subscription_token, subscriber_id, expires_at)
+        SELECT 'token-' || n, subscriber_id, expires_at
+        FROM subscriptions_tokens, generate_series(2, $1) AS n
+        "#,
+        settings.max_confirmation_emails as i32
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_resend_confirmation(&confirmation_links.subscription_token())
+        .await;
+
+    assert_is_throttled(
+        &response,
+        u64::from(settings.confirmation_email_window_hours) * 3600,
+    );
+}
+
+#[tokio::test]
+async fn notices_to_subscribed_addresses_are_capped_like_confirmation_emails() {
+    let app = spawn_app().await;
+    let settings = &app.configuration.subscription_throttling;
+    app.create_confirmed_subscriber().await;
+    // As if the address had been sent the other notices already.
+    let throttle = SubscriptionThrottle::new(&app.configuration.redis_uri, settings.clone())
+        .await
+        .unwrap();
+    for _ in 0..settings.max_confirmation_emails {
+        throttle
+            .count_subscribed_notice_to("example@gmail.com")
+            .await
+            .unwrap();
+    }
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=gregory&email=example%40gmail.com".into())
+        .await;
+
+    assert_is_throttled(
+        &response,
+        u64::from(settings.confirmation_email_window_hours) * 3600,
+    );
+}
+
+#[tokio::test]
+async fn filling_in_the_honeypot_gets_the_request_throttled() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=https%3A%2F%2Fspam.com".into(),
+        )
+        .await;
+
+    assert_is_throttled(
+        &response,
+        app.configuration.subscription_throttling.window_seconds,
+    );
+}
+
+#[tokio::test]
+async fn signup_forms_submitted_too_quickly_are_throttled() {
+    let app = spawn_app().await;
+    let min_form_fill_seconds = app
+        .configuration
+        .subscription_throttling
+        .min_form_fill_seconds;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let html_page = app.get_home_html().await;
+    let selector = scraper::Selector::parse(r#"form input[name="form_token"]"#).unwrap();
+    let form_token = scraper::Html::parse_document(&html_page)
+        .select(&selector)
+        .next()
+        .unwrap()
+        .value()
+        .attr("value")
+        .unwrap()
+        .to_owned();
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=&form_token={}",
+            form_token
+        ))
+        .await;
+
+    assert_is_throttled(&response, min_form_fill_seconds);
+}
+
+#[tokio::test]
+async fn signup_forms_filled_in_at_a_human_pace_are_accepted() {
+    let app = spawn_app().await;
+    let min_form_fill_seconds = app
+        .configuration
+        .subscription_throttling
+        .min_form_fill_seconds;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let served_at = chrono::Utc::now().timestamp() - min_form_fill_seconds as i64;
+    let form_token = SignupFormToken::new(served_at, &app.hmac_secret);
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=&form_token={}",
+            form_token.as_ref()
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn expired_signup_form_tokens_are_rejected() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let served_at = chrono::Utc::now().timestamp() - SIGNUP_FORM_TOKEN_MAX_AGE_SECONDS - 60;
+    let form_token = SignupFormToken::new(served_at, &app.hmac_secret);
+    let response = app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=&form_token={}",
+            form_token.as_ref()
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -5,7 +5,7 @@ use wiremock::{
 };
 use zero2prod::routes::generate_subscription_token;
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, TestApp};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -142,3 +142,129 @@ async fn confirming_a_subscription_with_an_unexisting_token_is_unauthorized() {
     let response = app.confirm_token(fake_token).await;
     assert_eq!(response.status().as_u16(), StatusCode::UNAUTHORIZED);
 }
+
+async fn expire_confirmation_tokens(app: &TestApp) {
+    sqlx::query!("UPDATE subscriptions_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn expired_confirmation_links_are_gone_and_offer_a_new_link() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    expire_confirmation_tokens(&app).await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), StatusCode::GONE);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    assert!(html_page.contains(r#"<form action="/subscriptions/confirm/resend" method="post">"#));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirmation_links_can_only_be_used_once() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), StatusCode::GONE);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has already been used."));
+}
+
+#[tokio::test]
+async fn confirmation_links_do_not_resubscribe_unsubscribed_subscribers() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), StatusCode::GONE);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("Sign up again"));
+    // Resending would be turned down: no dead end form.
+    assert!(!body.contains("/subscriptions/confirm/resend"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn resending_the_confirmation_emails_a_fresh_link() {
+    let app = spawn_app().await;
+    let expired_links = app.create_unconfirmed_subscriber().await;
+    expire_confirmation_tokens(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(&expired_links.subscription_token())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let fresh_links = app.get_confirmation_links(&email_request);
+    assert_ne!(fresh_links.html, expired_links.html);
+    let response = reqwest::get(fresh_links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn no_confirmation_is_resent_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    let confirmation_links = app.create_unconfirmed_subscriber().await;
+    reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_resend_confirmation(&confirmation_links.subscription_token())
+        .await;
+
+    assert_eq!(response.status().as_u16(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn resending_with_an_unknown_token_is_unauthorized() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_resend_confirmation(&generate_subscription_token())
+        .await;
+
+    assert_eq!(response.status().as_u16(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,319 @@
+use secrecy::Secret;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::domain::{PreferencesToken, UnsubscribeToken};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app, TestApp};
+
+/// Address of the subscribers created by the test helpers.
+const SUBSCRIBER_EMAIL: &str = "example@gmail.com";
+
+async fn subscriber_id(app: &TestApp) -> Uuid {
+    sqlx::query_scalar!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+}
+
+/// Creates a confirmed subscriber and returns the token of their
+/// preferences link.
+async fn confirmed_subscriber_token(app: &TestApp) -> String {
+    app.create_confirmed_subscriber().await;
+    let token = PreferencesToken::new(subscriber_id(app).await, SUBSCRIBER_EMAIL, &app.hmac_secret);
+    token.as_ref().to_owned()
+}
+
+fn preferences_path(token: &str) -> String {
+    format!("/subscriptions/preferences?token={}", token)
+}
+
+#[tokio::test]
+async fn issues_link_to_the_preferences_page() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_form_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let response = app
+        .get_preferences(app.get_preferences_link(&email_request))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"value="gregory""#));
+    assert!(html_page.contains(r#"value="example@gmail.com""#));
+    assert!(html_page.contains(r#"action="/subscriptions/unsubscribe""#));
+}
+
+#[tokio::test]
+async fn the_preferences_page_requires_a_valid_token() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    let subscriber_id = subscriber_id(&app).await;
+
+    let other_secret = Secret::new(Uuid::new_v4().to_string());
+    let forged = PreferencesToken::new(subscriber_id, SUBSCRIBER_EMAIL, &other_secret);
+    let unsubscribe = UnsubscribeToken::new(subscriber_id, &app.hmac_secret);
+    for token in [forged.as_ref(), unsubscribe.as_ref(), "not-a-token"] {
+        let response = app
+            .post_preferences(
+                "name",
+                &serde_json::json!({ "token": token, "name": "eve" }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    let name = sqlx::query_scalar!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(name, "gregory");
+}
+
+#[tokio::test]
+async fn pending_subscribers_have_no_preferences_page() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber().await;
+    let token = PreferencesToken::new(
+        subscriber_id(&app).await,
+        SUBSCRIBER_EMAIL,
+        &app.hmac_secret,
+    );
+
+    let mut link = reqwest::Url::parse(&app.address).unwrap();
+    link.set_path("/subscriptions/preferences");
+    link.set_query(Some(&format!("token={}", token.as_ref())));
+    let response = app.get_preferences(link).await;
+
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+
+    let response = app
+        .post_preferences(
+            "name",
+            &serde_json::json!({ "token": token, "name": "Gregory House" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &preferences_path(&token));
+
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("<p><i>Your name has been changed.</i></p>"));
+    assert!(html_page.contains(r#"value="Gregory House""#));
+}
+
+#[tokio::test]
+async fn invalid_names_are_rejected() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+
+    let response = app
+        .post_preferences("name", &serde_json::json!({ "token": token, "name": "  " }))
+        .await;
+    assert_is_redirect_to(&response, &preferences_path(&token));
+
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(!html_page.contains("Your name has been changed."));
+    assert!(html_page.contains(r#"value="gregory""#));
+}
+
+#[tokio::test]
+async fn a_new_email_address_is_used_once_confirmed() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_preferences(
+            "email",
+            &serde_json::json!({ "token": token, "email": "gregory@example.com" }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &preferences_path(&token));
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "gregory@example.com");
+    // Nothing changes until the new address is confirmed.
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains(r#"value="example@gmail.com""#));
+
+    let confirmation_link = app.get_confirmation_links(&email_request).html;
+    let response = app.get_preferences(confirmation_link.clone()).await;
+    let new_token = PreferencesToken::new(
+        subscriber_id(&app).await,
+        "gregory@example.com",
+        &app.hmac_secret,
+    );
+    assert_is_redirect_to(&response, &preferences_path(new_token.as_ref()));
+    let html_page = app.get_preferences_html(new_token.as_ref()).await;
+    assert!(html_page.contains("<p><i>Your email address has been changed.</i></p>"));
+    assert!(html_page.contains(r#"value="gregory@example.com""#));
+
+    // Links sent to the old address no longer give access.
+    let response = app
+        .post_preferences(
+            "name",
+            &serde_json::json!({ "token": token, "name": "eve" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // The link is single-use.
+    let response = app.get_preferences(confirmation_link).await;
+    assert_eq!(response.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn changing_to_a_subscribed_address_sends_no_email() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        VALUES ($1, 'taken@example.com', 'taken', now(), 'confirmed')
+        "#,
+        Uuid::new_v4()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_preferences(
+            "email",
+            &serde_json::json!({ "token": token, "email": "taken@example.com" }),
+        )
+        .await;
+
+    // Same answer as for a free address.
+    assert_is_redirect_to(&response, &preferences_path(&token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("We sent a confirmation link to taken@example.com."));
+}
+
+#[tokio::test]
+async fn invalid_email_addresses_are_rejected() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_preferences(
+            "email",
+            &serde_json::json!({ "token": token, "email": "not-an-email" }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &preferences_path(&token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(!html_page.contains("We sent a confirmation link"));
+}
+
+#[tokio::test]
+async fn paused_subscribers_are_skipped_until_they_resume() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+    app.login_with_test_user().await;
+
+    let response = app
+        .post_preferences("pause", &serde_json::json!({ "token": token, "days": 30 }))
+        .await;
+    assert_is_redirect_to(&response, &preferences_path(&token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("Delivery is paused until"));
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_form_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    let outcome = sqlx::query_scalar!("SELECT outcome FROM issue_delivery_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outcome, "skipped_paused");
+
+    app.post_preferences("pause", &serde_json::json!({ "token": token, "days": 0 }))
+        .await;
+    let paused_until = sqlx::query_scalar!("SELECT paused_until FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(paused_until.is_none());
+}
+
+#[tokio::test]
+async fn only_the_offered_pause_lengths_are_accepted() {
+    let app = spawn_app().await;
+    let token = confirmed_subscriber_token(&app).await;
+
+    let response = app
+        .post_preferences(
+            "pause",
+            &serde_json::json!({ "token": token, "days": 3650 }),
+        )
+        .await;
+
+    assert_is_redirect_to(&response, &preferences_path(&token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("<p><i>Choose one of the offered pause lengths.</i></p>"));
+    assert!(html_page.contains("You receive every issue."));
+}
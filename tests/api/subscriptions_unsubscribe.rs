@@ -0,0 +1,275 @@
+use reqwest::Url;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::domain::UnsubscribeToken;
+
+use crate::helpers::{spawn_app, TestApp};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>HTML body!</p>",
+        "text_content": "Plain text body",
+        "idempotency_key": Uuid::new_v4().to_string(),
+    })
+}
+
+fn token_from_link(link: &Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.into_owned())
+        .expect("The unsubscribe link has no token.")
+}
+
+/// Publishes an issue to a single confirmed subscriber and returns the
+/// email request sent for it.
+async fn deliver_issue(app: &TestApp) -> wiremock::Request {
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    let _guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_form_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap()
+}
+
+async fn deliver_issue_and_get_unsubscribe_link(app: &TestApp) -> Url {
+    let email_request = deliver_issue(app).await;
+    app.get_unsubscribe_link(&email_request)
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}
+
+#[tokio::test]
+async fn delivered_issues_carry_an_unsubscribe_link() {
+    let app = spawn_app().await;
+
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    assert_eq!(unsubscribe_link.path(), "/subscriptions/unsubscribe");
+    assert_eq!(
+        unsubscribe_link.host_str().unwrap(),
+        app.base_url.host_str().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_does_not_unsubscribe() {
+    let app = spawn_app().await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    let response = app.get_unsubscribe(unsubscribe_link).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let page_document = scraper::Html::parse_document(&html_page);
+    let form_selector =
+        scraper::Selector::parse(r#"form[action="/subscriptions/unsubscribe"]"#).unwrap();
+    assert_eq!(page_document.select(&form_selector).count(), 1);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn submitting_the_unsubscribe_form_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+
+    let response = app
+        .post_unsubscribe(&token_from_link(&unsubscribe_link))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_new_issues() {
+    let app = spawn_app().await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+    app.post_unsubscribe(&token_from_link(&unsubscribe_link))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let _guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_form_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_queued_before_unsubscribing_are_not_delivered() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber().await;
+    app.login_with_test_user().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_form_newsletters(newsletter_request_body()).await;
+
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    let token = UnsubscribeToken::new(subscriber_id, &app.hmac_secret);
+    app.post_unsubscribe(token.as_ref())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn tampered_unsubscribe_tokens_are_rejected() {
+    let app = spawn_app().await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+    let token = token_from_link(&unsubscribe_link);
+    let (_, tag) = token.split_once('.').unwrap();
+    let forged_token = format!("{}.{}", Uuid::new_v4(), tag);
+
+    let mut forged_link = unsubscribe_link.clone();
+    forged_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("token", &forged_token);
+    let response = app.get_unsubscribe(forged_link).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_unsubscribe(&forged_token).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_can_subscribe_again() {
+    let app = spawn_app().await;
+    let unsubscribe_link = deliver_issue_and_get_unsubscribe_link(&app).await;
+    app.post_unsubscribe(&token_from_link(&unsubscribe_link))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let _guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=gregory&email=example@gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn delivered_issues_carry_list_unsubscribe_headers() {
+    let app = spawn_app().await;
+
+    let email_request = deliver_issue(&app).await;
+
+    let one_click_link = app.get_one_click_unsubscribe_link(&email_request);
+    assert_eq!(
+        one_click_link.path(),
+        "/subscriptions/unsubscribe/one-click"
+    );
+    assert_eq!(
+        app.get_email_header(&email_request, "List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_works_without_a_session() {
+    let app = spawn_app().await;
+    let email_request = deliver_issue(&app).await;
+    let one_click_link = app.get_one_click_unsubscribe_link(&email_request);
+
+    let response = app
+        .post_one_click_unsubscribe(one_click_link, "List-Unsubscribe=One-Click")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_rejects_an_unexpected_body() {
+    let app = spawn_app().await;
+    let email_request = deliver_issue(&app).await;
+    let one_click_link = app.get_one_click_unsubscribe_link(&email_request);
+
+    let test_cases = vec![
+        ("", "empty body"),
+        ("List-Unsubscribe=Yes", "wrong value"),
+        ("token=abc", "missing List-Unsubscribe field"),
+    ];
+    for (body, description) in test_cases {
+        let response = app
+            .post_one_click_unsubscribe(one_click_link.clone(), body)
+            .await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_rejects_tampered_tokens() {
+    let app = spawn_app().await;
+    let email_request = deliver_issue(&app).await;
+    let one_click_link = app.get_one_click_unsubscribe_link(&email_request);
+    let token = token_from_link(&one_click_link);
+    let (_, tag) = token.split_once('.').unwrap();
+    let forged_token = format!("{}.{}", Uuid::new_v4(), tag);
+
+    let mut forged_link = one_click_link.clone();
+    forged_link
+        .query_pairs_mut()
+        .clear()
+        .append_pair("token", &forged_token);
+    let response = app
+        .post_one_click_unsubscribe(forged_link, "List-Unsubscribe=One-Click")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+}
@@ -0,0 +1,214 @@
+use crate::helpers::{assert_is_redirect_to, spawn_app, totp_code_in, TestApp};
+
+/// Enrols the test user, then logs them out.
+async fn enrolled_test_user(app: &TestApp) -> (secrecy::Secret<String>, Vec<String>) {
+    app.login_with_test_user().await;
+    let enrolment = app.enable_two_factor().await;
+    app.post_logout().await;
+    enrolment
+}
+
+#[tokio::test]
+async fn enrolment_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("otpauth://totp/zero2prod:"));
+
+    let response = app.post_enable_two_factor("not-a-code").await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>The authentication code is invalid.</i></p>"));
+    assert!(html_page.contains("Two-factor authentication is disabled."));
+}
+
+#[tokio::test]
+async fn enrolment_keeps_the_same_key_until_confirmed() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let first = app.get_two_factor_settings_html().await;
+    let second = app.get_two_factor_settings_html().await;
+    assert_eq!(first, second);
+}
+
+#[tokio::test]
+async fn enrolment_shows_the_recovery_codes() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+
+    let (_, recovery_codes) = app.enable_two_factor().await;
+
+    assert_eq!(recovery_codes.len(), 10);
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("Two-factor authentication is enabled."));
+    assert!(!html_page.contains(&recovery_codes[0]));
+    let stored: Vec<String> = sqlx::query_scalar!(
+        "SELECT code_hash FROM recovery_codes WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored.len(), 10);
+    assert!(!stored.contains(&recovery_codes[0]));
+}
+
+#[tokio::test]
+async fn users_without_two_factor_log_in_with_their_password() {
+    let app = spawn_app().await;
+
+    let response = app.login_with_test_user().await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_password_alone_does_not_log_in_enrolled_users() {
+    let app = spawn_app().await;
+    enrolled_test_user(&app).await;
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_valid_code_completes_the_login() {
+    let app = spawn_app().await;
+    let (secret, _) = enrolled_test_user(&app).await;
+    app.login_with_test_user().await;
+
+    // The enrolment used the code of the current time step.
+    let response = app.post_login_two_factor(&totp_code_in(&secret, 30)).await;
+
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn codes_cannot_be_replayed() {
+    let app = spawn_app().await;
+    let (secret, _) = enrolled_test_user(&app).await;
+    let code = totp_code_in(&secret, 30);
+    app.login_with_test_user().await;
+    app.post_login_two_factor(&code).await;
+    app.post_logout().await;
+
+    app.login_with_test_user().await;
+    let response = app.post_login_two_factor(&code).await;
+
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Authentication failed</i></p>"));
+}
+
+#[tokio::test]
+async fn recovery_codes_work_only_once() {
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enrolled_test_user(&app).await;
+
+    app.login_with_test_user().await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    app.login_with_test_user().await;
+    let response = app.post_login_two_factor(&recovery_codes[0]).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_valid_password_first() {
+    let app = spawn_app().await;
+
+    let response = app.get_login_two_factor().await;
+    assert_is_redirect_to(&response, "/login");
+
+    let response = app.post_login_two_factor("123456").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn wrong_codes_count_as_failed_logins() {
+    let app = spawn_app().await;
+    let max_failures = app.configuration.login_throttling.max_failures_per_user;
+    let (secret, _) = enrolled_test_user(&app).await;
+    app.login_with_test_user().await;
+
+    for _ in 0..max_failures {
+        app.post_login_two_factor("wrong-code").await;
+    }
+    let html_page = app.get_login_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Too many failed login attempts."));
+
+    // Even the right code is turned away during the lockout.
+    let response = app.post_login_two_factor(&totp_code_in(&secret, 30)).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn disabling_two_factor_requires_a_valid_code() {
+    let app = spawn_app().await;
+    app.login_with_test_user().await;
+    let (secret, _) = app.enable_two_factor().await;
+
+    let response = app.post_disable_two_factor("wrong-code").await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>The authentication code is invalid.</i></p>"));
+
+    let response = app
+        .post_disable_two_factor(&totp_code_in(&secret, 30))
+        .await;
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_settings_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+    app.post_logout().await;
+
+    let response = app.login_with_test_user().await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn owners_can_reset_the_two_factor_of_other_users() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+    app.enable_two_factor().await;
+    app.post_logout().await;
+
+    app.login_with_test_user().await;
+    let response = app.post_reset_two_factor(editor.user_id).await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page
+        .contains("<p><i>The two-factor authentication of the user has been reset.</i></p>"));
+    app.post_logout().await;
+
+    let response = app.login(&editor).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let events: Vec<String> = sqlx::query_scalar!(
+        "SELECT event FROM audit_log WHERE username = $1 ORDER BY recorded_at",
+        editor.username
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events, ["two_factor_enabled", "two_factor_reset"]);
+}
+
+#[tokio::test]
+async fn only_owners_can_reset_the_two_factor_of_other_users() {
+    let app = spawn_app().await;
+    let editor = app.create_user_with_role("editor").await;
+    app.login(&editor).await;
+
+    let response = app.post_reset_two_factor(app.test_user.user_id).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+}